@@ -0,0 +1,554 @@
+//! Typed views of a cart's chunks, and a builder to make carts from scratch.
+//!
+//! Views read a chunk of one bank as the RAM it's loaded into: chunks are usually
+//! cut short after their last non-zero byte, so anything past the end reads as zero,
+//! and a missing chunk is all zeroes.
+
+use crate::{CartError, Cartridge, Chunk, ChunkType};
+
+/// Banks a cart can have of each chunk.
+pub const BANKS: u8 = 8;
+
+/// The palette carts get when they don't have their own, SWEETIE-16.
+pub const DEFAULT_PALETTE: [u8; 48] = [
+    0x1a, 0x1c, 0x2c, 0x5d, 0x27, 0x5d, 0xb1, 0x3e, 0x53, 0xef, 0x7d, 0x57, //
+    0xff, 0xcd, 0x75, 0xa7, 0xf0, 0x70, 0x38, 0xb7, 0x64, 0x25, 0x71, 0x79, //
+    0x29, 0x36, 0x6f, 0x3b, 0x5d, 0xc9, 0x41, 0xa6, 0xf6, 0x73, 0xef, 0xf7, //
+    0xf4, 0xf4, 0xf4, 0x94, 0xb0, 0xc2, 0x56, 0x6c, 0x86, 0x33, 0x3c, 0x57, //
+];
+
+impl ChunkType {
+    /// Bytes a chunk of this type may hold in one bank, `None` if there's no limit
+    /// besides its size field.
+    #[allow(deprecated)]
+    pub const fn capacity(self) -> Option<usize> {
+        use ChunkType::*;
+        Some(match self {
+            Tiles | Sprites => 0x2000,
+            Map => 0x7F80,
+            Code => 0x10000,
+            Flags => 0x200,
+            Samples => 0x1080,
+            Waveform => 0x100,
+            Palette => 0x60,
+            Music => 0x198,
+            Patterns | PatternsDep => 0x2D00,
+            Screen => 0x3FC0,
+            Default => 0,
+            Dummy | Binary | CoverDep | CodeZip => return None,
+        })
+    }
+}
+
+impl Cartridge {
+    /// The chunk of `kind` in `bank`, checked to fit in it.
+    pub fn chunk(&self, kind: ChunkType, bank: u8) -> Result<Option<&Chunk>, CartError> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.info.chunk_type() == kind && chunk.info.bank() == bank);
+        match chunk {
+            Some(chunk) => validate(kind, bank, chunk.data.len()).map(|_| Some(chunk)),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks every chunk fits in its bank.
+    pub fn validate(&self) -> Result<(), CartError> {
+        for chunk in &self.chunks {
+            validate(chunk.info.chunk_type(), chunk.info.bank(), chunk.data.len())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn view(&self, kind: ChunkType, bank: u8) -> Result<&[u8], CartError> {
+        Ok(self
+            .chunk(kind, bank)?
+            .map_or(&[][..], |chunk| &chunk.data[..]))
+    }
+
+    /// The code, put together from all banks: it starts in the last bank that has
+    /// some and ends in the first. Bytes that aren't UTF-8 are replaced with `�`.
+    pub fn code(&self) -> Result<String, CartError> {
+        let mut code = vec![];
+        let mut found = false;
+        for bank in (0..BANKS).rev() {
+            if let Some(chunk) = self.chunk(ChunkType::Code, bank)? {
+                code.extend_from_slice(&chunk.data);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(CartError::NoCode);
+        }
+        Ok(match String::from_utf8(code) {
+            Ok(code) => code,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        })
+    }
+
+    /// Replaces the code, spreading it over as many banks as it needs.
+    pub fn set_code(&mut self, code: &str) -> Result<(), CartError> {
+        let chunks = code_chunks(code.as_bytes())?;
+        self.chunks
+            .retain(|chunk| chunk.info.chunk_type() != ChunkType::Code);
+        self.chunks.extend(chunks);
+        Ok(())
+    }
+
+    /// Sets a chunk, in place of any of the same type and bank.
+    pub fn set_chunk(&mut self, kind: ChunkType, bank: u8, data: Vec<u8>) -> Result<(), CartError> {
+        validate(kind, bank, data.len())?;
+        let chunk = Chunk::new(kind, bank, data)?;
+        self.chunks
+            .retain(|chunk| (chunk.info.chunk_type(), chunk.info.bank()) != (kind, bank));
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Background tiles, sprites 0 to 255.
+    pub fn tiles(&self, bank: u8) -> Result<Tiles<'_>, CartError> {
+        self.view(ChunkType::Tiles, bank).map(Tiles)
+    }
+
+    /// Foreground sprites, sprites 256 to 511.
+    pub fn sprites(&self, bank: u8) -> Result<Tiles<'_>, CartError> {
+        self.view(ChunkType::Sprites, bank).map(Tiles)
+    }
+
+    pub fn map(&self, bank: u8) -> Result<Map<'_>, CartError> {
+        self.view(ChunkType::Map, bank).map(Map)
+    }
+
+    pub fn palette(&self, bank: u8) -> Result<Palette<'_>, CartError> {
+        self.view(ChunkType::Palette, bank).map(Palette)
+    }
+
+    /// The colours the assets of `bank` are drawn in: those of its palette, else of
+    /// the palette of bank 0, else [`DEFAULT_PALETTE`].
+    pub fn colors(&self, bank: u8) -> Result<[[u8; 3]; Palette::COLORS], CartError> {
+        for bank in [bank, 0] {
+            if self.chunk(ChunkType::Palette, bank)?.is_some() {
+                let palette = self.palette(bank)?;
+                return Ok(std::array::from_fn(|i| palette.color(i)));
+            }
+        }
+        Ok(std::array::from_fn(|i| {
+            std::array::from_fn(|c| DEFAULT_PALETTE[i * 3 + c])
+        }))
+    }
+
+    pub fn sfx(&self, bank: u8) -> Result<Sfx<'_>, CartError> {
+        self.view(ChunkType::Samples, bank).map(Sfx)
+    }
+
+    pub fn patterns(&self, bank: u8) -> Result<Patterns<'_>, CartError> {
+        self.view(ChunkType::Patterns, bank).map(Patterns)
+    }
+
+    /// Starts a cart with no chunks.
+    pub fn builder() -> CartridgeBuilder {
+        CartridgeBuilder {
+            title: "cart.tic".to_string(),
+            chunks: vec![],
+            code: None,
+        }
+    }
+}
+
+/// Code cut into banks, the start in the last one, all full but bank 0.
+pub(crate) fn code_chunks(code: &[u8]) -> Result<Vec<Chunk>, CartError> {
+    let bank_size = ChunkType::Code.capacity().unwrap();
+    let max = bank_size * BANKS as usize;
+    if code.len() > max {
+        return Err(CartError::CodeTooLarge {
+            size: code.len(),
+            max,
+        });
+    }
+    let banks = code.len().div_ceil(bank_size).max(1);
+    (0..banks)
+        .map(|i| {
+            let end = code.len().min((i + 1) * bank_size);
+            let bank = (banks - 1 - i) as u8;
+            Chunk::new(ChunkType::Code, bank, code[i * bank_size..end].to_vec())
+        })
+        .collect()
+}
+
+pub(crate) fn validate(kind: ChunkType, bank: u8, size: usize) -> Result<(), CartError> {
+    if bank >= BANKS {
+        return Err(CartError::NoSuchBank { chunk: kind, bank });
+    }
+    let max = kind.capacity().unwrap_or(u16::MAX as usize);
+    if size > max {
+        return Err(CartError::ChunkTooLarge {
+            chunk: kind,
+            bank,
+            size,
+            max,
+        });
+    }
+    Ok(())
+}
+
+fn byte(data: &[u8], at: usize) -> u8 {
+    data.get(at).copied().unwrap_or(0)
+}
+
+/// 256 tiles of 8x8 pixels, 4 bits each.
+#[derive(Debug, Clone, Copy)]
+pub struct Tiles<'a>(&'a [u8]);
+
+impl Tiles<'_> {
+    pub const COUNT: usize = 256;
+    pub const SIZE: usize = 32;
+
+    /// The colour of a pixel of a tile.
+    ///
+    /// # Panics
+    ///
+    /// If the tile or the pixel are out of range.
+    pub fn pixel(&self, tile: usize, x: usize, y: usize) -> u8 {
+        assert!(tile < Self::COUNT && x < 8 && y < 8);
+        let at = tile * Self::SIZE + y * 4 + x / 2;
+        byte(self.0, at) >> (x % 2 * 4) & 0xF
+    }
+
+    /// The bytes of a tile, two pixels each, the left one in the low nibble.
+    pub fn tile(&self, tile: usize) -> [u8; 32] {
+        assert!(tile < Self::COUNT);
+        std::array::from_fn(|i| byte(self.0, tile * Self::SIZE + i))
+    }
+}
+
+/// 240x136 tile ids.
+#[derive(Debug, Clone, Copy)]
+pub struct Map<'a>(&'a [u8]);
+
+impl Map<'_> {
+    pub const WIDTH: usize = 240;
+    pub const HEIGHT: usize = 136;
+
+    /// # Panics
+    ///
+    /// If the cell is out of the map.
+    pub fn tile(&self, x: usize, y: usize) -> u8 {
+        assert!(x < Self::WIDTH && y < Self::HEIGHT);
+        byte(self.0, y * Self::WIDTH + x)
+    }
+}
+
+/// The 16 colours of the screen, and those of the `OVR` layer after them in carts
+/// that set it apart.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette<'a>(&'a [u8]);
+
+impl Palette<'_> {
+    pub const COLORS: usize = 16;
+    /// All black.
+    pub const EMPTY: Palette<'static> = Palette(&[]);
+
+    pub fn color(&self, index: usize) -> [u8; 3] {
+        assert!(index < Self::COLORS);
+        std::array::from_fn(|c| byte(self.0, index * 3 + c))
+    }
+
+    /// The `OVR` palette's colour, if the cart has one.
+    pub fn ovr_color(&self, index: usize) -> Option<[u8; 3]> {
+        assert!(index < Self::COLORS);
+        let ovr = self
+            .0
+            .get(Self::COLORS * 3..)
+            .filter(|ovr| !ovr.is_empty())?;
+        Some(std::array::from_fn(|c| byte(ovr, index * 3 + c)))
+    }
+}
+
+/// One of the 30 ticks of a sound effect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SfxTick {
+    pub volume: u8,
+    pub wave: u8,
+    pub arpeggio: u8,
+    /// From -8 to 7.
+    pub pitch: i8,
+}
+
+/// 64 sound effects.
+#[derive(Debug, Clone, Copy)]
+pub struct Sfx<'a>(&'a [u8]);
+
+impl Sfx<'_> {
+    pub const COUNT: usize = 64;
+    pub const TICKS: usize = 30;
+    pub const SIZE: usize = 66;
+
+    pub fn tick(&self, sfx: usize, tick: usize) -> SfxTick {
+        assert!(sfx < Self::COUNT && tick < Self::TICKS);
+        let at = sfx * Self::SIZE + tick * 2;
+        let (a, b) = (byte(self.0, at), byte(self.0, at + 1));
+        SfxTick {
+            volume: a & 0xF,
+            wave: a >> 4,
+            arpeggio: b & 0xF,
+            pitch: (b as i8) >> 4,
+        }
+    }
+
+    /// The note it plays at by default, 0 for C, and its octave.
+    pub fn note(&self, sfx: usize) -> (u8, u8) {
+        assert!(sfx < Self::COUNT);
+        let at = sfx * Self::SIZE + Self::TICKS * 2;
+        (byte(self.0, at + 1) & 0xF, byte(self.0, at) & 0x7)
+    }
+
+    /// Ticks each step lasts for, from -4 to 3.
+    pub fn speed(&self, sfx: usize) -> i8 {
+        assert!(sfx < Self::COUNT);
+        let at = sfx * Self::SIZE + Self::TICKS * 2;
+        ((byte(self.0, at) << 1) as i8) >> 5
+    }
+}
+
+/// A row of a music pattern.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatternRow {
+    /// 0 if empty, 1 to stop the note, from 4 for C to 15 for B.
+    pub note: u8,
+    pub param1: u8,
+    pub param2: u8,
+    /// 0 for none, then `M`, `C`, `J`, `S`, `P`, `V` and `D`.
+    pub command: u8,
+    pub sfx: u8,
+    pub octave: u8,
+}
+
+/// 60 patterns of 64 rows.
+#[derive(Debug, Clone, Copy)]
+pub struct Patterns<'a>(&'a [u8]);
+
+impl Patterns<'_> {
+    pub const COUNT: usize = 60;
+    pub const ROWS: usize = 64;
+
+    /// # Panics
+    ///
+    /// If the pattern or the row are out of range.
+    pub fn row(&self, pattern: usize, row: usize) -> PatternRow {
+        assert!(pattern < Self::COUNT && row < Self::ROWS);
+        let at = (pattern * Self::ROWS + row) * 3;
+        let [a, b, c] = [0, 1, 2].map(|i| byte(self.0, at + i));
+        PatternRow {
+            note: a & 0xF,
+            param1: a >> 4,
+            param2: b & 0xF,
+            command: b >> 4 & 0x7,
+            sfx: (b >> 7) << 5 | c & 0x1F,
+            octave: c >> 5,
+        }
+    }
+}
+
+/// Puts a cart together chunk by chunk. Sizes are checked once it's built.
+#[derive(Debug, Clone)]
+pub struct CartridgeBuilder {
+    title: String,
+    chunks: Vec<(ChunkType, u8, Vec<u8>)>,
+    code: Option<String>,
+}
+
+impl CartridgeBuilder {
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets a chunk, in place of any of the same type and bank. Trailing zeroes are
+    /// left out of assets, as they're implied.
+    pub fn chunk(mut self, kind: ChunkType, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        let mut data = data.into();
+        if kind != ChunkType::Code {
+            let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            data.truncate(len);
+        }
+        self.chunks.retain(|&(k, b, _)| (k, b) != (kind, bank));
+        self.chunks.push((kind, bank, data));
+        self
+    }
+
+    /// Sets the code, over as many banks as it needs once built.
+    pub fn code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn tiles(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Tiles, bank, data)
+    }
+
+    pub fn sprites(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Sprites, bank, data)
+    }
+
+    pub fn map(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Map, bank, data)
+    }
+
+    pub fn palette(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Palette, bank, data)
+    }
+
+    pub fn sfx(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Samples, bank, data)
+    }
+
+    pub fn patterns(self, bank: u8, data: impl Into<Vec<u8>>) -> Self {
+        self.chunk(ChunkType::Patterns, bank, data)
+    }
+
+    /// Fails if a chunk doesn't fit in its bank, the bank doesn't exist, or the code
+    /// doesn't fit in all banks.
+    pub fn build(self) -> Result<Cartridge, CartError> {
+        let mut chunks: Vec<Chunk> = self
+            .chunks
+            .into_iter()
+            .map(|(kind, bank, data)| {
+                validate(kind, bank, data.len())?;
+                Chunk::new(kind, bank, data)
+            })
+            .collect::<Result<_, _>>()?;
+        if let Some(code) = self.code {
+            chunks.retain(|chunk| chunk.info.chunk_type() != ChunkType::Code);
+            chunks.extend(code_chunks(code.as_bytes())?);
+        }
+        Ok(Cartridge {
+            title: self.title,
+            chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn views() {
+        let mut tiles = vec![0; 32 * 3];
+        tiles[32 * 2 + 4] = 0x3A; // tile 2, (0, 1) and (1, 1)
+        let mut sfx = vec![0; 66];
+        sfx[2] = 0x5F; // tick 1: wave 5 at full volume
+        sfx[3] = 0xE2; // pitch -2, arpeggio 2
+        sfx[60] = 0x34; // octave 4, speed 3
+        sfx[61] = 0x09; // A
+        let cart = Cartridge::builder()
+            .code("x = 1")
+            .tiles(0, tiles)
+            .map(1, [0, 0, 7])
+            .palette(0, [1, 2, 3])
+            .sfx(0, sfx)
+            .patterns(0, [0, 0, 0, 0xC4, 0x9C, 0x61])
+            .build()
+            .unwrap();
+
+        assert_eq!(cart.code().unwrap(), "x = 1");
+        let tiles = cart.tiles(0).unwrap();
+        assert_eq!((tiles.pixel(2, 0, 1), tiles.pixel(2, 1, 1)), (0xA, 0x3));
+        assert_eq!(tiles.pixel(200, 7, 7), 0);
+        assert_eq!(tiles.tile(2)[4], 0x3A);
+        assert_eq!(cart.map(1).unwrap().tile(2, 0), 7);
+        assert_eq!(cart.map(0).unwrap().tile(2, 0), 0);
+        let palette = cart.palette(0).unwrap();
+        assert_eq!(palette.color(0), [1, 2, 3]);
+        assert_eq!(palette.ovr_color(0), None);
+        assert_eq!(cart.colors(3).unwrap()[0], [1, 2, 3]);
+        let colors = Cartridge::builder().build().unwrap().colors(0).unwrap();
+        assert_eq!(colors[15], [0x33, 0x3c, 0x57]);
+
+        let sfx = cart.sfx(0).unwrap();
+        let tick = SfxTick {
+            volume: 15,
+            wave: 5,
+            arpeggio: 2,
+            pitch: -2,
+        };
+        assert_eq!(sfx.tick(0, 1), tick);
+        assert_eq!((sfx.note(0), sfx.speed(0)), ((9, 4), 3));
+
+        let row = cart.patterns(0).unwrap().row(0, 1);
+        let expected = PatternRow {
+            note: 4,
+            param1: 12,
+            param2: 12,
+            command: 1,
+            sfx: 33,
+            octave: 3,
+        };
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn builder() {
+        let cart = Cartridge::builder()
+            .code("a")
+            .code("b")
+            .tiles(0, [1, 0, 0])
+            .build()
+            .unwrap();
+        assert_eq!(cart.chunks.len(), 2);
+        assert_eq!(cart.code().unwrap(), "b");
+        // trailing zeroes are implied
+        assert_eq!(cart.chunk(ChunkType::Tiles, 0).unwrap().unwrap().size, 1);
+
+        let oversize = Cartridge::builder().palette(0, [1; 97]).build();
+        assert!(matches!(
+            oversize,
+            Err(CartError::ChunkTooLarge {
+                size: 97,
+                max: 96,
+                ..
+            })
+        ));
+        let bank = Cartridge::builder().map(8, [1]).build();
+        assert!(matches!(bank, Err(CartError::NoSuchBank { bank: 8, .. })));
+
+        // chunks read from a file are checked when they're looked at
+        let mut bytes = vec![6, 0x01, 0x02, 0]; // 513 bytes of flags
+        bytes.extend([0; 513]);
+        let cart = Cartridge::try_from(bytes.as_slice()).unwrap();
+        assert!(cart.validate().is_err());
+        assert!(cart.chunk(ChunkType::Flags, 0).is_err());
+        assert!(matches!(cart.code(), Err(CartError::NoCode)));
+    }
+
+    #[test]
+    fn code_banks() {
+        let code = "a".repeat(0x10000) + &"b".repeat(0x10000) + "c\u{e9}";
+        let mut cart = Cartridge::builder().code(&code).build().unwrap();
+        let sizes: Vec<_> = cart
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.info.bank(), chunk.size))
+            .collect();
+        assert_eq!(sizes, [(2, 0), (1, 0), (0, 3)]);
+        assert_eq!(cart.code().unwrap(), code);
+
+        let bytes = cart.to_bytes().unwrap();
+        assert_eq!(
+            Cartridge::try_from(&bytes[..]).unwrap().code().unwrap(),
+            code
+        );
+
+        // cut in the middle of a character
+        cart.set_code("\u{e9}").unwrap();
+        cart.chunks[0].data.truncate(1);
+        assert_eq!(cart.code().unwrap(), "\u{fffd}");
+
+        let too_long = "-".repeat(0x80001);
+        assert!(matches!(
+            cart.set_code(&too_long),
+            Err(CartError::CodeTooLarge { max: 0x80000, .. })
+        ));
+    }
+}
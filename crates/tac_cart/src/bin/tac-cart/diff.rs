@@ -0,0 +1,176 @@
+//! What changed between two carts.
+
+use tac_cart::{CartError, Cartridge, Chunk, ChunkType};
+
+/// What a chunk holds a list of, and the bytes each takes, so that changes can be
+/// told by item.
+fn items(kind: ChunkType) -> Option<(&'static str, usize)> {
+    Some(match kind {
+        ChunkType::Tiles => ("tiles", 32),
+        ChunkType::Sprites => ("sprites", 32),
+        ChunkType::Map => ("rows", 240),
+        ChunkType::Screen => ("rows", 120),
+        ChunkType::Samples => ("sfx", 66),
+        ChunkType::Waveform => ("waveforms", 16),
+        ChunkType::Music => ("tracks", 51),
+        ChunkType::Patterns => ("patterns", 192),
+        ChunkType::Palette => ("colours", 3),
+        ChunkType::Flags => ("flags", 1),
+        _ => return None,
+    })
+}
+
+/// The changes from `a` to `b`, one a line, in their canonical forms so that
+/// padding and chunk order don't count. Empty if they're the same.
+pub fn diff(a: &Cartridge, b: &Cartridge) -> Result<Vec<String>, CartError> {
+    let canonical = |cart: &Cartridge| -> Result<(Option<String>, Vec<Chunk>), CartError> {
+        let mut cart = cart.clone();
+        cart.upgrade()?;
+        let code = match cart.code() {
+            Ok(code) => Some(code),
+            Err(CartError::NoCode) => None,
+            Err(e) => return Err(e),
+        };
+        let mut chunks = cart.canonical()?;
+        chunks.retain(|chunk| chunk.info.chunk_type() != ChunkType::Code);
+        Ok((code, chunks))
+    };
+    let (code_a, chunks_a) = canonical(a)?;
+    let (code_b, chunks_b) = canonical(b)?;
+
+    let mut changes = vec![];
+    if code_a != code_b {
+        diff_code(
+            code_a.as_deref().unwrap_or(""),
+            code_b.as_deref().unwrap_or(""),
+            &mut changes,
+        );
+    }
+
+    let find = |chunks: &[Chunk], other: &Chunk| {
+        let key = (other.info.chunk_type(), other.info.bank());
+        chunks
+            .iter()
+            .find(|chunk| (chunk.info.chunk_type(), chunk.info.bank()) == key)
+            .map(|chunk| chunk.data.clone())
+    };
+    let added = chunks_b.iter().filter(|b| find(&chunks_a, b).is_none());
+    for chunk in chunks_a.iter().chain(added) {
+        let (kind, bank) = (chunk.info.chunk_type(), chunk.info.bank());
+        let name = format!("{:?} in bank {}", kind, bank);
+        let (a, b) = (find(&chunks_a, chunk), find(&chunks_b, chunk));
+        let change = match (a, b) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(a), Some(b)) => match items(kind) {
+                Some((items, size)) => {
+                    let count = a.len().max(b.len()).div_ceil(size);
+                    let changed: Vec<usize> = (0..count)
+                        .filter(|&i| item(&a, i, size) != item(&b, i, size))
+                        .collect();
+                    format!("~ {}: {} {}", name, items, ranges(&changed))
+                }
+                None => {
+                    let first = (0..).find(|&i| a.get(i) != b.get(i)).unwrap();
+                    format!(
+                        "~ {}: {} bytes, now {}, from byte {}",
+                        name,
+                        a.len(),
+                        b.len(),
+                        first
+                    )
+                }
+            },
+            (Some(a), None) => format!("- {}, {} bytes", name, a.len()),
+            (None, Some(b)) => format!("+ {}, {} bytes", name, b.len()),
+            (None, None) => unreachable!(),
+        };
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// An item of a chunk, with the zeros it was cut short of.
+fn item(data: &[u8], index: usize, size: usize) -> Vec<u8> {
+    (index * size..(index + 1) * size)
+        .map(|i| data.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+/// `1-3, 7` for `[1, 2, 3, 7]`.
+fn ranges(indices: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &i in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == i => *end = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|&(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect();
+    ranges.join(", ")
+}
+
+/// The lines between those both start and end with, as one unified diff hunk.
+fn diff_code(a: &str, b: &str, changes: &mut Vec<String>) {
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.lines().collect(), b.lines().collect());
+    let prefix = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (removed, added) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    changes.push(format!(
+        "@@ code -{},{} +{},{} @@",
+        prefix + 1,
+        removed.len(),
+        prefix + 1,
+        added.len()
+    ));
+    changes.extend(removed.iter().map(|line| format!("-{}", line)));
+    changes.extend(added.iter().map(|line| format!("+{}", line)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let a = Cartridge::builder()
+            .code("-- title: a\nx=1\ny=2\nz=3\n")
+            .tiles(0, [1, 0, 0, 0])
+            .map(0, [1; 480])
+            .chunk(ChunkType::Binary, 1, [1, 2, 3])
+            .build()
+            .unwrap();
+        let b = Cartridge::builder()
+            .code("-- title: a\nx=1\ny=20\ny=21\nz=3\n")
+            .tiles(0, [1, 0, 0, 0, 0])
+            .map(0, [[1; 240], [0; 240], [2; 240]].concat())
+            .chunk(ChunkType::Binary, 1, [1, 2, 4, 5])
+            .sfx(2, [1])
+            .build()
+            .unwrap();
+        assert_eq!(diff(&a, &a.clone()).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            diff(&a, &b).unwrap(),
+            [
+                "@@ code -3,1 +3,2 @@",
+                "-y=2",
+                "+y=20",
+                "+y=21",
+                "~ Map in bank 0: rows 1-2",
+                "~ Binary in bank 1: 3 bytes, now 4, from byte 2",
+                "+ Samples in bank 2, 1 bytes",
+            ]
+        );
+        assert_eq!(diff(&b, &a).unwrap()[5], "- Samples in bank 2, 1 bytes");
+    }
+}
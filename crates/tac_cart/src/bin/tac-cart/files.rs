@@ -0,0 +1,372 @@
+//! A cart as a directory of files other tools can edit, and back.
+
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tac_cart::{
+    CartError, Cartridge, ChunkType, Map, Palette, Quantize, TiledFormat, Tiles, BANKS,
+};
+
+/// Chunks that are written as they are, and the files they're written to.
+#[allow(deprecated)]
+const RAW: [(ChunkType, &str, &str); 9] = [
+    (ChunkType::Samples, "sfx", "bin"),
+    (ChunkType::Waveform, "waveforms", "bin"),
+    (ChunkType::Music, "music", "bin"),
+    (ChunkType::Patterns, "patterns", "bin"),
+    (ChunkType::Flags, "flags", "bin"),
+    (ChunkType::Screen, "screen", "bin"),
+    (ChunkType::Default, "default", "bin"),
+    (ChunkType::Binary, "binary", "bin"),
+    (ChunkType::CoverDep, "cover", "gif"),
+];
+
+fn file_name(name: &str, bank: u8, ext: &str) -> String {
+    match bank {
+        0 => format!("{}.{}", name, ext),
+        _ => format!("{}{}.{}", name, bank, ext),
+    }
+}
+
+/// The name, bank and extension of a file written by [`extract`].
+fn parse_name(file: &str) -> Option<(&str, u8, &str)> {
+    let (stem, ext) = file.rsplit_once('.')?;
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let bank = match &stem[name.len()..] {
+        "" => 0,
+        bank => bank.parse().ok().filter(|&bank| bank < BANKS)?,
+    };
+    Some((name, bank, ext))
+}
+
+/// Writes the cart's chunks to files in `dir`, and returns them.
+pub fn extract(cart: &Cartridge, dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut cart = cart.clone();
+    cart.upgrade()?;
+    fs::create_dir_all(dir)?;
+    let mut files = vec![];
+    let mut write = |name: String, bytes: Vec<u8>| {
+        let path = dir.join(name);
+        let result = fs::write(&path, bytes);
+        files.push(path);
+        result
+    };
+
+    match cart.code() {
+        Ok(code) => write("code.lua".to_string(), code.into_bytes())?,
+        Err(CartError::NoCode) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let (mut tiles, mut maps) = (vec![], vec![]);
+    for chunk in cart.canonical()? {
+        let (kind, bank) = (chunk.info.chunk_type(), chunk.info.bank());
+        match kind {
+            ChunkType::Code => {}
+            ChunkType::Tiles => {
+                let sheet = cart.tiles(bank)?.to_png(&cart.colors(bank)?)?;
+                write(file_name("tiles", bank, "png"), sheet)?;
+                tiles.push(bank);
+            }
+            ChunkType::Sprites => {
+                let sheet = cart.sprites(bank)?.to_png(&cart.colors(bank)?)?;
+                write(file_name("sprites", bank, "png"), sheet)?;
+            }
+            ChunkType::Map => {
+                let map = map_csv(cart.map(bank)?);
+                write(file_name("map", bank, "csv"), map.into_bytes())?;
+                maps.push(bank);
+            }
+            ChunkType::Palette => {
+                let colors: Vec<[u8; 3]> = chunk
+                    .data
+                    .chunks(3)
+                    .map(|rgb| std::array::from_fn(|c| rgb.get(c).copied().unwrap_or(0)))
+                    .collect();
+                write(
+                    file_name("palette", bank, "hex"),
+                    palette_hex(&colors).into_bytes(),
+                )?;
+                write(
+                    file_name("palette", bank, "gpl"),
+                    palette_gpl(&colors).into_bytes(),
+                )?;
+            }
+            _ => match RAW.iter().find(|(raw, _, _)| *raw == kind) {
+                Some((_, name, ext)) => write(file_name(name, bank, ext), chunk.data)?,
+                None => eprintln!("skipping {:?} chunk in bank {}", kind, bank),
+            },
+        }
+    }
+
+    // the maps for Tiled, which needs the tiles they're drawn with
+    if !maps.is_empty() {
+        for &bank in maps.iter().filter(|bank| !tiles.contains(bank)) {
+            let sheet = cart.tiles(bank)?.to_png(&cart.colors(bank)?)?;
+            write(file_name("tiles", bank, "png"), sheet)?;
+        }
+        let tmx = cart.to_tiled(TiledFormat::Tmx, |bank| file_name("tiles", bank, "png"))?;
+        write("map.tmx".to_string(), tmx.into_bytes())?;
+    }
+    Ok(files)
+}
+
+/// Makes a cart out of the files [`extract`] writes. The palette is read from its
+/// `.hex`, or its `.gpl` if there's no `.hex`. The maps are read from their CSV,
+/// then from `map.tmx` or `map.json` over them, with the flags of the tiles, as
+/// Tiled is where they're edited.
+pub fn pack(dir: &Path) -> Result<Cartridge, Box<dyn Error>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    let read = |name: &str| fs::read(dir.join(name));
+    let text = |name: &str| fs::read_to_string(dir.join(name));
+
+    let mut builder = Cartridge::builder();
+    let (mut sheets, mut tiled) = (vec![], None);
+    for name in &names {
+        builder = match parse_name(name) {
+            Some(("code", 0, "lua")) => builder.code(&String::from_utf8_lossy(&read(name)?)),
+            Some(("tiles", bank, "png")) => {
+                sheets.push((name, 0, bank));
+                builder
+            }
+            Some(("sprites", bank, "png")) => {
+                sheets.push((name, Tiles::COUNT, bank));
+                builder
+            }
+            Some(("map", bank, "csv")) => {
+                let data = parse_csv(&text(name)?).map_err(|e| format!("{}: {}", name, e))?;
+                builder.map(bank, data)
+            }
+            Some(("palette", bank, "hex")) => {
+                let colors = parse_hex(&text(name)?).map_err(|e| format!("{}: {}", name, e))?;
+                builder.palette(bank, colors.concat())
+            }
+            Some(("palette", bank, "gpl"))
+                if !names.contains(&file_name("palette", bank, "hex")) =>
+            {
+                let colors = parse_gpl(&text(name)?).map_err(|e| format!("{}: {}", name, e))?;
+                builder.palette(bank, colors.concat())
+            }
+            Some(("map", 0, "tmx" | "json")) => {
+                tiled = TiledFormat::from_path(name).map(|format| (name, format));
+                builder
+            }
+            // read from the .hex
+            Some(("palette", _, "gpl")) => builder,
+            Some((file, bank, ext)) => match RAW.iter().find(|raw| (raw.1, raw.2) == (file, ext)) {
+                Some(&(kind, _, _)) => builder.chunk(kind, bank, read(name)?),
+                None => {
+                    eprintln!("skipping {}", name);
+                    builder
+                }
+            },
+            None => {
+                eprintln!("skipping {}", name);
+                builder
+            }
+        };
+    }
+
+    // once the palettes are in, the sheets are matched to them
+    let mut cart = builder.build()?;
+    for (name, id, bank) in sheets {
+        cart.import_sprites(&read(name)?, id, bank, Quantize::Indices)
+            .map_err(|e| format!("{}: {}", name, e))?;
+    }
+    if let Some((name, format)) = tiled {
+        cart.import_tiled(&text(name)?, format)
+            .map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(cart)
+}
+
+fn map_csv(map: Map) -> String {
+    let mut csv = String::new();
+    for y in 0..Map::HEIGHT {
+        let row: Vec<String> = (0..Map::WIDTH)
+            .map(|x| map.tile(x, y).to_string())
+            .collect();
+        writeln!(csv, "{}", row.join(",")).unwrap();
+    }
+    csv
+}
+
+fn parse_csv(csv: &str) -> Result<Vec<u8>, String> {
+    let mut data = vec![0; Map::WIDTH * Map::HEIGHT];
+    let rows = csv.lines().filter(|line| !line.trim().is_empty());
+    for (y, row) in rows.enumerate() {
+        for (x, cell) in row.split(',').enumerate() {
+            if x >= Map::WIDTH || y >= Map::HEIGHT {
+                return Err(format!("the map is only {}x{}", Map::WIDTH, Map::HEIGHT));
+            }
+            data[y * Map::WIDTH + x] = cell
+                .trim()
+                .parse()
+                .map_err(|_| format!("line {}: {:?} is not a tile", y + 1, cell))?;
+        }
+    }
+    Ok(data)
+}
+
+fn palette_hex(colors: &[[u8; 3]]) -> String {
+    let colors = padded(colors);
+    colors
+        .iter()
+        .map(|[r, g, b]| format!("{:02x}{:02x}{:02x}\n", r, g, b))
+        .collect()
+}
+
+fn palette_gpl(colors: &[[u8; 3]]) -> String {
+    let mut gpl = format!(
+        "GIMP Palette\nName: TIC-80\nColumns: {}\n#\n",
+        Palette::COLORS
+    );
+    for (i, [r, g, b]) in padded(colors).iter().enumerate() {
+        writeln!(gpl, "{:3} {:3} {:3}\t{}", r, g, b, i).unwrap();
+    }
+    gpl
+}
+
+/// The colours up to the end of the screen palette, or of the `OVR` one if there
+/// are more.
+fn padded(colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    let len = match colors.len() > Palette::COLORS {
+        true => Palette::COLORS * 2,
+        false => Palette::COLORS,
+    };
+    let mut colors = colors.to_vec();
+    colors.resize(len, [0; 3]);
+    colors
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<[u8; 3]>, String> {
+    let colors = hex.lines().map(str::trim).filter(|line| !line.is_empty());
+    let colors = colors.map(|line| {
+        let line = line.trim_start_matches('#');
+        let rgb = u32::from_str_radix(line, 16)
+            .ok()
+            .filter(|_| line.len() == 6);
+        let rgb = rgb.ok_or(format!("{:?} is not a colour", line))?;
+        Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+    });
+    check_len(colors.collect::<Result<_, String>>()?)
+}
+
+/// Reads a GIMP palette: a header, then a colour a line, with an optional name
+/// after its red, green and blue.
+fn parse_gpl(gpl: &str) -> Result<Vec<[u8; 3]>, String> {
+    let mut lines = gpl.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err("not a GIMP palette".into());
+    }
+    let mut colors = vec![];
+    for line in lines.map(str::trim) {
+        let header = ["Name:", "Columns:", "#"]
+            .iter()
+            .any(|h| line.starts_with(h));
+        if line.is_empty() || header {
+            continue;
+        }
+        let rgb: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(|c| c.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("{:?} is not a colour", line))?;
+        colors.push(
+            rgb.try_into()
+                .map_err(|_| format!("{:?} is not a colour", line))?,
+        );
+    }
+    check_len(colors)
+}
+
+fn check_len(colors: Vec<[u8; 3]>) -> Result<Vec<[u8; 3]>, String> {
+    match colors.len() {
+        16 | 32 => Ok(colors),
+        len => Err(format!(
+            "palettes have 16 colours, or 32 with OVR ones, not {}",
+            len
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("tac_cart_files_{}", std::process::id()));
+        // black twice, so only the index tells them apart
+        let mut palette = tac_cart::DEFAULT_PALETTE.to_vec();
+        palette[42..].fill(0);
+        palette.extend([0xFF, 0, 0]);
+        let cart = Cartridge::builder()
+            .code(&format!("-- title: round trip\n{}", "x=1\n".repeat(0x5000)))
+            .tiles(0, [0x10, 0xF0, 0x32])
+            .sprites(1, [0x54; 0x2000])
+            .map(0, [0, 1, 2, 255])
+            .map(3, [9; 0x7F80])
+            .palette(0, palette)
+            .palette(1, tac_cart::DEFAULT_PALETTE)
+            .sfx(0, [1, 2, 3])
+            .chunk(ChunkType::Flags, 0, [0x81])
+            .chunk(ChunkType::Binary, 2, [7, 7])
+            .build()
+            .unwrap();
+
+        let files = extract(&cart, &dir).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap()).collect();
+        for name in [
+            "code.lua",
+            "sprites1.png",
+            "tiles3.png",
+            "map.tmx",
+            "palette.gpl",
+            "binary2.bin",
+        ] {
+            assert!(names.contains(&name.as_ref()), "{}", name);
+        }
+        let packed = pack(&dir).unwrap();
+        assert_eq!(packed.to_bytes().unwrap(), cart.to_bytes().unwrap());
+
+        // the same colours from a GIMP palette instead
+        fs::remove_file(dir.join("palette.hex")).unwrap();
+        let packed = pack(&dir).unwrap();
+        assert_eq!(packed.to_bytes().unwrap(), cart.to_bytes().unwrap());
+
+        // a map edited in Tiled wins over the CSVs
+        let mut edited = cart.clone();
+        edited.set_chunk(ChunkType::Map, 0, vec![4, 4]).unwrap();
+        edited.set_chunk(ChunkType::Flags, 0, vec![0, 2]).unwrap();
+        let json = edited
+            .to_tiled(TiledFormat::Json, |_| String::new())
+            .unwrap();
+        fs::remove_file(dir.join("map.tmx")).unwrap();
+        fs::write(dir.join("map.json"), json).unwrap();
+        let packed = pack(&dir).unwrap();
+        assert_eq!(packed.to_bytes().unwrap(), edited.to_bytes().unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse_name("tiles.png"), Some(("tiles", 0, "png")));
+        assert_eq!(parse_name("map7.csv"), Some(("map", 7, "csv")));
+        assert_eq!(parse_name("map8.csv"), None);
+        assert_eq!(parse_name("README"), None);
+        assert!(parse_hex("1a1c2c\n").is_err());
+        assert!(parse_csv("1,2,x").is_err());
+    }
+}
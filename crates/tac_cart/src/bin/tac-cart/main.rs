@@ -0,0 +1,237 @@
+//! Looks into carts and takes them apart.
+//!
+//! ```text
+//! tac-cart info <cart>
+//! tac-cart extract <cart> <dir>
+//! tac-cart pack <dir> <cart>
+//! tac-cart diff <a> <b>
+//! tac-cart convert <from> <to>
+//! tac-cart import <image.png> <cart> [--id N] [--bank N] [--dither]
+//! tac-cart import <map.tmx|map.json> <cart>
+//! tac-cart export <cart> <map.tmx|map.json>
+//! ```
+//!
+//! `extract` writes the code to `code.lua`, tiles and sprites to 128x128 PNG
+//! sheets in the cart's palette, the maps to CSV and all together to a Tiled
+//! `map.tmx`, the palette to `.gpl` and `.hex`, and every other chunk as it is.
+//! Assets of other banks than 0 get the bank after their name, `tiles1.png`. `pack`
+//! makes a cart out of such a directory again, skipping files it doesn't know.
+//!
+//! `import` draws an image over the sprites of a cart, from sprite `--id` (0 by
+//! default) of `--bank` (0 by default), and saves the cart back. Indexed images
+//! keep their colour indices, others get the closest colours of the palette, or
+//! dithered ones with `--dither`; see [`Cartridge::import_sprites`]. It reads the
+//! maps and tile flags of a Tiled map the same way, see [`Cartridge::import_tiled`].
+//! `export` writes the maps as a Tiled map, and their tiles as PNG tilesets next to
+//! it, `<map>-tiles.png`.
+//!
+//! Carts are read and written as `.tic`, `.tic.png` or `.lua` source by their
+//! extension, see [`Cartridge::load`] and [`Cartridge::save`].
+//!
+//! Exit codes: 0 on success, 1 when `diff` finds differences, 2 for anything else
+//! (bad arguments, unreadable cart, I/O errors).
+
+mod diff;
+mod files;
+
+use std::{cell::RefCell, env, error::Error, fs, path::PathBuf, process::ExitCode};
+
+use tac_cart::{Cartridge, ChunkType, Quantize, TiledFormat, BANKS};
+
+const USAGE: &str = "usage: tac-cart info <cart>
+       tac-cart extract <cart> <dir>
+       tac-cart pack <dir> <cart>
+       tac-cart diff <a> <b>
+       tac-cart convert <from> <to>
+       tac-cart import <image.png> <cart> [--id N] [--bank N] [--dither]
+       tac-cart import <map.tmx|map.json> <cart>
+       tac-cart export <cart> <map.tmx|map.json>";
+
+enum Command {
+    Info(PathBuf),
+    Extract(PathBuf, PathBuf),
+    Pack(PathBuf, PathBuf),
+    Diff(PathBuf, PathBuf),
+    Convert(PathBuf, PathBuf),
+    Import {
+        file: PathBuf,
+        cart: PathBuf,
+        id: usize,
+        bank: u8,
+        quantize: Quantize,
+    },
+    Export(PathBuf, PathBuf),
+}
+
+impl Command {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = args.next().ok_or("no command given")?;
+        let mut path = || args.next().map(PathBuf::from).ok_or("missing argument");
+        let parsed = match command.as_str() {
+            "info" => Command::Info(path()?),
+            "extract" => Command::Extract(path()?, path()?),
+            "pack" => Command::Pack(path()?, path()?),
+            "diff" => Command::Diff(path()?, path()?),
+            "convert" => Command::Convert(path()?, path()?),
+            "import" => {
+                let (file, cart) = (path()?, path()?);
+                let (mut id, mut bank, mut quantize) = (0, 0, Quantize::Indices);
+                while let Some(arg) = args.next() {
+                    let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+                    match arg.as_str() {
+                        "--id" => id = value()?.parse().map_err(|e| format!("--id: {}", e))?,
+                        "--bank" => {
+                            bank = value()?.parse().map_err(|e| format!("--bank: {}", e))?
+                        }
+                        "--dither" => quantize = Quantize::Dither,
+                        _ => return Err(format!("unknown option {}", arg)),
+                    }
+                }
+                Command::Import {
+                    file,
+                    cart,
+                    id,
+                    bank,
+                    quantize,
+                }
+            }
+            "export" => Command::Export(path()?, path()?),
+            _ => return Err(format!("unknown command {}", command)),
+        };
+        match args.next() {
+            Some(arg) => Err(format!("unexpected argument {}", arg)),
+            None => Ok(parsed),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let command = match Command::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// `false` if `diff` found differences.
+fn run(command: Command) -> Result<bool, Box<dyn Error>> {
+    match command {
+        Command::Info(path) => info(&Cartridge::load(path)?)?,
+        Command::Extract(path, dir) => {
+            for file in files::extract(&Cartridge::load(path)?, &dir)? {
+                println!("{}", file.display());
+            }
+        }
+        Command::Pack(dir, path) => files::pack(&dir)?.save(path)?,
+        Command::Diff(a, b) => {
+            let changes = diff::diff(&Cartridge::load(a)?, &Cartridge::load(b)?)?;
+            for change in &changes {
+                println!("{}", change);
+            }
+            return Ok(changes.is_empty());
+        }
+        Command::Convert(from, to) => Cartridge::load(from)?.save(to)?,
+        Command::Import {
+            file,
+            cart: path,
+            id,
+            bank,
+            quantize,
+        } => {
+            let mut cart = Cartridge::load(&path)?;
+            match TiledFormat::from_path(&file) {
+                Some(format) => cart.import_tiled(&fs::read_to_string(file)?, format)?,
+                None => cart.import_sprites(&fs::read(file)?, id, bank, quantize)?,
+            }
+            cart.save(path)?;
+        }
+        Command::Export(path, map) => {
+            let format =
+                TiledFormat::from_path(&map).ok_or("maps are exported to .tmx or .json")?;
+            let cart = Cartridge::load(path)?;
+            let stem = map.file_stem().unwrap_or_default().to_string_lossy();
+            let tileset = |bank: u8| match bank {
+                0 => format!("{}-tiles.png", stem),
+                _ => format!("{}-tiles{}.png", stem, bank),
+            };
+            let banks = RefCell::new(vec![]);
+            let text = cart.to_tiled(format, |bank| {
+                banks.borrow_mut().push(bank);
+                tileset(bank)
+            })?;
+            fs::write(&map, text)?;
+            for bank in banks.into_inner() {
+                let sheet = cart.tiles(bank)?.to_png(&cart.colors(bank)?)?;
+                fs::write(map.with_file_name(tileset(bank)), sheet)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn info(cart: &Cartridge) -> Result<(), Box<dyn Error>> {
+    let meta = cart.metadata();
+    println!("{}", cart.title);
+    let tags = [
+        ("title", meta.title),
+        ("author", meta.author),
+        ("desc", meta.desc),
+        ("script", Some(meta.script.to_string())),
+        (
+            "input",
+            meta.input
+                .map(|input| format!("{:?}", input).to_lowercase()),
+        ),
+        ("saveid", meta.saveid),
+        (
+            "menu",
+            Some(meta.menu.join(" ")).filter(|menu| !menu.is_empty()),
+        ),
+    ];
+    for (tag, value) in tags {
+        if let Some(value) = value {
+            println!("  {:<7} {}", format!("{}:", tag), value);
+        }
+    }
+
+    if let Ok(code) = cart.code() {
+        let banks = (0..BANKS)
+            .filter(|&bank| matches!(cart.chunk(ChunkType::Code, bank), Ok(Some(_))))
+            .count();
+        println!(
+            "code: {} bytes, {} lines, in {} bank{}",
+            code.len(),
+            code.lines().count(),
+            banks,
+            if banks == 1 { "" } else { "s" }
+        );
+    }
+    println!("chunks:");
+    for chunk in &cart.chunks {
+        let (kind, bank) = (chunk.info.chunk_type(), chunk.info.bank());
+        let capacity = match kind.capacity() {
+            Some(capacity) if capacity > 0 => format!(" of {}", capacity),
+            _ => String::new(),
+        };
+        println!(
+            "  bank {}  {:<12} {:>6} bytes{}",
+            bank,
+            format!("{:?}", kind),
+            chunk.data.len(),
+            capacity
+        );
+    }
+    Ok(())
+}
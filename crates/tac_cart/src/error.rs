@@ -0,0 +1,58 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::ChunkType;
+
+#[derive(Debug, Error)]
+pub enum CartError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("malformed chunk at byte {offset}: {source}")]
+    Malformed {
+        offset: u64,
+        #[source]
+        source: binread::Error,
+    },
+    #[error("unknown chunk type {kind} at byte {offset}")]
+    UnknownChunk { kind: u8, offset: u64 },
+    #[error("{chunk:?} chunks are not supported")]
+    Unsupported { chunk: ChunkType },
+    #[error("{chunk:?} chunk in bank {bank} is {size} bytes, it can't be more than {max}")]
+    ChunkTooLarge {
+        chunk: ChunkType,
+        bank: u8,
+        size: usize,
+        max: usize,
+    },
+    #[error("the code is {size} bytes, it can't be more than {max}")]
+    CodeTooLarge { size: usize, max: usize },
+    #[error("{chunk:?} chunk in bank {bank}, there are only 8 banks")]
+    NoSuchBank { chunk: ChunkType, bank: u8 },
+    #[error("invalid PNG: {0}")]
+    Png(#[from] png::DecodingError),
+    #[error("can't write PNG: {0}")]
+    PngEncoding(#[from] png::EncodingError),
+    #[error("line {line}: {message}")]
+    BadText { line: usize, message: &'static str },
+    #[error("invalid GIF cover: {0}")]
+    Gif(#[from] gif::DecodingError),
+    #[error("the cover image has no width")]
+    EmptyCover,
+    #[error("there is no cart in the PNG")]
+    NoCartInPng,
+    #[error("the cartridge has no code")]
+    NoCode,
+    #[error("the image is {width}x{height}, it must be made of whole 8x8 sprites")]
+    NotSprites { width: usize, height: usize },
+    #[error("a {width}x{height} image from sprite {id} goes past the sprite sheet")]
+    OffSheet {
+        id: usize,
+        width: usize,
+        height: usize,
+    },
+    #[error("colour {index} of the image is not one of the palette's 16")]
+    NotInPalette { index: u8 },
+    #[error("invalid Tiled map: {0}")]
+    Tiled(String),
+}
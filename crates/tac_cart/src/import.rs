@@ -0,0 +1,314 @@
+//! Sprites drawn in other tools, from PNG images, and sprite sheets for them.
+
+use png::{BitDepth, ColorType, Transformations};
+
+use crate::{CartError, Cartridge, ChunkType, Cover, Palette, Tiles};
+
+/// How the colours of an image are turned into those of the palette.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quantize {
+    /// Each pixel gets the closest colour.
+    #[default]
+    Nearest,
+    /// The closest colour, with what it's off by spread over the pixels to the right
+    /// and below (Floyd-Steinberg), so that gradients keep their tone.
+    Dither,
+    /// The pixels of an indexed image keep their index, for images drawn in the
+    /// cart's palette. Those of other images get the closest colour.
+    Indices,
+}
+
+/// Sprites in a row of the sheet, and in all of it, tiles then sprites.
+const COLUMNS: usize = 16;
+const SPRITES: usize = Tiles::COUNT * 2;
+/// Pixels less opaque than this are transparent, and get colour 0.
+const OPAQUE: u8 = 128;
+
+type Colors = [[u8; 3]; Palette::COLORS];
+
+impl Cartridge {
+    /// Draws a PNG image over the sprites of `bank`, its top left corner on sprite
+    /// `id`. The image is cut into 8x8 sprites laid out as in TIC-80's sprite
+    /// editor, 16 a row, and ids from 256 are the foreground sprites. Colours are
+    /// matched to those of [`Cartridge::colors`], and transparent pixels get colour 0.
+    ///
+    /// Fails if the image isn't made of whole sprites, or goes past the right or
+    /// bottom of the sheet.
+    pub fn import_sprites(
+        &mut self,
+        png: &[u8],
+        id: usize,
+        bank: u8,
+        quantize: Quantize,
+    ) -> Result<(), CartError> {
+        let image = Cover::from_png(png)?;
+        let (width, height) = (image.width as usize, image.height as usize);
+        if width % 8 != 0 || height % 8 != 0 {
+            return Err(CartError::NotSprites { width, height });
+        }
+        let (columns, rows) = (width / 8, height / 8);
+        let off_sheet = CartError::OffSheet { id, width, height };
+        if id >= SPRITES || id % COLUMNS + columns > COLUMNS {
+            return Err(off_sheet);
+        }
+        let last = (rows.max(1) - 1)
+            .checked_mul(COLUMNS)
+            .and_then(|below| below.checked_add(id + columns.max(1) - 1))
+            .filter(|&last| last < SPRITES)
+            .ok_or(off_sheet)?;
+
+        let colors = self.colors(bank)?;
+        let pixels = match quantize {
+            Quantize::Nearest => nearest(&image, &colors, false),
+            Quantize::Dither => nearest(&image, &colors, true),
+            Quantize::Indices => match indices(png)? {
+                Some(indices) => indices,
+                None => nearest(&image, &colors, false),
+            },
+        };
+
+        let kinds = [ChunkType::Tiles, ChunkType::Sprites];
+        let mut sheets = vec![];
+        for kind in kinds {
+            let mut sheet = self.view(kind, bank)?.to_vec();
+            sheet.resize(Tiles::COUNT * Tiles::SIZE, 0);
+            sheets.push(sheet);
+        }
+        for (i, &pixel) in pixels.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let sprite = id + y / 8 * COLUMNS + x / 8;
+            let sheet = &mut sheets[sprite / Tiles::COUNT];
+            let at = sprite % Tiles::COUNT * Tiles::SIZE + y % 8 * 4 + x % 8 / 2;
+            let shift = x % 2 * 4;
+            sheet[at] = sheet[at] & !(0xF << shift) | pixel << shift;
+        }
+        for (sheet, (kind, data)) in kinds.into_iter().zip(sheets).enumerate() {
+            if (id / Tiles::COUNT..=last / Tiles::COUNT).contains(&sheet) {
+                self.set_chunk(kind, bank, data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tiles<'_> {
+    /// The tiles as one 128x128 image, 16 tiles a row, the way TIC-80's sprite editor
+    /// shows them. It's indexed, so that pixels keep their colour where `colors` has
+    /// the same one twice, see [`Quantize::Indices`].
+    pub fn to_png(&self, colors: &Colors) -> Result<Vec<u8>, CartError> {
+        const SIZE: usize = COLUMNS * 8;
+        let pixel = |x: usize, y: usize| self.pixel(y / 8 * COLUMNS + x / 8, x % 8, y % 8);
+        let data: Vec<u8> = (0..SIZE * SIZE / 2)
+            .map(|i| (i * 2 % SIZE, i * 2 / SIZE))
+            .map(|(x, y)| pixel(x, y) << 4 | pixel(x + 1, y))
+            .collect();
+
+        let mut out = vec![];
+        let mut encoder = png::Encoder::new(&mut out, SIZE as u32, SIZE as u32);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Four);
+        encoder.set_palette(colors.concat());
+        encoder.write_header()?.write_image_data(&data)?;
+        Ok(out)
+    }
+}
+
+/// The palette indices of the pixels, if the image is indexed.
+fn indices(png: &[u8]) -> Result<Option<Vec<u8>>, CartError> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != ColorType::Indexed {
+        return Ok(None);
+    }
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let bits = info.bit_depth as usize;
+    let mask = ((1 << bits) - 1) as u8;
+    let mut indices = Vec::with_capacity((info.width * info.height) as usize);
+    for row in data.chunks(info.line_size) {
+        for x in 0..info.width as usize {
+            let shift = 8 - bits - x * bits % 8;
+            let index = row[x * bits / 8] >> shift & mask;
+            if index as usize >= Palette::COLORS {
+                return Err(CartError::NotInPalette { index });
+            }
+            indices.push(index);
+        }
+    }
+    Ok(Some(indices))
+}
+
+/// The closest colour of each pixel, optionally dithered.
+fn nearest(image: &Cover, colors: &Colors, dither: bool) -> Vec<u8> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut error = vec![[0.0f32; 3]; width * height];
+    let mut pixels = Vec::with_capacity(width * height);
+    for (i, rgba) in image.rgba.chunks(4).enumerate() {
+        if rgba[3] < OPAQUE {
+            pixels.push(0);
+            continue;
+        }
+        let wanted: [f32; 3] =
+            std::array::from_fn(|c| (rgba[c] as f32 + error[i][c]).clamp(0.0, 255.0));
+        let index = closest(colors, wanted);
+        pixels.push(index as u8);
+        if !dither {
+            continue;
+        }
+
+        let (x, y) = (i % width, i / width);
+        let off: [f32; 3] = std::array::from_fn(|c| wanted[c] - colors[index][c] as f32);
+        for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+            let (x, y) = (x as isize + dx, y + dy);
+            if x < 0 || x as usize >= width || y >= height {
+                continue;
+            }
+            let error = &mut error[y * width + x as usize];
+            for c in 0..3 {
+                error[c] += off[c] * weight / 16.0;
+            }
+        }
+    }
+    pixels
+}
+
+/// The index of the colour nearest to `rgb`, the first of those as near.
+fn closest(colors: &Colors, rgb: [f32; 3]) -> usize {
+    let distance = |color: &[u8; 3]| -> f32 {
+        color
+            .iter()
+            .zip(rgb)
+            .map(|(&a, b)| (a as f32 - b).powi(2))
+            .sum()
+    };
+    (0..colors.len())
+        .min_by(|&a, &b| distance(&colors[a]).total_cmp(&distance(&colors[b])))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rgba(width: u32, height: u32, pixel: impl Fn(usize, usize) -> [u8; 4]) -> Vec<u8> {
+        let (w, h) = (width as usize, height as usize);
+        let rgba = (0..w * h).flat_map(|i| pixel(i % w, i / w)).collect();
+        Cover {
+            width,
+            height,
+            rgba,
+        }
+        .to_png()
+        .unwrap()
+    }
+
+    #[test]
+    fn nearest() {
+        let mut cart = Cartridge::builder().tiles(0, [0x77; 32]).build().unwrap();
+        // close to colour 2 but for a transparent pixel, and colour 12 on the sprite
+        // below
+        let png = rgba(8, 16, |x, y| match (x, y) {
+            (0, 0) => [0xf4, 0xf4, 0xf4, 0],
+            (_, 0..=7) => [0xb0, 0x40, 0x50, 255],
+            _ => [0xf4, 0xf4, 0xf4, 255],
+        });
+        cart.import_sprites(&png, 255, 0, Quantize::Nearest)
+            .unwrap();
+        let tiles = cart.tiles(0).unwrap();
+        assert_eq!((tiles.pixel(255, 0, 0), tiles.pixel(255, 1, 0)), (0, 2));
+        assert_eq!(tiles.pixel(0, 0, 0), 7);
+        assert_eq!(cart.sprites(0).unwrap().pixel(15, 7, 7), 12);
+    }
+
+    #[test]
+    fn dither() {
+        // halfway between colours 0 and 12, which alternate
+        let mut palette = vec![0; 48];
+        palette[36..39].copy_from_slice(&[0xFF; 3]);
+        let mut cart = Cartridge::builder().palette(1, palette).build().unwrap();
+        let png = rgba(16, 8, |_, _| [0x80, 0x80, 0x80, 255]);
+        cart.import_sprites(&png, 0, 1, Quantize::Dither).unwrap();
+        let tiles = cart.tiles(1).unwrap();
+        let white = (0..8).filter(|&x| tiles.pixel(0, x, 0) == 12).count();
+        assert_eq!(white, 4);
+        assert_eq!((tiles.pixel(0, 0, 0), tiles.pixel(0, 1, 0)), (12, 0));
+
+        cart.import_sprites(&png, 0, 1, Quantize::Nearest).unwrap();
+        assert_eq!(cart.tiles(1).unwrap().pixel(0, 1, 0), 12);
+    }
+
+    #[test]
+    fn indexed() {
+        let mut png = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut png, 8, 8);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_depth(BitDepth::Four);
+            // all the same, so that only the index tells them apart
+            encoder.set_palette([0; 48].to_vec());
+            let data: Vec<u8> = (0..32).map(|i| i as u8 % 16 * 0x11).collect();
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&data)
+                .unwrap();
+        }
+        let mut cart = Cartridge::builder().build().unwrap();
+        cart.import_sprites(&png, 256, 0, Quantize::Indices)
+            .unwrap();
+        let sprites = cart.sprites(0).unwrap();
+        assert_eq!((sprites.pixel(0, 0, 0), sprites.pixel(0, 2, 1)), (0, 5));
+        assert_eq!(cart.tiles(0).unwrap().pixel(0, 0, 0), 0);
+        assert!(cart.chunk(ChunkType::Tiles, 0).unwrap().is_none());
+
+        cart.import_sprites(&png, 256, 0, Quantize::Nearest)
+            .unwrap();
+        assert_eq!(cart.sprites(0).unwrap().pixel(0, 2, 1), 0);
+    }
+
+    #[test]
+    fn sheet() {
+        // the same colour twice, so that only the index tells them apart
+        let mut cart = Cartridge::builder()
+            .tiles(0, [0x10, 0xF0, 0x32])
+            .palette(0, [0; 48])
+            .build()
+            .unwrap();
+        let sheet = cart
+            .tiles(0)
+            .unwrap()
+            .to_png(&cart.colors(0).unwrap())
+            .unwrap();
+        let expected = cart.to_bytes().unwrap();
+        cart.set_chunk(ChunkType::Tiles, 0, vec![]).unwrap();
+        cart.import_sprites(&sheet, 0, 0, Quantize::Indices)
+            .unwrap();
+        assert_eq!(cart.to_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn errors() {
+        let mut cart = Cartridge::builder().build().unwrap();
+        let result = cart.import_sprites(&rgba(12, 8, |_, _| [0; 4]), 0, 0, Quantize::Nearest);
+        assert!(matches!(
+            result,
+            Err(CartError::NotSprites {
+                width: 12,
+                height: 8
+            })
+        ));
+        let wide = rgba(16, 8, |_, _| [0; 4]);
+        let result = cart.import_sprites(&wide, 15, 0, Quantize::Nearest);
+        assert!(matches!(result, Err(CartError::OffSheet { id: 15, .. })));
+        assert!(cart
+            .import_sprites(&wide, 510, 0, Quantize::Nearest)
+            .is_ok());
+        let tall = rgba(8, 16, |_, _| [0; 4]);
+        let result = cart.import_sprites(&tall, 500, 0, Quantize::Nearest);
+        assert!(matches!(result, Err(CartError::OffSheet { .. })));
+        let result = cart.import_sprites(&wide, usize::MAX, 0, Quantize::Nearest);
+        assert!(matches!(result, Err(CartError::OffSheet { .. })));
+    }
+}
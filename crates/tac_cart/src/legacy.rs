@@ -0,0 +1,183 @@
+//! Chunks older versions of TIC-80 wrote, which current carts store differently.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::{CartError, Cartridge, Chunk, ChunkType, Cover};
+
+/// Notes below this are empty or note offs, which never had a volume.
+const FIRST_NOTE: u8 = 4;
+const MAX_VOLUME: u8 = 15;
+/// The `M` command, that sets the volume.
+const VOLUME_COMMAND: u8 = 1;
+
+impl Cartridge {
+    /// Replaces deprecated chunks with the current ones: zlib compressed code is
+    /// inflated, and patterns from before effect commands are converted. A
+    /// deprecated chunk is dropped if the cart also has its replacement.
+    #[allow(deprecated)]
+    pub fn upgrade(&mut self) -> Result<(), CartError> {
+        let current = |kind, bank| {
+            self.chunks
+                .iter()
+                .any(|chunk| chunk.info.chunk_type() == kind && chunk.info.bank() == bank)
+        };
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        for chunk in &self.chunks {
+            let (kind, data) = match chunk.info.chunk_type() {
+                ChunkType::CodeZip => (ChunkType::Code, inflate_code(&chunk.data)?),
+                ChunkType::PatternsDep => (ChunkType::Patterns, upgrade_patterns(&chunk.data)),
+                _ => {
+                    chunks.push(chunk.clone());
+                    continue;
+                }
+            };
+            if !current(kind, chunk.info.bank()) {
+                chunks.push(Chunk::new(kind, chunk.info.bank(), data)?);
+            }
+        }
+        self.chunks = chunks;
+        Ok(())
+    }
+
+    /// The cover image carts used to have, a GIF. Current carts show their `Screen`
+    /// chunk instead, see [`Cartridge::cover`].
+    #[allow(deprecated)]
+    pub fn legacy_cover(&self) -> Result<Option<Cover>, CartError> {
+        let Some(chunk) = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.info.chunk_type() == ChunkType::CoverDep)
+        else {
+            return Ok(None);
+        };
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(&chunk.data[..])?;
+        let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+        let mut rgba = [0, 0, 0, 255].repeat(width * height);
+        // a frame without pixels would fail to decode, it's left out
+        let frame = decoder.next_frame_info()?;
+        if let Some(frame) = frame.filter(|frame| frame.width > 0 && frame.height > 0) {
+            let (left, top) = (frame.left as usize, frame.top as usize);
+            let stride = frame.width as usize * 4;
+            let mut buffer = vec![0; decoder.buffer_size()];
+            decoder.read_into_buffer(&mut buffer)?;
+            for (y, row) in buffer.chunks(stride).enumerate() {
+                for (x, pixel) in row.chunks(4).enumerate() {
+                    let (x, y) = (left + x, top + y);
+                    if x < width && y < height && pixel[3] != 0 {
+                        let at = (y * width + x) * 4;
+                        rgba[at..at + 4].copy_from_slice(pixel);
+                    }
+                }
+            }
+        }
+        Ok(Some(Cover {
+            width: width as u32,
+            height: height as u32,
+            rgba,
+        }))
+    }
+}
+
+fn inflate_code(data: &[u8]) -> Result<Vec<u8>, CartError> {
+    let mut code = vec![];
+    ZlibDecoder::new(data).read_to_end(&mut code)?;
+    Ok(code)
+}
+
+/// Pattern rows had no command, only a volume where the second parameter is now,
+/// counting down from full. They become an `M` command with that volume.
+fn upgrade_patterns(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for row in data.chunks_exact_mut(3) {
+        let note = row[0] & 0xF;
+        if note >= FIRST_NOTE {
+            let volume = MAX_VOLUME - (row[1] & 0xF);
+            row[0] = note | volume << 4;
+            row[1] = row[1] & 0x80 | VOLUME_COMMAND << 4 | volume;
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn cart(chunks: &[(u8, &[u8])]) -> Cartridge {
+        let mut bytes = vec![];
+        for &(kind, data) in chunks {
+            bytes.extend([kind, data.len() as u8, (data.len() >> 8) as u8, 0]);
+            bytes.extend(data);
+        }
+        let mut cart = Cartridge::try_from(bytes.as_slice()).unwrap();
+        cart.upgrade().unwrap();
+        cart
+    }
+
+    fn chunk(cart: &Cartridge, kind: ChunkType) -> &[u8] {
+        let chunk = cart.chunks.iter().find(|c| c.info.chunk_type() == kind);
+        &chunk.unwrap().data
+    }
+
+    #[test]
+    fn code_zip() {
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(b"function TIC() cls() end").unwrap();
+        let cart = cart(&[(16, &zlib.finish().unwrap())]);
+        assert_eq!(chunk(&cart, ChunkType::Code), b"function TIC() cls() end");
+        assert_eq!(cart.chunks.len(), 1);
+    }
+
+    #[test]
+    fn patterns() {
+        // C-4 at volume 12, sfx 33; a note off; an empty row
+        let rows = [0x04, 0x83, 0x61, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let cart = cart(&[(13, &rows)]);
+        assert_eq!(
+            chunk(&cart, ChunkType::Patterns),
+            [0xC4, 0x9C, 0x61, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+
+        // the current chunk wins over the deprecated one
+        let cart = self::cart(&[(13, &rows), (15, &[0x05, 0, 0])]);
+        assert_eq!(cart.chunks.len(), 1);
+        assert_eq!(chunk(&cart, ChunkType::Patterns), [0x05, 0, 0]);
+    }
+
+    #[test]
+    fn cover() {
+        let mut gif = vec![];
+        {
+            let palette = [0, 0, 0, 0xFF, 0, 0];
+            let mut encoder = gif::Encoder::new(&mut gif, 4, 2, &palette).unwrap();
+            let mut frame = gif::Frame::from_indexed_pixels(3, 1, [1, 0, 1], None);
+            frame.left = 1;
+            frame.top = 1;
+            encoder.write_frame(&frame).unwrap();
+        }
+        let cart = cart(&[(5, b"x=1"), (3, &gif)]);
+        let cover = cart.legacy_cover().unwrap().unwrap();
+        assert_eq!((cover.width, cover.height), (4, 2));
+        let red = |x: usize, y: usize| cover.rgba[(y * 4 + x) * 4] == 0xFF;
+        assert!(red(1, 1) && !red(2, 1) && red(3, 1));
+        assert!(!red(1, 0) && !red(0, 1));
+
+        assert_eq!(self::cart(&[(5, b"x=1")]).legacy_cover().unwrap(), None);
+
+        // a 4x2 image, its one frame 0 pixels wide
+        let mut gif = b"GIF89a\x04\0\x02\0\x80\0\0".to_vec();
+        gif.extend([0; 6]);
+        gif.extend(b"\x2C\0\0\0\0\0\0\x02\0\0\x02\x01\x2C\0\x3B");
+        let cover = self::cart(&[(5, b"x=1"), (3, &gif)]).legacy_cover();
+        assert_eq!(cover.unwrap().unwrap().rgba, [0, 0, 0, 255].repeat(8));
+    }
+}
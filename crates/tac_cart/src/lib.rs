@@ -1,68 +1,141 @@
-use std::{error::Error, fmt::Debug, io::Cursor, path::Path, u8, slice::Iter};
+mod assets;
+mod error;
+mod import;
+mod legacy;
+mod meta;
+mod png;
+mod text;
+mod tiled;
+mod write;
+
+use std::{fmt::Debug, io::Cursor, path::Path};
 
 use binread::prelude::*;
 use binwrite::*;
-use modular_bitfield::prelude::*;
-
-#[derive(BitfieldSpecifier, Debug, Clone, Copy, PartialEq, Eq)]
-#[bits = 5]
-pub enum ChunkType {
-    Dummy = 0,
-    Tiles = 1,
-    Sprites = 2,
-
-    Map = 4,
-    Code = 5,
-    Flags = 6,
-    Samples = 9,
-    Waveform = 10,
-    Palette = 12,
-    Music = 14,
-    Patterns = 15,
-    Default = 17,
-    Screen = 18,
-    Binary = 19,
-
-    #[deprecated]
-    CoverDep = 3,
-    #[deprecated]
-    CodeZip = 16,
-    #[deprecated]
-    PatternsDep = 13,
-}
 
-#[bitfield]
-#[derive(BinRead, BinWrite, Debug, Clone)]
-#[br(map = Self::from_bytes)]
-pub struct ChunkInfo {
-    pub chunk_type: ChunkType,
-    pub bank: B3, // specifies in which bank the chunk lives
-}
+pub use assets::{
+    CartridgeBuilder, Map, Palette, PatternRow, Patterns, Sfx, SfxTick, Tiles, BANKS,
+    DEFAULT_PALETTE,
+};
+pub use error::CartError;
+pub use header::{ChunkInfo, ChunkType};
+pub use import::Quantize;
+pub use meta::{CartMetadata, Input, Language};
+pub use png::Cover;
+pub use tiled::TiledFormat;
+
+mod header {
+    // modular-bitfield's generated code trips these on the deprecated chunk types
+    #![allow(deprecated, unused_parens, clippy::new_without_default)]
+
+    use binread::prelude::*;
+    use binwrite::*;
+    use modular_bitfield::prelude::*;
+
+    #[derive(BitfieldSpecifier, Debug, Clone, Copy, PartialEq, Eq)]
+    #[bits = 5]
+    pub enum ChunkType {
+        Dummy = 0,
+        Tiles = 1,
+        Sprites = 2,
+
+        Map = 4,
+        Code = 5,
+        Flags = 6,
+        Samples = 9,
+        Waveform = 10,
+        Palette = 12,
+        Music = 14,
+        Patterns = 15,
+        Default = 17,
+        Screen = 18,
+        Binary = 19,
+
+        #[deprecated]
+        CoverDep = 3,
+        #[deprecated]
+        CodeZip = 16,
+        #[deprecated]
+        PatternsDep = 13,
+    }
 
+    #[bitfield]
+    #[derive(BinRead, BinWrite, Debug, Clone, PartialEq, Eq)]
+    #[br(map = Self::from_bytes)]
+    pub struct ChunkInfo {
+        pub chunk_type: ChunkType,
+        pub bank: B3, // specifies in which bank the chunk lives
+    }
+}
 
-#[derive(BinRead, BinWrite, Debug, Clone)]
+#[derive(BinRead, BinWrite, Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
     pub info: ChunkInfo,
 
+    /// 0 for code that takes the whole bank, 64KB.
     #[br(little)]
+    #[binwrite(little)]
     pub size: u16,
 
     pub reserved: u8,
 
-    #[br(count=size)]
+    #[br(count = Chunk::len(&info, size))]
     pub data: Vec<u8>,
 }
 
+impl Chunk {
+    /// Code that fills its bank.
+    const FULL_CODE: usize = 0x10000;
+
+    pub(crate) fn new(kind: ChunkType, bank: u8, data: Vec<u8>) -> Result<Chunk, CartError> {
+        let size = match (kind, data.len()) {
+            (ChunkType::Code, Self::FULL_CODE) => 0,
+            (_, len) => u16::try_from(len).map_err(|_| CartError::ChunkTooLarge {
+                chunk: kind,
+                bank,
+                size: len,
+                max: u16::MAX as usize,
+            })?,
+        };
+        Ok(Chunk {
+            info: ChunkInfo::new().with_chunk_type(kind).with_bank(bank),
+            size,
+            reserved: 0,
+            data,
+        })
+    }
+
+    /// Bytes of data a chunk has, from its header.
+    fn len(info: &ChunkInfo, size: u16) -> usize {
+        match (info.chunk_type_or_err(), size) {
+            (Ok(ChunkType::Code), 0) => Self::FULL_CODE,
+            _ => size as usize,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Cartridge {
+    /// The name of the file it was loaded from. See [`Cartridge::metadata`] for the
+    /// title it gives itself.
     pub title: String,
     pub chunks: Vec<Chunk>,
 }
 
 impl TryFrom<&[u8]> for Cartridge {
-    type Error = Box<dyn Error>;
+    type Error = CartError;
 
+    /// Reads a cart from its chunks, or from a PNG image it's hidden in.
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        match bytes.starts_with(png::SIGNATURE) {
+            true => Cartridge::from_png(bytes),
+            false => Cartridge::from_chunks(bytes),
+        }
+    }
+}
+
+impl Cartridge {
+    fn from_chunks(bytes: &[u8]) -> Result<Self, CartError> {
         let mut cursor = Cursor::new(bytes);
         let mut cart = Cartridge {
             chunks: vec![],
@@ -70,7 +143,17 @@ impl TryFrom<&[u8]> for Cartridge {
         };
 
         while (cursor.position() as usize) < bytes.len() {
-            let chunk: Chunk = cursor.read_ne()?;
+            let offset = cursor.position();
+            let chunk: Chunk = cursor
+                .read_ne()
+                .map_err(|source| CartError::Malformed { offset, source })?;
+            // so that `chunk_type` can't panic later on
+            if chunk.info.chunk_type_or_err().is_err() {
+                return Err(CartError::UnknownChunk {
+                    kind: bytes[offset as usize] & 0x1F,
+                    offset,
+                });
+            }
             cart.chunks.push(chunk);
         }
         Ok(cart)
@@ -78,21 +161,38 @@ impl TryFrom<&[u8]> for Cartridge {
 }
 
 impl TryInto<Vec<u8>> for Cartridge {
-    type Error = Box<dyn Error>;
+    type Error = CartError;
 
     fn try_into(self) -> Result<Vec<u8>, Self::Error> {
-        let mut out = Vec::new();
-        for chunk in self.chunks {
-            chunk.write(&mut out)?;
-        }
-        Ok(out)
+        self.to_bytes()
     }
 }
 
 impl Cartridge {
-    pub fn load(path: impl AsRef<Path>) -> Result<Cartridge, Box<dyn Error>> {
+    /// Reads the cart at `path`: a `.lua` file saved as source, or a binary or PNG cart.
+    pub fn load(path: impl AsRef<Path>) -> Result<Cartridge, CartError> {
+        let path = path.as_ref();
         let bytes = std::fs::read(path)?;
-        Ok(Cartridge::try_from(bytes.as_slice())?)
+        let mut cart = Cartridge::from_file(path, &bytes)?;
+        if let Some(name) = path.file_name() {
+            cart.title = name.to_string_lossy().into_owned();
+        }
+        Ok(cart)
+    }
+
+    /// Reads a cart from `bytes`, the contents of the file at `path`, as
+    /// [`Cartridge::load`] does.
+    pub fn from_file(path: &Path, bytes: &[u8]) -> Result<Cartridge, CartError> {
+        if Cartridge::is_text(path) {
+            Cartridge::from_text(&String::from_utf8_lossy(bytes))
+        } else {
+            Cartridge::try_from(bytes)
+        }
+    }
+
+    /// Whether the file at `path` is a cart saved as source.
+    pub fn is_text(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "lua")
     }
 }
 
@@ -111,3 +211,26 @@ impl Debug for Cartridge {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errors() {
+        let code = [5, 3, 0, 0, b'x', b'=', b'1'];
+        let cart = Cartridge::try_from(&code[..]).unwrap();
+        assert_eq!(cart.chunks[0].info.chunk_type(), ChunkType::Code);
+        assert_eq!(cart.chunks[0].data, b"x=1");
+
+        let unknown = [&code[..], &[7, 0, 0, 0]].concat();
+        assert!(matches!(
+            Cartridge::try_from(&unknown[..]),
+            Err(CartError::UnknownChunk { kind: 7, offset: 7 })
+        ));
+        assert!(matches!(
+            Cartridge::try_from(&code[..6]),
+            Err(CartError::Malformed { offset: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//! What a cart says about itself, in comments at the top of its code:
+//!
+//! ```lua
+//! -- title:  Space Cats
+//! -- author: someone
+//! -- script: lua
+//! -- saveid: spacecats
+//! ```
+
+use std::fmt;
+
+use crate::Cartridge;
+
+/// The language a cart is written in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    Lua,
+    Moon,
+    Js,
+    Wren,
+    Fennel,
+    Squirrel,
+    Ruby,
+    Janet,
+    Python,
+    Other(String),
+}
+
+impl Language {
+    fn parse(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "lua" => Language::Lua,
+            "moon" | "moonscript" => Language::Moon,
+            "js" | "javascript" => Language::Js,
+            "wren" => Language::Wren,
+            "fennel" => Language::Fennel,
+            "squirrel" => Language::Squirrel,
+            "ruby" => Language::Ruby,
+            "janet" => Language::Janet,
+            "python" => Language::Python,
+            _ => Language::Other(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Language::Lua => "lua",
+            Language::Moon => "moon",
+            Language::Js => "js",
+            Language::Wren => "wren",
+            Language::Fennel => "fennel",
+            Language::Squirrel => "squirrel",
+            Language::Ruby => "ruby",
+            Language::Janet => "janet",
+            Language::Python => "python",
+            Language::Other(name) => name,
+        };
+        f.write_str(name)
+    }
+}
+
+/// The input a cart asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Gamepad,
+    Mouse,
+    Keyboard,
+}
+
+/// The tags in a cart's header comments. Missing tags are `None`, or empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CartMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub desc: Option<String>,
+    /// Lua if it's not set.
+    pub script: Language,
+    /// `None` if it's not set, or not one this knows.
+    pub input: Option<Input>,
+    /// What pmem is saved under, so that it stays with a cart that changes.
+    pub saveid: Option<String>,
+    /// Items of the game menu.
+    pub menu: Vec<String>,
+}
+
+impl CartMetadata {
+    /// Reads the tags from comment lines like `-- title: ...`. The comment can be
+    /// any of `--`, `//`, `#` and `;;`, so that it works for every language. Only
+    /// the first of each tag counts.
+    pub fn parse(code: &str) -> Self {
+        let tag = |name: &str| {
+            code.lines().find_map(|line| {
+                let line = line.trim_start();
+                let comment = ["--", "//", "#", ";;"]
+                    .iter()
+                    .find_map(|comment| line.strip_prefix(comment))?;
+                let value = comment.trim_start().strip_prefix(name)?.strip_prefix(':')?;
+                Some(value.trim().to_string()).filter(|value| !value.is_empty())
+            })
+        };
+
+        CartMetadata {
+            title: tag("title"),
+            author: tag("author"),
+            desc: tag("desc"),
+            script: tag("script").map_or(Language::Lua, |name| Language::parse(&name)),
+            input: tag("input").and_then(|input| match input.to_ascii_lowercase().as_str() {
+                "gamepad" => Some(Input::Gamepad),
+                "mouse" => Some(Input::Mouse),
+                "keyboard" => Some(Input::Keyboard),
+                _ => None,
+            }),
+            saveid: tag("saveid"),
+            menu: tag("menu")
+                .map(|menu| menu.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Cartridge {
+    /// The tags in the cart's code. All empty if it has none, or no code.
+    pub fn metadata(&self) -> CartMetadata {
+        self.code()
+            .map(|code| CartMetadata::parse(&code))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let meta = CartMetadata::parse(
+            "-- title:   Space Cats \n\
+             --author: someone\n\
+             -- desc: shoot: the cats\n\
+             -- script: Moon\n\
+             -- input: mouse\n\
+             -- saveid: spacecats\n\
+             -- menu: CONTINUE RESTART  QUIT\n\
+             -- title: not this one\n\
+             x = 1 -- desc: nor this",
+        );
+        assert_eq!(
+            meta,
+            CartMetadata {
+                title: Some("Space Cats".to_string()),
+                author: Some("someone".to_string()),
+                desc: Some("shoot: the cats".to_string()),
+                script: Language::Moon,
+                input: Some(Input::Mouse),
+                saveid: Some("spacecats".to_string()),
+                menu: vec!["CONTINUE".into(), "RESTART".into(), "QUIT".into()],
+            }
+        );
+
+        let meta = CartMetadata::parse("// title: in js\n// script: js\n// titles: no");
+        assert_eq!(meta.title.as_deref(), Some("in js"));
+        assert_eq!(meta.script, Language::Js);
+
+        let meta = CartMetadata::parse("# script: basic\n# input: joystick\n-- saveid:");
+        assert_eq!(meta.script, Language::Other("basic".to_string()));
+        assert_eq!((meta.input, meta.saveid), (None, None));
+        assert_eq!(CartMetadata::parse("x = 1"), CartMetadata::default());
+    }
+}
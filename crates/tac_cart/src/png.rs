@@ -0,0 +1,326 @@
+//! Carts hidden in PNG images, the way TIC-80 shares them.
+//!
+//! Current versions spread the cart over the least significant bits of the image's
+//! RGBA bytes. The first 8 bytes hold its size, 4 bits each, least significant
+//! nibble first. Every byte after that holds the next `bits` bits of the cart, the
+//! fewest per byte that fit it in the image, least significant first. Older
+//! versions stored it whole in a `caRt` chunk instead. Either way the cart is
+//! usually zlib compressed.
+
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use png::{BitDepth, ColorType, Transformations};
+
+use crate::{CartError, Cartridge, ChunkType, Palette};
+
+pub(crate) const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunk the cart is stored in by older versions of TIC-80.
+const CART_CHUNK: &[u8; 4] = b"caRt";
+/// Image bytes taken by the size of the cart, and how many of their bits it takes.
+const HEADER_SIZE: usize = 8;
+const HEADER_BITS: usize = 4;
+
+/// An image to hide a cart in, or any other image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cover {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels as RGBA, row by row.
+    pub rgba: Vec<u8>,
+}
+
+impl Cover {
+    /// Reads any PNG image.
+    pub fn from_png(bytes: &[u8]) -> Result<Self, CartError> {
+        read_png(bytes)
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, CartError> {
+        let mut out = vec![];
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(ColorType::RGBA);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.rgba)?;
+        Ok(out)
+    }
+}
+
+impl Cartridge {
+    /// Reads a cart from a PNG image saved by TIC-80, or by [`Cartridge::to_png`].
+    pub fn from_png(bytes: &[u8]) -> Result<Self, CartError> {
+        let payload = match cart_chunk(bytes)? {
+            Some(payload) => payload.to_vec(),
+            None => {
+                let cover = read_png(bytes)?;
+                extract(&cover.rgba).ok_or(CartError::NoCartInPng)?
+            }
+        };
+        Cartridge::from_chunks(&inflate(payload)?)
+    }
+
+    /// Writes the cart into `cover`, growing it downwards if it's too small, as a PNG
+    /// image. Fails if the cover has no width to grow.
+    pub fn to_png(&self, cover: &Cover) -> Result<Vec<u8>, CartError> {
+        if cover.width == 0 {
+            return Err(CartError::EmptyCover);
+        }
+        let raw = self.to_bytes()?;
+        let mut zlib = ZlibEncoder::new(vec![], Compression::best());
+        zlib.write_all(&raw)?;
+        let payload = zlib.finish()?;
+
+        let row = cover.width as usize * 4;
+        let mut rgba = cover.rgba.clone();
+        let needed = HEADER_SIZE + payload.len();
+        if rgba.len() < needed {
+            let rows = (needed - rgba.len()).div_ceil(row);
+            rgba.extend([0, 0, 0, 255].repeat(rows * cover.width as usize));
+        }
+        embed(&mut rgba, &payload);
+        Cover {
+            width: cover.width,
+            height: (rgba.len() / row) as u32,
+            rgba,
+        }
+        .to_png()
+    }
+
+    /// The cart's screen chunk in its palette, for a cover. Black if it has neither, or
+    /// they don't fit.
+    pub fn cover(&self) -> Cover {
+        const WIDTH: usize = 240;
+        const HEIGHT: usize = 136;
+        let screen = match self.chunk(ChunkType::Screen, 0) {
+            Ok(Some(chunk)) => &chunk.data[..],
+            _ => &[],
+        };
+        let palette = self.palette(0).unwrap_or(Palette::EMPTY);
+
+        let mut rgba = Vec::with_capacity(WIDTH * HEIGHT * 4);
+        for i in 0..WIDTH * HEIGHT {
+            let byte = screen.get(i / 2).copied().unwrap_or(0);
+            let color = (byte >> (i % 2 * 4) & 0xF) as usize;
+            rgba.extend(palette.color(color));
+            rgba.push(255);
+        }
+        Cover {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            rgba,
+        }
+    }
+}
+
+/// The contents of the `caRt` chunk, if there is one.
+fn cart_chunk(bytes: &[u8]) -> Result<Option<&[u8]>, CartError> {
+    let mut rest = bytes
+        .strip_prefix(SIGNATURE)
+        .ok_or(CartError::NoCartInPng)?;
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..]);
+        if data.len() < len + 4 {
+            break;
+        }
+        if kind == CART_CHUNK {
+            return Ok(Some(&data[..len]));
+        }
+        rest = &data[len + 4..]; // and the CRC
+    }
+    Ok(None)
+}
+
+fn read_png(bytes: &[u8]) -> Result<Cover, CartError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+
+    let rgba = match info.color_type {
+        ColorType::RGBA => pixels,
+        ColorType::RGB => pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => pixels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        _ => pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+    };
+    Ok(Cover {
+        width: info.width,
+        height: info.height,
+        rgba,
+    })
+}
+
+/// Bits of the cart each image byte holds, for a cart of `size` bytes.
+fn bits_per_byte(size: usize, image: usize) -> Option<usize> {
+    let capacity = image.checked_sub(HEADER_SIZE).filter(|&c| c > 0)?;
+    let bits = (size * 8).div_ceil(capacity).max(1);
+    (bits <= 8).then_some(bits)
+}
+
+fn extract(image: &[u8]) -> Option<Vec<u8>> {
+    let mut size = 0;
+    for (i, byte) in image.get(..HEADER_SIZE)?.iter().enumerate() {
+        size |= ((byte & 0xF) as usize) << (i * HEADER_BITS);
+    }
+    if size == 0 {
+        return None;
+    }
+    let bits = bits_per_byte(size, image.len())?;
+
+    let mut out = vec![0; size];
+    for (i, byte) in image[HEADER_SIZE..].iter().enumerate() {
+        for b in 0..bits {
+            let to = i * bits + b;
+            if to >= size * 8 {
+                return Some(out);
+            }
+            out[to / 8] |= (byte >> b & 1) << (to % 8);
+        }
+    }
+    Some(out)
+}
+
+fn embed(image: &mut [u8], data: &[u8]) {
+    for (i, byte) in image[..HEADER_SIZE].iter_mut().enumerate() {
+        *byte = *byte & 0xF0 | (data.len() >> (i * HEADER_BITS) & 0xF) as u8;
+    }
+    let bits = bits_per_byte(data.len(), image.len()).unwrap();
+    let mask = ((1u16 << bits) - 1) as u8;
+    for (i, byte) in image[HEADER_SIZE..].iter_mut().enumerate() {
+        let from = i * bits;
+        if from >= data.len() * 8 {
+            break;
+        }
+        let mut value = 0;
+        for b in 0..bits {
+            let bit = data
+                .get((from + b) / 8)
+                .map_or(0, |d| d >> ((from + b) % 8) & 1);
+            value |= bit << b;
+        }
+        *byte = *byte & !mask | value;
+    }
+}
+
+/// Decompresses `payload` if it's zlib compressed.
+fn inflate(payload: Vec<u8>) -> Result<Vec<u8>, CartError> {
+    // the headers zlib writes at each compression level
+    let zlib = matches!(payload[..], [0x78, 0x01 | 0x5E | 0x9C | 0xDA, ..]);
+    if !zlib {
+        return Ok(payload);
+    }
+    let mut out = vec![];
+    ZlibDecoder::new(&payload[..]).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cart() -> Cartridge {
+        let mut bytes = vec![5, 10, 0, 0];
+        bytes.extend(b"-- a cart\n");
+        bytes.extend([18, 0xC0, 0x3F, 0]); // 16320 bytes of screen
+        bytes.extend((0..16320).map(|i| (i % 7) as u8));
+        Cartridge::try_from(bytes.as_slice()).unwrap()
+    }
+
+    fn raw(cart: &Cartridge) -> Vec<u8> {
+        cart.clone().try_into().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let cart = cart();
+        let cover = cart.cover();
+        assert_eq!(cover.rgba.len(), 240 * 136 * 4);
+        let png = cart.to_png(&cover).unwrap();
+        assert_eq!(raw(&Cartridge::from_png(&png).unwrap()), raw(&cart));
+        assert_eq!(
+            raw(&Cartridge::try_from(png.as_slice()).unwrap()),
+            raw(&cart)
+        );
+
+        // the image still looks like the cover
+        let image = read_png(&png).unwrap();
+        assert_eq!((image.width, image.height), (240, 136));
+        for (a, b) in image.rgba.iter().zip(&cover.rgba).skip(HEADER_SIZE) {
+            assert!(a.abs_diff(*b) < 4);
+        }
+
+        // a cover too small for the cart grows
+        let tiny = Cover {
+            width: 4,
+            height: 1,
+            rgba: vec![255; 16],
+        };
+        let png = cart.to_png(&tiny).unwrap();
+        assert!(read_png(&png).unwrap().height > 1);
+        assert_eq!(raw(&Cartridge::from_png(&png).unwrap()), raw(&cart));
+        let empty = Cover {
+            width: 0,
+            height: 0,
+            rgba: vec![],
+        };
+        assert!(matches!(cart.to_png(&empty), Err(CartError::EmptyCover)));
+    }
+
+    #[test]
+    fn lsb_uncompressed() {
+        let cart = cart();
+        let data = raw(&cart);
+        let mut rgba = vec![0x80; 256 * 256 * 4];
+        embed(&mut rgba, &data);
+        assert_eq!(extract(&rgba).unwrap(), data);
+
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, 256, 256);
+        encoder.set_color(ColorType::RGBA);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&rgba)
+            .unwrap();
+        assert_eq!(raw(&Cartridge::from_png(&png).unwrap()), data);
+    }
+
+    #[test]
+    fn cart_chunk() {
+        let cart = cart();
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&raw(&cart)).unwrap();
+
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, 2, 2);
+        encoder.set_color(ColorType::RGB);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_chunk(*CART_CHUNK, &zlib.finish().unwrap())
+            .unwrap();
+        writer.write_image_data(&[0; 12]).unwrap();
+        drop(writer);
+        assert_eq!(raw(&Cartridge::from_png(&png).unwrap()), raw(&cart));
+
+        let mut blank = vec![];
+        let mut encoder = png::Encoder::new(&mut blank, 2, 2);
+        encoder.set_color(ColorType::RGB);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0; 12])
+            .unwrap();
+        assert!(matches!(
+            Cartridge::from_png(&blank),
+            Err(CartError::NoCartInPng)
+        ));
+    }
+}
@@ -0,0 +1,302 @@
+//! Carts saved as plain source, the way TIC-80 writes `.lua` carts: the code, then
+//! every asset as hex dumps in comment blocks.
+//!
+//! ```text
+//! function TIC() ... end
+//!
+//! -- <TILES>
+//! -- 001:00000000000ff00000f00f000f0000f0
+//! -- </TILES>
+//! ```
+//!
+//! Each line of a block is one item: a tile, a map row, a waveform... numbered in
+//! decimal. Items that are all zeroes are left out, and so are empty blocks. Blocks
+//! for banks other than the first are suffixed with the bank, `-- <TILES1>`. Pixel
+//! data is written with the nibbles of each byte swapped, so pixels read left to
+//! right.
+
+use crate::{CartError, Cartridge, Chunk, ChunkType};
+
+/// A kind of asset block: its tag, chunk, and how it's cut into lines.
+struct Section {
+    tag: &'static str,
+    chunk: ChunkType,
+    count: usize,
+    size: usize,
+    flip: bool,
+}
+
+/// In the order they are written.
+const SECTIONS: [Section; 10] = [
+    Section::new("TILES", ChunkType::Tiles, 256, 32, true),
+    Section::new("SPRITES", ChunkType::Sprites, 256, 32, true),
+    Section::new("MAP", ChunkType::Map, 136, 240, true),
+    Section::new("WAVES", ChunkType::Waveform, 16, 16, true),
+    Section::new("SFX", ChunkType::Samples, 64, 66, true),
+    Section::new("PATTERNS", ChunkType::Patterns, 60, 192, true),
+    Section::new("TRACKS", ChunkType::Music, 8, 51, true),
+    Section::new("FLAGS", ChunkType::Flags, 2, 256, false),
+    Section::new("SCREEN", ChunkType::Screen, 136, 120, true),
+    Section::new("PALETTE", ChunkType::Palette, 2, 48, false),
+];
+
+impl Section {
+    const fn new(
+        tag: &'static str,
+        chunk: ChunkType,
+        count: usize,
+        size: usize,
+        flip: bool,
+    ) -> Self {
+        Self {
+            tag,
+            chunk,
+            count,
+            size,
+            flip,
+        }
+    }
+
+    /// The section and bank a `-- <TAG>` line opens.
+    fn open(line: &str) -> Option<(&'static Section, u8)> {
+        let tag = line.trim_end().strip_prefix("-- <")?.strip_suffix('>')?;
+        SECTIONS.iter().find_map(|section| {
+            let bank = tag.strip_prefix(section.tag)?;
+            match bank {
+                "" => Some((section, 0)),
+                _ => bank.parse().ok().filter(|&b| b < 8).map(|b| (section, b)),
+            }
+        })
+    }
+}
+
+impl Cartridge {
+    /// Reads a cart saved as source.
+    pub fn from_text(text: &str) -> Result<Self, CartError> {
+        let mut code = String::new();
+        let mut chunks = vec![];
+        let mut lines = text.split_inclusive('\n').enumerate();
+        let mut sections = false;
+
+        while let Some((_, line)) = lines.next() {
+            let Some((section, bank)) = Section::open(line) else {
+                code.push_str(line);
+                continue;
+            };
+            // the blank line the block was set apart with
+            if code.ends_with("\n\n") || code == "\n" {
+                code.pop();
+            }
+            sections = true;
+
+            let close = line.trim_end().replacen("-- <", "-- </", 1);
+            let mut data = vec![0; section.count * section.size];
+            loop {
+                let Some((n, line)) = lines.next() else {
+                    return Err(bad_text(text.lines().count(), "unclosed block"));
+                };
+                let line = line.trim_end();
+                if line == close {
+                    break;
+                }
+                let (index, hex) = line
+                    .strip_prefix("-- ")
+                    .and_then(|line| line.split_once(':'))
+                    .ok_or_else(|| bad_text(n + 1, "expected `-- NNN:data`"))?;
+                let index: usize = index
+                    .parse()
+                    .ok()
+                    .filter(|&i| i < section.count)
+                    .ok_or_else(|| bad_text(n + 1, "item number out of range"))?;
+                if hex.len() > section.size * 2 || hex.len() % 2 != 0 {
+                    return Err(bad_text(n + 1, "wrong length of data"));
+                }
+                let item = &mut data[index * section.size..];
+                for (byte, pair) in item.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                    let pair = std::str::from_utf8(pair).unwrap_or("");
+                    let value =
+                        u8::from_str_radix(pair, 16).map_err(|_| bad_text(n + 1, "invalid hex"))?;
+                    *byte = if section.flip {
+                        value.rotate_left(4)
+                    } else {
+                        value
+                    };
+                }
+            }
+
+            let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            if len > 0 {
+                data.truncate(len);
+                chunks.push(Chunk::new(section.chunk, bank, data)?);
+            }
+        }
+        if sections {
+            code.pop();
+        }
+
+        let mut cart = Cartridge {
+            title: "cart.lua".to_string(),
+            chunks,
+        };
+        cart.set_code(&code)?;
+        Ok(cart)
+    }
+
+    /// Writes the cart as source. Chunks that aren't code or assets, like the
+    /// default palette flag, are left out.
+    pub fn to_text(&self) -> Result<String, CartError> {
+        let mut cart = self.clone();
+        cart.upgrade()?;
+        let mut out = match cart.code() {
+            Err(CartError::NoCode) => String::new(),
+            code => code?,
+        };
+        for chunk in &cart.chunks {
+            match chunk.info.chunk_type() {
+                ChunkType::Code | ChunkType::Default | ChunkType::Dummy => {}
+                kind if SECTIONS.iter().any(|section| section.chunk == kind) => {}
+                kind => return Err(CartError::Unsupported { chunk: kind }),
+            }
+        }
+
+        let mut blocks = String::new();
+        for section in &SECTIONS {
+            for bank in 0..8 {
+                let chunk = cart.chunks.iter().find(|chunk| {
+                    chunk.info.chunk_type() == section.chunk && chunk.info.bank() == bank
+                });
+                let Some(chunk) = chunk else { continue };
+                if chunk.data.len() > section.count * section.size {
+                    return Err(CartError::ChunkTooLarge {
+                        chunk: section.chunk,
+                        bank,
+                        size: chunk.data.len(),
+                        max: section.count * section.size,
+                    });
+                }
+
+                let mut lines = String::new();
+                for (index, item) in chunk.data.chunks(section.size).enumerate() {
+                    if item.iter().all(|&b| b == 0) {
+                        continue;
+                    }
+                    lines.push_str(&format!("-- {:03}:", index));
+                    for i in 0..section.size {
+                        let byte = item.get(i).copied().unwrap_or(0);
+                        let byte = if section.flip {
+                            byte.rotate_left(4)
+                        } else {
+                            byte
+                        };
+                        lines.push_str(&format!("{:02x}", byte));
+                    }
+                    lines.push('\n');
+                }
+                if lines.is_empty() {
+                    continue;
+                }
+                let tag = match bank {
+                    0 => section.tag.to_string(),
+                    bank => format!("{}{}", section.tag, bank),
+                };
+                blocks.push_str(&format!("\n-- <{}>\n{}-- </{}>\n", tag, lines, tag));
+            }
+        }
+        if !blocks.is_empty() {
+            out.push('\n');
+            out.push_str(&blocks);
+        }
+        Ok(out)
+    }
+}
+
+fn bad_text(line: usize, message: &'static str) -> CartError {
+    CartError::BadText { line, message }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source() -> String {
+        let line = |index: usize, data: &str, size: usize| {
+            format!(
+                "-- {:03}:{}{}\n",
+                index,
+                data,
+                "0".repeat(size * 2 - data.len())
+            )
+        };
+        format!(
+            "-- title: test\nfunction TIC() cls(1) end\n\n\
+             -- <TILES>\n{}-- </TILES>\n\n\
+             -- <MAP>\n{}-- </MAP>\n\n\
+             -- <MAP1>\n{}-- </MAP1>\n\n\
+             -- <PALETTE>\n{}-- </PALETTE>\n",
+            line(1, "00000000000ff00000f00f00", 32),
+            line(2, "1020", 240),
+            line(0, "10", 240),
+            line(0, "1a1c2c5d275db13e53ef7d57", 48),
+        )
+    }
+
+    fn find(cart: &Cartridge, kind: ChunkType, bank: u8) -> &[u8] {
+        let chunk = cart
+            .chunks
+            .iter()
+            .find(|c| c.info.chunk_type() == kind && c.info.bank() == bank);
+        &chunk.unwrap().data
+    }
+
+    #[test]
+    fn parse() {
+        let cart = Cartridge::from_text(&source()).unwrap();
+        assert_eq!(
+            find(&cart, ChunkType::Code, 0),
+            b"-- title: test\nfunction TIC() cls(1) end"
+        );
+        // tile 1, its second row has pixels 3 and 4 set, nibbles swapped
+        let tiles = find(&cart, ChunkType::Tiles, 0);
+        assert_eq!(tiles.len(), 32 + 11);
+        assert_eq!(&tiles[32 + 4..32 + 8], &[0x00, 0xF0, 0x0F, 0x00]);
+        let map = find(&cart, ChunkType::Map, 0);
+        assert_eq!(&map[480..], &[0x01, 0x02]);
+        assert_eq!(find(&cart, ChunkType::Map, 1), &[0x01]);
+        assert_eq!(
+            &find(&cart, ChunkType::Palette, 0)[..3],
+            &[0x1a, 0x1c, 0x2c]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let cart = Cartridge::from_text(&source()).unwrap();
+        assert_eq!(cart.to_text().unwrap(), source());
+
+        for code in ["", "x = 1", "x = 1\n", "x = 1\n\n"] {
+            let mut cart = Cartridge::from_text(&source()).unwrap();
+            cart.set_code(code).unwrap();
+            let text = cart.to_text().unwrap();
+            let binary: Vec<u8> = cart.try_into().unwrap();
+            let again: Vec<u8> = Cartridge::from_text(&text).unwrap().try_into().unwrap();
+            assert_eq!(again, binary, "{:?}", code);
+
+            // and with no assets at all
+            assert_eq!(Cartridge::from_text(code).unwrap().to_text().unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let bad = |text: &str| match Cartridge::from_text(text) {
+            Err(CartError::BadText { line, .. }) => line,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(bad("x = 1\n-- <TILES>\n-- 256:00\n-- </TILES>\n"), 3);
+        assert_eq!(bad("-- <WAVES>\n-- 000:0g\n-- </WAVES>\n"), 2);
+        assert_eq!(bad("-- <WAVES>\n-- 000:000\n-- </WAVES>\n"), 2);
+        assert_eq!(bad("-- <WAVES>\n-- 000:00\n"), 2);
+        // not a block this format knows
+        assert!(Cartridge::from_text("-- <TILES9>\n").is_ok());
+    }
+}
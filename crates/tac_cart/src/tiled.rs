@@ -0,0 +1,686 @@
+//! Maps as [Tiled](https://www.mapeditor.org) maps, in its `.tmx` or its JSON format.
+//!
+//! The map of each bank is a tile layer named `bank N`, drawn with a tileset of the
+//! tiles of the same bank, also named `bank N`. Tiled keeps tile id 0 for no tile,
+//! so a tileset's ids start at one more than the last tileset's last id. The flags of
+//! a tile are its `flags` property.
+
+use std::{fmt::Write, io::Read, path::Path};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde_json::{json, Value};
+
+use crate::{CartError, Cartridge, ChunkType, Map, Tiles, BANKS};
+
+/// The formats Tiled saves maps in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiledFormat {
+    Tmx,
+    Json,
+}
+
+impl TiledFormat {
+    /// The format of a file ending in `.tmx` or `.json`.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "tmx" => Some(TiledFormat::Tmx),
+            "json" => Some(TiledFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The bits of a tile id Tiled flips and rotates tiles with. Maps can't, so they're
+/// left out.
+const FLIPS: u32 = 0xF000_0000;
+const TILE_SIZE: usize = 8;
+
+/// A bank as a tileset and a layer.
+struct Bank {
+    bank: u8,
+    image: String,
+    /// The tiles with flags, and their flags.
+    flags: Vec<(usize, u8)>,
+    tiles: Vec<u32>,
+}
+
+struct Tileset {
+    name: String,
+    first_id: u32,
+    /// `None` for tilesets in files of their own, which aren't read.
+    flags: Option<Vec<(usize, i64)>>,
+}
+
+struct Layer {
+    name: String,
+    width: usize,
+    height: usize,
+    tiles: Vec<u32>,
+}
+
+impl Cartridge {
+    /// The maps of all banks that have one as a Tiled map, or an empty one for bank 0
+    /// if none do. The tileset of a bank is the image at `tileset(bank)`, see
+    /// [`Tiles::to_png`].
+    pub fn to_tiled(
+        &self,
+        format: TiledFormat,
+        tileset: impl Fn(u8) -> String,
+    ) -> Result<String, CartError> {
+        let mut maps = vec![];
+        for bank in 0..BANKS {
+            if self.chunk(ChunkType::Map, bank)?.is_some() {
+                maps.push(bank);
+            }
+        }
+        if maps.is_empty() {
+            maps.push(0);
+        }
+
+        let mut banks = vec![];
+        for (i, &bank) in maps.iter().enumerate() {
+            let first_id = (1 + i * Tiles::COUNT) as u32;
+            let map = self.map(bank)?;
+            let tiles = (0..Map::HEIGHT)
+                .flat_map(|y| (0..Map::WIDTH).map(move |x| first_id + map.tile(x, y) as u32))
+                .collect();
+            let flags = self.view(ChunkType::Flags, bank)?;
+            let flags = flags
+                .iter()
+                .take(Tiles::COUNT)
+                .enumerate()
+                .filter(|&(_, &flags)| flags != 0)
+                .map(|(tile, &flags)| (tile, flags))
+                .collect();
+            banks.push(Bank {
+                bank,
+                image: tileset(bank),
+                flags,
+                tiles,
+            });
+        }
+        Ok(match format {
+            TiledFormat::Tmx => to_tmx(&banks),
+            TiledFormat::Json => to_json(&banks),
+        })
+    }
+
+    /// Reads maps from a Tiled map. Tile layers named `bank N` replace the map of bank
+    /// N, and tilesets named so the flags of its tiles. If none are named so, the
+    /// first layer and tileset are those of bank 0. Layers can be smaller than the
+    /// map, but not larger.
+    pub fn import_tiled(&mut self, text: &str, format: TiledFormat) -> Result<(), CartError> {
+        let (tilesets, layers) = match format {
+            TiledFormat::Tmx => parse_tmx(text)?,
+            TiledFormat::Json => parse_json(text)?,
+        };
+
+        let mut first_ids: Vec<u32> = tilesets.iter().map(|tileset| tileset.first_id).collect();
+        first_ids.sort_unstable();
+        for (bank, layer) in by_bank(&layers, |layer| &layer.name) {
+            if layer.width > Map::WIDTH || layer.height > Map::HEIGHT {
+                return Err(tiled(format!(
+                    "layer {:?} is {}x{} tiles, maps are at most {}x{}",
+                    layer.name,
+                    layer.width,
+                    layer.height,
+                    Map::WIDTH,
+                    Map::HEIGHT
+                )));
+            }
+            if layer.tiles.len() != layer.width * layer.height {
+                return Err(tiled(format!("layer {:?} is not all there", layer.name)));
+            }
+            let mut map = vec![0; Map::WIDTH * Map::HEIGHT];
+            for (i, &id) in layer.tiles.iter().enumerate() {
+                let id = id & !FLIPS;
+                if id == 0 {
+                    continue;
+                }
+                let first_id = first_ids.iter().rev().find(|&&first| first <= id);
+                let tile = id - first_id.ok_or(tiled(format!("tile {} has no tileset", id)))?;
+                if tile as usize >= Tiles::COUNT {
+                    return Err(tiled(format!(
+                        "tile {} is past the {} tiles",
+                        tile,
+                        Tiles::COUNT
+                    )));
+                }
+                map[i / layer.width * Map::WIDTH + i % layer.width] = tile as u8;
+            }
+            self.set_chunk(ChunkType::Map, bank, map)?;
+        }
+
+        for (bank, tileset) in by_bank(&tilesets, |tileset| &tileset.name) {
+            let Some(tiles) = &tileset.flags else {
+                continue;
+            };
+            let mut flags = self.view(ChunkType::Flags, bank)?.to_vec();
+            flags.resize(ChunkType::Flags.capacity().unwrap(), 0);
+            flags[..Tiles::COUNT].fill(0);
+            for &(tile, value) in tiles {
+                let value = u8::try_from(value)
+                    .map_err(|_| tiled(format!("flags of tile {} are not a byte", tile)))?;
+                match flags.get_mut(tile).filter(|_| tile < Tiles::COUNT) {
+                    Some(flags) => *flags = value,
+                    None => return Err(tiled(format!("tile {} is past the tileset", tile))),
+                }
+            }
+            self.set_chunk(ChunkType::Flags, bank, flags)?;
+        }
+        Ok(())
+    }
+}
+
+fn tiled(message: String) -> CartError {
+    CartError::Tiled(message)
+}
+
+/// The items named `bank N` and their bank, or else the first as bank 0's.
+fn by_bank<T>(items: &[T], name: impl Fn(&T) -> &String) -> Vec<(u8, &T)> {
+    let named: Vec<(u8, &T)> = items
+        .iter()
+        .filter_map(|item| {
+            let bank = name(item).strip_prefix("bank ")?.trim().parse().ok()?;
+            Some((bank, item)).filter(|&(bank, _)| bank < BANKS)
+        })
+        .collect();
+    match named.is_empty() {
+        true => items.first().map(|item| (0, item)).into_iter().collect(),
+        false => named,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_tmx(banks: &[Bank]) -> String {
+    let mut tmx = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        tmx,
+        "<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" \
+         width=\"{}\" height=\"{}\" tilewidth=\"{size}\" tileheight=\"{size}\" infinite=\"0\" \
+         nextlayerid=\"{}\" nextobjectid=\"1\">",
+        Map::WIDTH,
+        Map::HEIGHT,
+        banks.len() + 1,
+        size = TILE_SIZE,
+    )
+    .unwrap();
+    for (i, bank) in banks.iter().enumerate() {
+        writeln!(
+            tmx,
+            " <tileset firstgid=\"{}\" name=\"bank {}\" tilewidth=\"{size}\" \
+             tileheight=\"{size}\" tilecount=\"{}\" columns=\"16\">\n  \
+             <image source=\"{}\" width=\"128\" height=\"128\"/>",
+            1 + i * Tiles::COUNT,
+            bank.bank,
+            Tiles::COUNT,
+            escape(&bank.image),
+            size = TILE_SIZE,
+        )
+        .unwrap();
+        for &(tile, flags) in &bank.flags {
+            writeln!(
+                tmx,
+                "  <tile id=\"{}\">\n   <properties>\n    \
+                 <property name=\"flags\" type=\"int\" value=\"{}\"/>\n   \
+                 </properties>\n  </tile>",
+                tile, flags
+            )
+            .unwrap();
+        }
+        tmx.push_str(" </tileset>\n");
+    }
+    for (i, bank) in banks.iter().enumerate() {
+        writeln!(
+            tmx,
+            " <layer id=\"{}\" name=\"bank {}\" width=\"{}\" height=\"{}\">\n  \
+             <data encoding=\"csv\">",
+            i + 1,
+            bank.bank,
+            Map::WIDTH,
+            Map::HEIGHT
+        )
+        .unwrap();
+        for (y, row) in bank.tiles.chunks(Map::WIDTH).enumerate() {
+            let row: Vec<String> = row.iter().map(u32::to_string).collect();
+            let end = if y + 1 < Map::HEIGHT { "," } else { "" };
+            writeln!(tmx, "{}{}", row.join(","), end).unwrap();
+        }
+        tmx.push_str("</data>\n </layer>\n");
+    }
+    tmx.push_str("</map>\n");
+    tmx
+}
+
+fn to_json(banks: &[Bank]) -> String {
+    let tilesets: Vec<Value> = banks
+        .iter()
+        .enumerate()
+        .map(|(i, bank)| {
+            let tiles: Vec<Value> = bank
+                .flags
+                .iter()
+                .map(|&(tile, flags)| {
+                    json!({
+                        "id": tile,
+                        "properties": [{ "name": "flags", "type": "int", "value": flags }],
+                    })
+                })
+                .collect();
+            json!({
+                "firstgid": 1 + i * Tiles::COUNT,
+                "name": format!("bank {}", bank.bank),
+                "image": bank.image,
+                "imagewidth": 128,
+                "imageheight": 128,
+                "tilewidth": TILE_SIZE,
+                "tileheight": TILE_SIZE,
+                "tilecount": Tiles::COUNT,
+                "columns": 16,
+                "margin": 0,
+                "spacing": 0,
+                "tiles": tiles,
+            })
+        })
+        .collect();
+    let layers: Vec<Value> = banks
+        .iter()
+        .enumerate()
+        .map(|(i, bank)| {
+            json!({
+                "id": i + 1,
+                "name": format!("bank {}", bank.bank),
+                "type": "tilelayer",
+                "x": 0,
+                "y": 0,
+                "width": Map::WIDTH,
+                "height": Map::HEIGHT,
+                "opacity": 1,
+                "visible": true,
+                "data": bank.tiles,
+            })
+        })
+        .collect();
+    let map = json!({
+        "type": "map",
+        "version": "1.10",
+        "orientation": "orthogonal",
+        "renderorder": "right-down",
+        "width": Map::WIDTH,
+        "height": Map::HEIGHT,
+        "tilewidth": TILE_SIZE,
+        "tileheight": TILE_SIZE,
+        "infinite": false,
+        "nextlayerid": banks.len() + 1,
+        "nextobjectid": 1,
+        "tilesets": tilesets,
+        "layers": layers,
+    });
+    serde_json::to_string_pretty(&map).unwrap()
+}
+
+fn parse_tmx(text: &str) -> Result<(Vec<Tileset>, Vec<Layer>), CartError> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| tiled(e.to_string()))?;
+    let map = doc.root_element();
+    if !map.has_tag_name("map") {
+        return Err(tiled("there is no <map>".to_string()));
+    }
+    if map.attribute("infinite") == Some("1") {
+        return Err(tiled("infinite maps are not supported".to_string()));
+    }
+    let number = |node: roxmltree::Node, name: &str| -> Result<i64, CartError> {
+        node.attribute(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or(tiled(format!(
+                "<{}> has no {}",
+                node.tag_name().name(),
+                name
+            )))
+    };
+    let name = |node: roxmltree::Node| node.attribute("name").unwrap_or("").to_string();
+
+    let mut tilesets = vec![];
+    for tileset in map.children().filter(|node| node.has_tag_name("tileset")) {
+        let flags = match tileset.attribute("source") {
+            Some(_) => None,
+            None => {
+                let mut flags = vec![];
+                for tile in tileset.children().filter(|node| node.has_tag_name("tile")) {
+                    let property = tile
+                        .descendants()
+                        .filter(|node| node.has_tag_name("property"))
+                        .find(|node| node.attribute("name") == Some("flags"));
+                    if let Some(property) = property {
+                        flags.push((number(tile, "id")? as usize, number(property, "value")?));
+                    }
+                }
+                Some(flags)
+            }
+        };
+        tilesets.push(Tileset {
+            name: name(tileset),
+            first_id: number(tileset, "firstgid")? as u32,
+            flags,
+        });
+    }
+
+    let mut layers = vec![];
+    for layer in map.descendants().filter(|node| node.has_tag_name("layer")) {
+        let data = layer
+            .children()
+            .find(|node| node.has_tag_name("data"))
+            .ok_or(tiled(format!("layer {:?} has no <data>", name(layer))))?;
+        let tiles = match data.attribute("encoding") {
+            Some(encoding) => decode(
+                data.text().unwrap_or(""),
+                encoding,
+                data.attribute("compression"),
+            )?,
+            None => data
+                .children()
+                .filter(|node| node.has_tag_name("tile"))
+                .map(|tile| tile.attribute("gid").map_or(Ok(0), |gid| gid.parse()))
+                .collect::<Result<_, _>>()
+                .map_err(|_| tiled(format!("layer {:?} has a bad tile", name(layer))))?,
+        };
+        layers.push(Layer {
+            name: name(layer),
+            width: number(layer, "width")? as usize,
+            height: number(layer, "height")? as usize,
+            tiles,
+        });
+    }
+    Ok((tilesets, layers))
+}
+
+fn parse_json(text: &str) -> Result<(Vec<Tileset>, Vec<Layer>), CartError> {
+    let map: Value = serde_json::from_str(text).map_err(|e| tiled(e.to_string()))?;
+    if map["infinite"] == true {
+        return Err(tiled("infinite maps are not supported".to_string()));
+    }
+    let number = |value: &Value, name: &str| -> Result<u64, CartError> {
+        value[name]
+            .as_u64()
+            .ok_or(tiled(format!("{} is missing", name)))
+    };
+    let name = |value: &Value| value["name"].as_str().unwrap_or("").to_string();
+
+    let mut tilesets = vec![];
+    for tileset in map["tilesets"].as_array().into_iter().flatten() {
+        let flags = match tileset.get("source") {
+            Some(_) => None,
+            None => {
+                let mut flags = vec![];
+                for tile in tileset["tiles"].as_array().into_iter().flatten() {
+                    let property = tile["properties"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .find(|property| property["name"] == "flags");
+                    if let Some(property) = property {
+                        let value = property["value"]
+                            .as_i64()
+                            .ok_or(tiled("flags are not a number".to_string()))?;
+                        flags.push((number(tile, "id")? as usize, value));
+                    }
+                }
+                Some(flags)
+            }
+        };
+        tilesets.push(Tileset {
+            name: name(tileset),
+            first_id: number(tileset, "firstgid")? as u32,
+            flags,
+        });
+    }
+
+    let mut layers = vec![];
+    let mut groups = vec![&map];
+    while let Some(group) = groups.pop() {
+        for layer in group["layers"].as_array().into_iter().flatten() {
+            match layer["type"].as_str() {
+                Some("group") => groups.push(layer),
+                Some("tilelayer") => {
+                    let tiles = match (&layer["data"], layer["encoding"].as_str()) {
+                        (Value::String(data), Some(encoding)) => {
+                            decode(data, encoding, layer["compression"].as_str())?
+                        }
+                        (Value::Array(tiles), _) => tiles
+                            .iter()
+                            .map(|tile| tile.as_u64().map(|tile| tile as u32))
+                            .collect::<Option<_>>()
+                            .ok_or(tiled(format!("layer {:?} has a bad tile", name(layer))))?,
+                        _ => return Err(tiled(format!("layer {:?} has no data", name(layer)))),
+                    };
+                    layers.push(Layer {
+                        name: name(layer),
+                        width: number(layer, "width")? as usize,
+                        height: number(layer, "height")? as usize,
+                        tiles,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok((tilesets, layers))
+}
+
+/// The tile ids of a layer's data, as comma separated numbers, or as base64 of their
+/// little endian bytes, which may be compressed.
+fn decode(data: &str, encoding: &str, compression: Option<&str>) -> Result<Vec<u32>, CartError> {
+    match encoding {
+        "csv" => data
+            .split(',')
+            .map(str::trim)
+            .filter(|tile| !tile.is_empty())
+            .map(|tile| {
+                tile.parse()
+                    .map_err(|_| tiled(format!("{:?} is not a tile", tile)))
+            })
+            .collect(),
+        "base64" => {
+            let bytes = base64(data)?;
+            let mut inflated = vec![];
+            match compression.unwrap_or("") {
+                "" => inflated = bytes,
+                "zlib" => {
+                    ZlibDecoder::new(&bytes[..]).read_to_end(&mut inflated)?;
+                }
+                "gzip" => {
+                    GzDecoder::new(&bytes[..]).read_to_end(&mut inflated)?;
+                }
+                other => return Err(tiled(format!("{} compression is not supported", other))),
+            }
+            Ok(inflated
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect())
+        }
+        other => Err(tiled(format!("{} encoding is not supported", other))),
+    }
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, CartError> {
+    let mut bytes = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return Err(tiled(format!("{:?} is not base64", c as char))),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn cart() -> Cartridge {
+        let mut flags = vec![0; 0x200];
+        flags[3] = 0x81;
+        flags[300] = 4; // a sprite's, which isn't in the tileset
+        let mut map = vec![0; Map::WIDTH * Map::HEIGHT];
+        map[Map::WIDTH * 135 + 239] = 255;
+        map[1] = 3;
+        Cartridge::builder()
+            .map(0, map)
+            .map(2, [7, 8])
+            .chunk(ChunkType::Flags, 0, flags)
+            .chunk(ChunkType::Flags, 2, [0, 1])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let cart = cart();
+        for format in [TiledFormat::Tmx, TiledFormat::Json] {
+            let text = cart
+                .to_tiled(format, |bank| format!("tiles{}.png", bank))
+                .unwrap();
+            let mut loaded = Cartridge::builder()
+                .chunk(
+                    ChunkType::Flags,
+                    0,
+                    [0; 300].into_iter().chain([4]).collect::<Vec<_>>(),
+                )
+                .map(1, [5])
+                .build()
+                .unwrap();
+            loaded.import_tiled(&text, format).unwrap();
+            let mut expected = cart.clone();
+            expected.set_chunk(ChunkType::Map, 1, vec![5]).unwrap();
+            assert_eq!(
+                loaded.to_bytes().unwrap(),
+                expected.to_bytes().unwrap(),
+                "{:?}",
+                format
+            );
+        }
+
+        let tmx = cart
+            .to_tiled(TiledFormat::Tmx, |_| "a&b.png".to_string())
+            .unwrap();
+        assert!(tmx.contains("<tileset firstgid=\"257\" name=\"bank 2\""));
+        assert!(tmx.contains("source=\"a&amp;b.png\""));
+        assert!(tmx.contains("<property name=\"flags\" type=\"int\" value=\"129\"/>"));
+        let json = Cartridge::builder().build().unwrap();
+        let json = json
+            .to_tiled(TiledFormat::Json, |_| "tiles.png".to_string())
+            .unwrap();
+        assert!(json.contains("\"name\": \"bank 0\""));
+    }
+
+    #[test]
+    fn encodings() {
+        // a 3x2 layer, flipped tiles and all, from a tileset that starts at 10
+        let tiles: [u32; 6] = [0, 10, 11 | 0x8000_0000, 12, 0, 265];
+        let bytes: Vec<u8> = tiles.iter().flat_map(|tile| tile.to_le_bytes()).collect();
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&bytes).unwrap();
+        let encode = |bytes: &[u8]| -> String {
+            const ALPHABET: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+            let mut text = String::new();
+            for chunk in bytes.chunks(3) {
+                let bits = chunk.iter().fold(0u32, |bits, &b| bits << 8 | b as u32)
+                    << (8 * (3 - chunk.len()));
+                for i in 0..=chunk.len() {
+                    text.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                }
+            }
+            text + &"=".repeat((3 - bytes.len() % 3) % 3)
+        };
+        let data = [
+            r#"<data encoding="csv">0,10,2147483659,
+12,0,265</data>"#
+                .to_string(),
+            format!(r#"<data encoding="base64">{}</data>"#, encode(&bytes)),
+            format!(
+                r#"<data encoding="base64" compression="zlib">{}</data>"#,
+                encode(&zlib.finish().unwrap())
+            ),
+            tiles.iter().fold("<data>".to_string(), |data, tile| {
+                data + &format!(r#"<tile gid="{}"/>"#, tile)
+            }) + "</data>",
+        ];
+        for data in data {
+            let tmx = format!(
+                r#"<map infinite="0"><tileset firstgid="10" source="tiles.tsx"/>
+                   <group><layer name="ground" width="3" height="2">{}</layer></group></map>"#,
+                data
+            );
+            let mut cart = Cartridge::builder().build().unwrap();
+            cart.import_tiled(&tmx, TiledFormat::Tmx).unwrap();
+            let map = cart.map(0).unwrap();
+            let row = |y| [0, 1, 2].map(|x| map.tile(x, y));
+            assert_eq!((row(0), row(1)), ([0, 0, 1], [2, 0, 255]), "{}", data);
+            assert_eq!(map.tile(3, 0), 0);
+        }
+
+        let json = r#"{"layers": [{"type": "group", "layers": [
+            {"type": "tilelayer", "name": "bank 3", "width": 2, "height": 1, "data": [2, 1]}
+        ]}], "tilesets": [{"firstgid": 1, "name": "bank 3",
+            "tiles": [{"id": 1, "properties": [{"name": "flags", "value": 6}]}]}]}"#;
+        let mut cart = Cartridge::builder().build().unwrap();
+        cart.import_tiled(json, TiledFormat::Json).unwrap();
+        assert_eq!(cart.map(3).unwrap().tile(0, 0), 1);
+        assert_eq!(cart.chunk(ChunkType::Flags, 3).unwrap().unwrap().data[1], 6);
+    }
+
+    #[test]
+    fn errors() {
+        let mut cart = Cartridge::builder().build().unwrap();
+        let layer = |width, height, tiles: &str| {
+            format!(
+                r#"<map><tileset firstgid="1" name="bank 0"/>
+                   <layer name="bank 0" width="{}" height="{}">
+                   <data encoding="csv">{}</data></layer></map>"#,
+                width, height, tiles
+            )
+        };
+        for tmx in [
+            layer(241, 1, &"1,".repeat(241)),
+            layer(2, 1, "1"),
+            layer(1, 1, "258"),
+            layer(1, 1, "x"),
+            "<map infinite=\"1\"/>".to_string(),
+            "<tileset/>".to_string(),
+            "<map".to_string(),
+        ] {
+            let result = cart.import_tiled(&tmx, TiledFormat::Tmx);
+            assert!(matches!(result, Err(CartError::Tiled(_))), "{}", tmx);
+        }
+        let result = cart.import_tiled("[", TiledFormat::Json);
+        assert!(matches!(result, Err(CartError::Tiled(_))));
+        assert_eq!(
+            TiledFormat::from_path("a/map.json"),
+            Some(TiledFormat::Json)
+        );
+        assert_eq!(TiledFormat::from_path("map.csv"), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Writing carts the way TIC-80 does, so that a cart it saved is saved back byte for
+//! byte.
+
+use std::path::Path;
+
+use binwrite::BinWrite;
+
+use crate::{CartError, Cartridge, Chunk, ChunkType, BANKS};
+
+/// The order assets are written in, bank by bank.
+const ASSETS: [ChunkType; 10] = [
+    ChunkType::Tiles,
+    ChunkType::Sprites,
+    ChunkType::Map,
+    ChunkType::Samples,
+    ChunkType::Waveform,
+    ChunkType::Music,
+    ChunkType::Patterns,
+    ChunkType::Palette,
+    ChunkType::Flags,
+    ChunkType::Screen,
+];
+
+impl Cartridge {
+    /// The chunks as they're written: the default chunk first, then the assets of
+    /// each bank, then the code from its start, and the binary data of each bank.
+    /// Assets are cut short after their last non-zero byte, empty chunks and padding
+    /// are left out, and of two chunks of the same type and bank only the last one is
+    /// kept. Fails if a chunk doesn't fit in its bank.
+    pub fn canonical(&self) -> Result<Vec<Chunk>, CartError> {
+        let mut chunks: Vec<Chunk> = vec![];
+        for chunk in self.chunks.iter().rev() {
+            let (kind, bank) = (chunk.info.chunk_type(), chunk.info.bank());
+            let duplicate = chunks
+                .iter()
+                .any(|c| c.info.chunk_type() == kind && c.info.bank() == bank);
+            if duplicate || kind == ChunkType::Dummy {
+                continue;
+            }
+
+            let mut data = chunk.data.clone();
+            if ASSETS.contains(&kind) {
+                let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                data.truncate(len);
+            }
+            if data.is_empty() && kind != ChunkType::Default {
+                continue;
+            }
+            crate::assets::validate(kind, bank, data.len())?;
+            chunks.push(Chunk::new(kind, bank, data)?);
+        }
+        chunks.sort_by_key(order);
+        Ok(chunks)
+    }
+
+    /// Writes the cart in its canonical form, see [`Cartridge::canonical`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CartError> {
+        let mut out = vec![];
+        for chunk in self.canonical()? {
+            chunk.write(&mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// Saves the cart to `path`, as source if it ends in `.lua`, in its cover if it
+    /// ends in `.png`, and as a binary cart otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CartError> {
+        let path = path.as_ref();
+        let bytes = match path.extension() {
+            Some(ext) if ext == "lua" => self.to_text()?.into_bytes(),
+            Some(ext) if ext == "png" => self.to_png(&self.cover())?,
+            _ => self.to_bytes()?,
+        };
+        Ok(std::fs::write(path, bytes)?)
+    }
+}
+
+#[allow(deprecated)]
+fn order(chunk: &Chunk) -> (u8, u8, u8) {
+    let (kind, bank) = (chunk.info.chunk_type(), chunk.info.bank());
+    match kind {
+        ChunkType::Default => (0, bank, 0),
+        // the code starts in the last bank
+        ChunkType::Code => (2, BANKS - 1 - bank, 0),
+        ChunkType::Binary => (3, bank, 0),
+        ChunkType::CodeZip | ChunkType::PatternsDep | ChunkType::CoverDep => (4, bank, kind as u8),
+        _ => {
+            let index = ASSETS.iter().position(|&asset| asset == kind);
+            (1, bank, index.unwrap_or(ASSETS.len()) as u8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const KINDS: [ChunkType; 13] = [
+        ChunkType::Tiles,
+        ChunkType::Sprites,
+        ChunkType::Map,
+        ChunkType::Code,
+        ChunkType::Flags,
+        ChunkType::Samples,
+        ChunkType::Waveform,
+        ChunkType::Palette,
+        ChunkType::Music,
+        ChunkType::Patterns,
+        ChunkType::Default,
+        ChunkType::Screen,
+        ChunkType::Binary,
+    ];
+
+    fn chunk() -> impl Strategy<Value = (ChunkType, u8, Vec<u8>)> {
+        (0..KINDS.len(), 0..8u8).prop_flat_map(|(kind, bank)| {
+            let kind = KINDS[kind];
+            let max = match kind {
+                ChunkType::Default => 0,
+                // enough to cross the size field's byte boundary, but quick
+                _ => kind.capacity().unwrap_or(0x1000).min(0x1000),
+            };
+            let data = prop::collection::vec(any::<u8>(), 0..=max);
+            (Just(kind), Just(bank), data)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(chunks in prop::collection::vec(chunk(), 0..12)) {
+            let mut builder = Cartridge::builder();
+            for (kind, bank, data) in chunks {
+                builder = builder.chunk(kind, bank, data);
+            }
+            let cart = builder.build().unwrap();
+
+            let bytes = cart.to_bytes().unwrap();
+            let loaded = Cartridge::try_from(bytes.as_slice()).unwrap();
+            prop_assert_eq!(&loaded.chunks, &cart.canonical().unwrap());
+            prop_assert_eq!(loaded.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let cart = Cartridge::builder()
+            .code("x=1")
+            .map(1, vec![7; 0x1234])
+            .tiles(1, [1, 0, 0])
+            .tiles(0, [2])
+            .chunk(ChunkType::Default, 0, [])
+            .build()
+            .unwrap();
+        let bytes = cart.to_bytes().unwrap();
+        let mut expected = vec![17, 0, 0, 0];
+        expected.extend([1, 1, 0, 0, 2]);
+        expected.extend([1 | 1 << 5, 1, 0, 0, 1]);
+        expected.extend([4 | 1 << 5, 0x34, 0x12, 0]);
+        expected.extend([7; 0x1234]);
+        expected.extend([5, 3, 0, 0]);
+        expected.extend(b"x=1");
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn full_code_bank() {
+        let code = "-".repeat(0x10000);
+        let cart = Cartridge::builder().code(&code).build().unwrap();
+        let bytes = cart.to_bytes().unwrap();
+        // a size of 0 stands for a whole bank
+        assert_eq!(bytes[..4], [5, 0, 0, 0]);
+        let loaded = Cartridge::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.code().unwrap(), code);
+
+        let too_long = Cartridge::builder()
+            .chunk(ChunkType::Code, 0, code + "-")
+            .build();
+        assert!(matches!(too_long, Err(CartError::ChunkTooLarge { .. })));
+    }
+
+    #[test]
+    fn save() {
+        let dir = std::env::temp_dir().join(format!("tac_cart_save_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cart = Cartridge::builder()
+            .code("x=1")
+            .tiles(0, [1])
+            .build()
+            .unwrap();
+        for name in ["cart.tic", "cart.lua", "cart.png"] {
+            cart.save(dir.join(name)).unwrap();
+            let loaded = Cartridge::load(dir.join(name)).unwrap();
+            assert_eq!(
+                loaded.to_bytes().unwrap(),
+                cart.to_bytes().unwrap(),
+                "{}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rgb::RGBA8;
+use tac_core::{ColorKey, PixBuf, Screen, TAC70};
+
+fn console() -> TAC70 {
+    let mut mem = vec![0u8; 0x18000];
+    // fill tiles and map with something that isn't all zeroes
+    for (i, b) in mem[0x4000..0x8000].iter_mut().enumerate() {
+        *b = (i * 7 % 256) as u8;
+    }
+    for (i, b) in mem[0x8000..0x8000 + 240 * 136].iter_mut().enumerate() {
+        *b = (i % 256) as u8;
+    }
+    TAC70::new(&mem, String::new())
+}
+
+/// Per-pixel reference implementations, i.e. how everything was drawn before
+/// the span and pair paths existed.
+fn naive_clear<P: PixBuf>(buf: &mut P, pix: u8) {
+    for i in 0..P::WIDTH {
+        for j in 0..P::HEIGHT {
+            buf.set_pix(i as i32, j as i32, pix);
+        }
+    }
+}
+
+fn naive_blit<P: PixBuf, O: PixBuf>(buf: &mut P, x: i32, y: i32, spr: &O, alpha: ColorKey) {
+    for i in 0..O::WIDTH as i32 {
+        for j in 0..O::HEIGHT as i32 {
+            let pix = spr.get_pix(i, j);
+            if !alpha.contains(pix) {
+                buf.set_pix(x + i, y + j, pix);
+            }
+        }
+    }
+}
+
+fn draw_map(tac: &TAC70, naive: bool) {
+    let mut screen = tac.screen();
+    for j in 0..17 {
+        for i in 0..30 {
+            let spr = tac.sprite(tac.map().get(i, j).unwrap() as u16).unwrap();
+            if naive {
+                naive_blit(&mut screen, i * 8, j * 8, &spr, ColorKey::NONE);
+            } else {
+                screen.blit(i * 8, j * 8, &spr, ColorKey::NONE, false, false, 1);
+            }
+        }
+    }
+}
+
+fn draw_sprites(tac: &TAC70, naive: bool) {
+    let mut screen = tac.screen();
+    for n in 0..64 {
+        let spr = tac.sprite(256 + n as u16).unwrap();
+        let (x, y) = ((n * 37) % 240 - 4, (n * 53) % 136 - 4);
+        if naive {
+            naive_blit(&mut screen, x, y, &spr, ColorKey::single(0));
+        } else {
+            screen.blit(x, y, &spr, ColorKey::single(0), false, false, 1);
+        }
+    }
+}
+
+fn bench_cls(c: &mut Criterion) {
+    let tac = console();
+    let mut group = c.benchmark_group("cls");
+    group.bench_function("per_pixel", |b| {
+        b.iter(|| naive_clear(&mut tac.screen(), black_box(3)))
+    });
+    group.bench_function("fill", |b| b.iter(|| tac.screen().clear(black_box(3))));
+    group.finish();
+}
+
+fn bench_rect(c: &mut Criterion) {
+    let tac = console();
+    let mut group = c.benchmark_group("rect");
+    group.bench_function("per_pixel", |b| {
+        b.iter(|| {
+            let mut screen = tac.screen();
+            for i in 0..black_box(201) {
+                for j in 0..black_box(99) {
+                    screen.set_pix(17 + i, 13 + j, 5);
+                }
+            }
+        })
+    });
+    group.bench_function("fill", |b| {
+        b.iter(|| tac.screen().rect(17, 13, black_box(201), black_box(99), 5))
+    });
+    group.finish();
+}
+
+fn bench_frame(c: &mut Criterion) {
+    let tac = console();
+    let mut group = c.benchmark_group("frame");
+    group.bench_function("per_pixel", |b| {
+        b.iter(|| {
+            naive_clear(&mut tac.screen(), 0);
+            draw_map(&tac, true);
+            draw_sprites(&tac, true);
+        })
+    });
+    group.bench_function("fast", |b| {
+        b.iter(|| {
+            tac.screen().clear(0);
+            draw_map(&tac, false);
+            draw_sprites(&tac, false);
+        })
+    });
+    group.finish();
+}
+
+fn bench_rgba(c: &mut Criterion) {
+    let tac = console();
+    draw_map(&tac, false);
+    let mut group = c.benchmark_group("rgba");
+    group.bench_function("per_pixel", |b| {
+        b.iter(|| {
+            let (screen, palette) = (tac.screen(), tac.palette());
+            let mut out = Vec::with_capacity(Screen::WIDTH * Screen::HEIGHT);
+            for j in 0..Screen::HEIGHT as i32 {
+                for i in 0..Screen::WIDTH as i32 {
+                    out.push(palette.get(screen.get_pix(i, j)).unwrap().alpha(255));
+                }
+            }
+            out
+        })
+    });
+    let mut out = vec![RGBA8::default(); Screen::WIDTH * Screen::HEIGHT];
+    group.bench_function("render_into", |b| {
+        b.iter(|| tac.screen().render_into(&tac.palette(), &mut out))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_cls, bench_rect, bench_frame, bench_rgba);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use rgb::RGB8;
+
+use crate::{CaptureError, Palette, PixBuf, Screen};
+
+/// A snapshot of the screen as palette indices, one byte per pixel, along with the
+/// palette it was drawn with. This is what screenshots and recordings are made of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedFrame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    pub palette: [RGB8; 16],
+}
+
+impl IndexedFrame {
+    pub fn capture(screen: &Screen, palette: &Palette) -> Self {
+        let mut pixels = Vec::with_capacity(Screen::WIDTH * Screen::HEIGHT);
+        for i in 0..Screen::WIDTH * Screen::HEIGHT / 2 {
+            let pair = screen.get_buf(i);
+            pixels.extend([pair & 0xF, pair >> 4]);
+        }
+        Self {
+            width: Screen::WIDTH,
+            height: Screen::HEIGHT,
+            pixels,
+            palette: std::array::from_fn(|i| palette.get(i as u8).unwrap()),
+        }
+    }
+
+    /// Nearest-neighbour upscale by an integer factor.
+    pub fn scaled(&self, scale: usize) -> Self {
+        let scale = scale.max(1);
+        let mut pixels = Vec::with_capacity(self.pixels.len() * scale * scale);
+        for row in self.pixels.chunks_exact(self.width) {
+            let row: Vec<u8> = row.iter().flat_map(|&p| [p].repeat(scale)).collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&row);
+            }
+        }
+        Self {
+            width: self.width * scale,
+            height: self.height * scale,
+            pixels,
+            palette: self.palette,
+        }
+    }
+
+    /// The palette as packed RGB triplets.
+    pub fn palette_bytes(&self) -> Vec<u8> {
+        rgb_bytes(&self.palette)
+    }
+
+    /// Writes the frame as an indexed PNG.
+    pub fn write_png(&self, w: impl Write) -> Result<(), CaptureError> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(self.palette_bytes());
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+/// Writes frames captured at 60 Hz into an endlessly looping GIF. The file is
+/// complete once the recorder is dropped.
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: usize,
+    palette: [RGB8; 16],
+    frames: u32,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// `palette` is the palette most frames are expected to use, frames drawn with
+    /// another one carry their own.
+    pub fn new(w: W, scale: usize, palette: [RGB8; 16]) -> Result<Self, CaptureError> {
+        let scale = scale.max(1);
+        let mut encoder = gif::Encoder::new(
+            w,
+            (Screen::WIDTH * scale) as u16,
+            (Screen::HEIGHT * scale) as u16,
+            &rgb_bytes(&palette),
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(Self {
+            encoder,
+            scale,
+            palette,
+            frames: 0,
+        })
+    }
+
+    pub fn push(&mut self, frame: &IndexedFrame) -> Result<(), CaptureError> {
+        let scaled = frame.scaled(self.scale);
+        let (width, height) = (scaled.width as u16, scaled.height as u16);
+        let mut out = if frame.palette == self.palette {
+            gif::Frame::from_indexed_pixels(width, height, scaled.pixels, None)
+        } else {
+            let palette = scaled.palette_bytes();
+            gif::Frame::from_palette_pixels(width, height, scaled.pixels, palette, None)
+        };
+        // GIF delays are in hundredths of a second, so alternate 2 and 1 to
+        // average out to 60 frames per second
+        let n = self.frames as u64;
+        out.delay = ((n + 1) * 100 / 60 - n * 100 / 60) as u16;
+        self.encoder.write_frame(&out)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames recorded so far.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+fn rgb_bytes(palette: &[RGB8; 16]) -> Vec<u8> {
+    palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TAC70;
+
+    fn console() -> TAC70 {
+        let mut mem = vec![0u8; 0x18000];
+        for (i, c) in mem[0x3FC0..0x3FF0].chunks_exact_mut(3).enumerate() {
+            c.copy_from_slice(&[i as u8 * 16, 0, 255 - i as u8 * 16]);
+        }
+        TAC70::new(&mem, String::new())
+    }
+
+    #[test]
+    fn capture_and_scale() {
+        let tac = console();
+        tac.screen().set_pix(0, 0, 5);
+        tac.screen().set_pix(1, 0, 9);
+        tac.screen().set_pix(239, 135, 15);
+
+        let frame = IndexedFrame::capture(&tac.screen(), &tac.palette());
+        assert_eq!(&frame.pixels[..3], [5, 9, 0]);
+        assert_eq!(frame.pixels[240 * 136 - 1], 15);
+        assert_eq!(frame.palette[5], RGB8::new(80, 0, 175));
+
+        let scaled = frame.scaled(2);
+        assert_eq!((scaled.width, scaled.height), (480, 272));
+        assert_eq!(&scaled.pixels[..5], [5, 5, 9, 9, 0]);
+        assert_eq!(&scaled.pixels[480..485], [5, 5, 9, 9, 0]);
+    }
+
+    #[test]
+    fn png_and_gif_headers() {
+        let tac = console();
+        let frame = IndexedFrame::capture(&tac.screen(), &tac.palette());
+
+        let mut png = vec![];
+        frame.write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let mut gif = vec![];
+        let mut rec = GifRecorder::new(&mut gif, 1, frame.palette).unwrap();
+        for _ in 0..3 {
+            rec.push(&frame).unwrap();
+        }
+        assert_eq!(rec.frames(), 3);
+        drop(rec);
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(gif.last(), Some(&0x3B)); // trailer
+    }
+}
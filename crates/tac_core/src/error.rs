@@ -0,0 +1,27 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("PNG encoding failed: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("GIF encoding failed: {0}")]
+    Gif(#[from] gif::EncodingError),
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not a replay")]
+    NotAReplay,
+    #[error("unsupported replay version {0}")]
+    Version(u8),
+    #[error("corrupt replay: {0}")]
+    Corrupt(&'static str),
+    #[error("replays hold at most {} frames", u32::MAX)]
+    TooLong,
+}
@@ -1,6 +1,20 @@
 use rgb::{RGB8, RGBA8};
-use tac_cart::Cartridge;
-use std::cell::Cell;
+use std::{cell::Cell, ops::Range, path::Path};
+use tac_cart::{CartError, Cartridge, ChunkType};
+
+mod capture;
+mod error;
+mod map;
+mod replay;
+mod sprite;
+mod tri;
+
+pub use capture::{GifRecorder, IndexedFrame};
+pub use error::{CaptureError, ReplayError};
+pub use map::{MapRegion, MapTile};
+pub use replay::{InputState, Replay};
+pub use sprite::SpriteView;
+pub use tri::{TexVertex, Texture};
 
 #[derive(Clone)]
 pub struct Sprite([Cell<u8>; 8 * 4]);
@@ -17,6 +31,10 @@ pub trait PixBuf {
     const HEIGHT: usize;
     const BPP: usize;
     const MASK: u8 = pix_mask(Self::BPP);
+    /// Whether `get_buf` holds the pixels exactly as `get_pix` returns them. Views that
+    /// remap pixels (rotation, recoloring...) must set this to false to opt out of the
+    /// byte-level fast paths.
+    const PACKED: bool = true;
 
     // fn buf(&self) -> &[Cell<u8>];
     // fn buf_mut(&mut self) -> &mut [Cell<u8>];
@@ -37,20 +55,55 @@ pub trait PixBuf {
             return;
         }
         let (x, y) = (x as usize, y as usize);
-        let bit = (x + y * Self::WIDTH) * 4;
+        let bit = (x + y * Self::WIDTH) * Self::BPP;
         let off = bit % 8;
         let mut byte = self.get_buf(bit / 8);
         byte &= !(Self::MASK << off);
-        byte |= pix << off;
+        byte |= (pix & Self::MASK) << off;
         self.set_buf(bit / 8, byte);
     }
 
+    /// Fills `w` pixels of row `y` starting at `x`, clipped to the buffer.
+    /// 4bpp buffers write whole bytes (two pixels at a time) between the edges.
+    fn fill_span(&mut self, x: i32, y: i32, w: u32, pix: u8) {
+        if y < 0 || y >= Self::HEIGHT as i32 {
+            return;
+        }
+        let x0 = x.max(0);
+        let x1 = (x as i64 + w as i64).min(Self::WIDTH as i64) as i32;
+        if x0 >= x1 {
+            return;
+        }
+        if Self::BPP != 4 || !Self::PACKED {
+            for i in x0..x1 {
+                self.set_pix(i, y, pix);
+            }
+            return;
+        }
+
+        let (mut x0, mut x1) = (x0, x1);
+        if x0 % 2 != 0 {
+            self.set_pix(x0, y, pix);
+            x0 += 1;
+        }
+        if x1 % 2 != 0 && x1 > x0 {
+            x1 -= 1;
+            self.set_pix(x1, y, pix);
+        }
+        let pair = (pix & 0xF) | (pix << 4);
+        let row = y as usize * Self::WIDTH / 2;
+        for i in (x0 as usize / 2)..(x1 as usize / 2) {
+            self.set_buf(row + i, pair);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn blit<O: PixBuf>(
         &mut self,
         x: i32,
         y: i32,
         spr: &O,
-        alpha: Option<u8>,
+        alpha: ColorKey,
         hflip: bool,
         vflip: bool,
         scale: u32,
@@ -58,6 +111,10 @@ pub trait PixBuf {
         if scale == 0 {
             return;
         }
+        if scale == 1 && !hflip && !vflip {
+            self.blit_fast(x, y, spr, alpha);
+            return;
+        }
         let scale = scale as usize;
         for i in 0..O::WIDTH {
             for j in 0..O::HEIGHT {
@@ -66,7 +123,7 @@ pub trait PixBuf {
                     if vflip { O::HEIGHT - j - 1 } else { j } as i32,
                 );
 
-                if Some(spr_pix) == alpha {
+                if alpha.contains(spr_pix) {
                     continue;
                 }
 
@@ -83,11 +140,115 @@ pub trait PixBuf {
         }
     }
 
+    /// Unscaled, unflipped blit. When both buffers are packed 4bpp, source pixels are
+    /// read a byte (pixel pair) at a time and copied as whole bytes whenever the pair
+    /// lands byte-aligned, fully on screen and has no transparent pixel.
+    fn blit_fast<O: PixBuf>(&mut self, x: i32, y: i32, spr: &O, alpha: ColorKey) {
+        let (w, h) = (O::WIDTH as i32, O::HEIGHT as i32);
+        if x >= Self::WIDTH as i32 || y >= Self::HEIGHT as i32 || x + w <= 0 || y + h <= 0 {
+            return;
+        }
+        let j0 = (-y).max(0);
+        let j1 = h.min(Self::HEIGHT as i32 - y);
+
+        if !(O::PACKED && O::BPP == 4 && Self::PACKED && Self::BPP == 4 && O::WIDTH % 2 == 0) {
+            let i0 = (-x).max(0);
+            let i1 = w.min(Self::WIDTH as i32 - x);
+            for j in j0..j1 {
+                for i in i0..i1 {
+                    let pix = spr.get_pix(i, j);
+                    if !alpha.contains(pix) {
+                        self.set_pix(x + i, y + j, pix);
+                    }
+                }
+            }
+            return;
+        }
+
+        let aligned = x % 2 == 0;
+        for j in j0..j1 {
+            let src_row = j as usize * O::WIDTH / 2;
+            let dst_y = y + j;
+            for i in (0..w).step_by(2) {
+                let dx = x + i;
+                if dx + 1 < 0 || dx >= Self::WIDTH as i32 {
+                    continue;
+                }
+                let pair = spr.get_buf(src_row + i as usize / 2);
+                let (p0, p1) = (pair & 0xF, pair >> 4);
+                if aligned
+                    && dx >= 0
+                    && dx + 1 < Self::WIDTH as i32
+                    && !alpha.contains(p0)
+                    && !alpha.contains(p1)
+                {
+                    self.set_buf((dx as usize + dst_y as usize * Self::WIDTH) / 2, pair);
+                    continue;
+                }
+                if !alpha.contains(p0) {
+                    self.set_pix(dx, dst_y, p0);
+                }
+                if !alpha.contains(p1) {
+                    self.set_pix(dx + 1, dst_y, p1);
+                }
+            }
+        }
+    }
+
+    /// Draws a (possibly multi-tile, flipped or rotated) sprite view with every pixel
+    /// scaled up to a `scale`×`scale` square.
+    fn draw_sprite(&mut self, x: i32, y: i32, spr: &SpriteView, alpha: ColorKey, scale: u32) {
+        if scale == 0 {
+            return;
+        }
+        if scale == 1 && spr.is_plain() {
+            let (cols, rows) = spr.tiles();
+            for j in 0..rows {
+                for i in 0..cols {
+                    let (tx, ty) = (
+                        x + (i as usize * Sprite::WIDTH) as i32,
+                        y + (j as usize * Sprite::HEIGHT) as i32,
+                    );
+                    self.blit_fast(tx, ty, &spr.tile(i, j), alpha);
+                }
+            }
+            return;
+        }
+
+        let s = scale as i32;
+        for j in 0..spr.height() as i32 {
+            let py = y + j * s;
+            if py + s <= 0 || py >= Self::HEIGHT as i32 {
+                continue;
+            }
+            for i in 0..spr.width() as i32 {
+                let px = x + i * s;
+                if px + s <= 0 || px >= Self::WIDTH as i32 {
+                    continue;
+                }
+                let pix = spr.get_pix(i, j);
+                if alpha.contains(pix) {
+                    continue;
+                }
+                if scale == 1 {
+                    self.set_pix(px, py, pix);
+                } else {
+                    self.rect(px, py, scale, scale, pix);
+                }
+            }
+        }
+    }
+
     fn clear(&mut self, pix: u8) {
-        for i in 0..Self::WIDTH {
+        if Self::BPP != 4 || !Self::PACKED {
             for j in 0..Self::HEIGHT {
-                self.set_pix(i as i32, j as i32, pix);
+                self.fill_span(0, j as i32, Self::WIDTH as u32, pix);
             }
+            return;
+        }
+        let pair = (pix & 0xF) | (pix << 4);
+        for i in 0..Self::WIDTH * Self::HEIGHT / 2 {
+            self.set_buf(i, pair);
         }
     }
 
@@ -95,10 +256,10 @@ pub trait PixBuf {
         if w == 0 || h == 0 {
             return;
         }
-        for i in 0..w as i32 {
-            for j in 0..h as i32 {
-                self.set_pix(x + i, y + j, pix);
-            }
+        let y0 = y.max(0);
+        let y1 = (y as i64 + h as i64).min(Self::HEIGHT as i64) as i32;
+        for j in y0..y1 {
+            self.fill_span(x, j, w, pix);
         }
     }
 
@@ -106,12 +267,10 @@ pub trait PixBuf {
         if w == 0 || h == 0 {
             return;
         }
+        self.fill_span(x, y, w, pix);
+        self.fill_span(x, y + h as i32 - 1, w, pix);
         let (w, h) = (w as i32, h as i32);
-        for i in 0..w {
-            self.set_pix(x + i, y, pix);
-            self.set_pix(x + i, y + h - 1, pix);
-        }
-        for j in 0..h - 1 {
+        for j in 1..h - 1 {
             self.set_pix(x, y + j, pix);
             self.set_pix(x + w - 1, y + j, pix);
         }
@@ -149,8 +308,10 @@ pub trait PixBuf {
         self.set_pix(a.0 as i32, a.1 as i32, pix);
     }
 
-    fn tri(&mut self, mut a: (f64, f64), mut b: (f64, f64), mut c: (f64, f64), pix: u8) {
-        todo!();
+    fn tri(&mut self, a: (f64, f64), b: (f64, f64), c: (f64, f64), pix: u8) {
+        for (y, xs) in tri::spans([a, b, c], Self::HEIGHT) {
+            self.fill_span(xs.start, y, xs.len() as u32, pix);
+        }
     }
 
     fn trib(&mut self, a: (f64, f64), b: (f64, f64), c: (f64, f64), pix: u8) {
@@ -161,16 +322,30 @@ pub trait PixBuf {
 
     ///
     /// Returns vec of size WIDTH*HEIGHT
-    fn to_rgba<'p>(&self, palette: &Palette<'p>) -> Vec<RGBA8> {
-        let mut out = Vec::with_capacity(Self::WIDTH * Self::HEIGHT);
-        for j in 0..Self::HEIGHT {
-            for i in 0..Self::WIDTH {
-                let pix = self.get_pix(i as i32, j as i32);
-                out.push(palette.get(pix).unwrap().alpha(255));
+    fn to_rgba(&self, palette: &Palette) -> Vec<RGBA8> {
+        let mut out = vec![RGBA8::default(); Self::WIDTH * Self::HEIGHT];
+        self.render_into(palette, &mut out);
+        out
+    }
+
+    /// Converts the buffer to RGBA into `out`, which must hold WIDTH*HEIGHT pixels.
+    /// Packed 4bpp buffers are expanded a byte (two pixels) at a time.
+    fn render_into(&self, palette: &Palette, out: &mut [RGBA8]) {
+        assert_eq!(out.len(), Self::WIDTH * Self::HEIGHT);
+        let lut = palette.rgba();
+        if Self::BPP != 4 || !Self::PACKED {
+            for j in 0..Self::HEIGHT {
+                for i in 0..Self::WIDTH {
+                    out[i + j * Self::WIDTH] = lut[self.get_pix(i as i32, j as i32) as usize];
+                }
             }
+            return;
         }
 
-        out
+        let pairs: [[RGBA8; 2]; 256] = std::array::from_fn(|b| [lut[b & 0xF], lut[b >> 4]]);
+        for (i, px) in out.chunks_exact_mut(2).enumerate() {
+            px.copy_from_slice(&pairs[self.get_buf(i) as usize]);
+        }
     }
 }
 
@@ -178,6 +353,37 @@ const fn pix_mask(bpp: usize) -> u8 {
     u8::MAX >> (8_u8 - bpp as u8)
 }
 
+/// Set of palette colors left out when blitting, one bit per color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColorKey(pub u16);
+
+impl ColorKey {
+    pub const NONE: ColorKey = ColorKey(0);
+
+    pub const fn single(color: u8) -> Self {
+        Self::NONE.with(color)
+    }
+
+    /// Adds `color` to the set. Colors outside of the palette are ignored.
+    pub const fn with(self, color: u8) -> Self {
+        if color < 16 {
+            ColorKey(self.0 | 1 << color)
+        } else {
+            self
+        }
+    }
+
+    pub const fn contains(&self, color: u8) -> bool {
+        color < 16 && self.0 & (1 << color) != 0
+    }
+}
+
+impl FromIterator<u8> for ColorKey {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::NONE, Self::with)
+    }
+}
+
 #[derive(Clone)]
 pub struct TAC70 {
     pub mem: [Cell<u8>; 0x18000],
@@ -188,13 +394,23 @@ pub struct TAC70 {
 impl TAC70 {
     const CHAR_COUNT: usize = 127 * 2;
 
-    pub fn new(mem: &[u8], code: String) -> Self {
-        let mut mem = mem.to_owned();
-        mem[0x14604..0x14604 + 8 * Self::CHAR_COUNT].copy_from_slice(include_bytes!("font.bin")); // Load font to memory
+    /// The parts of RAM a cart's assets are loaded into: palette, tiles, sprites, map,
+    /// waveforms, samples, patterns, music and sprite flags.
+    pub const ASSETS: [Range<usize>; 4] = [
+        0x3FC0..0x3FF0,
+        0x4000..0xFF80,
+        0xFFE4..0x13FFC,
+        0x14404..0x14604,
+    ];
 
+    /// TIC-80's default palette, SWEETIE-16.
+    pub const SYSTEM_PALETTE: [u8; 48] = tac_cart::DEFAULT_PALETTE;
+
+    pub fn new(mem: &[u8], code: String) -> Self {
         let mem = mem
-            .into_iter()
-            .map(|b| Cell::new(b))
+            .iter()
+            .copied()
+            .map(Cell::new)
             .collect::<Vec<Cell<u8>>>();
 
         let mut tac = Self {
@@ -202,18 +418,36 @@ impl TAC70 {
             code,
             char_cache: [(0, 0); Self::CHAR_COUNT],
         };
-        tac.update_font_data();
+        tac.load_system_font();
 
         tac
     }
 
-    pub fn palette(&self) -> Palette {
+    /// Puts the built-in font back in place of whatever the cart wrote over it.
+    pub fn load_system_font(&mut self) {
+        let font = include_bytes!("font.bin");
+        for (cell, &byte) in self.mem[0x14604..0x14604 + font.len()].iter().zip(font) {
+            cell.set(byte);
+        }
+        self.update_font_data();
+    }
+
+    pub fn load_system_palette(&mut self) {
+        for (cell, &byte) in self.mem[0x3FC0..0x3FC0 + 48]
+            .iter()
+            .zip(&Self::SYSTEM_PALETTE)
+        {
+            cell.set(byte);
+        }
+    }
+
+    pub fn palette(&self) -> Palette<'_> {
         Palette {
             mem: &self.mem[0x3FC0..0x3FF0],
         }
     }
 
-    pub fn screen(&self) -> Screen {
+    pub fn screen(&self) -> Screen<'_> {
         let palette = self.palette().to_owned();
         Screen {
             pixels: &self.mem[0..Screen::PX_BUFFER_SIZE],
@@ -221,7 +455,7 @@ impl TAC70 {
         }
     }
 
-    pub fn map(&self) -> Map {
+    pub fn map(&self) -> Map<'_> {
         Map {
             tiles: &self.mem[0x8000..0x8000 + Map::WIDTH * Map::HEIGHT],
         }
@@ -232,14 +466,17 @@ impl TAC70 {
             return None;
         }
         let off = id as usize * 8 * 4;
-        let mut spr = vec![Cell::new(0); 32];
-        for i in 0..32 {
-            spr[i] = self.mem[0x4000 + off + i].clone();
-        }
-        Some(Sprite(spr.try_into().unwrap()))
+        Some(Sprite(std::array::from_fn(|i| {
+            self.mem[0x4000 + off + i].clone()
+        })))
     }
 
-    pub fn gamepads(&self) -> Gamepads {
+    /// A `w`×`h` block of tiles starting at sprite `id`, see [`SpriteView`].
+    pub fn sprite_view(&self, id: u16, w: u32, h: u32) -> SpriteView<'_> {
+        SpriteView::new(&self.mem[0x4000..0x8000], id, w, h)
+    }
+
+    pub fn gamepads(&self) -> Gamepads<'_> {
         Gamepads {
             mem: &self.mem[0x0FF80..0x0FF80 + 4],
         }
@@ -247,13 +484,10 @@ impl TAC70 {
 
     pub fn char(&self, c: char, alt: bool) -> Option<FontChar> {
         let c = c as usize;
-        if (0..128).contains(&c) {
-            let ccode = c as usize + if alt { 127 } else { 0 };
+        if (0..127).contains(&c) {
+            let ccode = c + if alt { 127 } else { 0 };
             let off = ccode * 8;
-            let mut font = vec![Cell::new(0); 8];
-            for i in 0..8 {
-                font[i] = self.mem[0x14604 + off + i].clone();
-            }
+            let font = self.mem[0x14604 + off..0x14604 + off + 8].to_vec();
             Some(FontChar {
                 mem: font.try_into().unwrap(),
                 width: self.char_cache[ccode].0,
@@ -264,12 +498,58 @@ impl TAC70 {
         }
     }
 
-    pub fn mouse(&self) -> Mouse {
+    /// Draws `text` with the font in RAM, like `print` in TIC-80, and returns its width.
+    /// Characters the font doesn't have are skipped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn print(
+        &self,
+        text: &str,
+        x: i32,
+        y: i32,
+        pix: u8,
+        fixed: bool,
+        scale: u32,
+        smallfont: bool,
+    ) -> i32 {
+        let mut cursor = 0;
+        let fixedw = if smallfont { 3 } else { 5 };
+        for c in text.chars() {
+            let Some(fchar) = self.char(c, smallfont) else {
+                continue;
+            };
+            let advance = if !fixed { fchar.width as i32 } else { fixedw } + 1;
+            self.screen().blit(
+                x + (cursor - if !fixed { fchar.padx } else { 0 }) * scale as i32,
+                y,
+                &Colorized(pix, fchar),
+                ColorKey::single(0),
+                false,
+                false,
+                scale,
+            );
+            cursor += advance;
+        }
+        cursor
+    }
+
+    pub fn mouse(&self) -> Mouse<'_> {
         Mouse {
             mem: self.mem[0x0FF84..0x0FF84 + 4].try_into().unwrap(),
         }
     }
 
+    pub fn keyboard(&self) -> Keyboard<'_> {
+        Keyboard {
+            mem: self.mem[0x0FF88..0x0FF88 + 4].try_into().unwrap(),
+        }
+    }
+
+    pub fn pmem(&self) -> Pmem<'_> {
+        Pmem {
+            mem: &self.mem[0x14004..0x14004 + Pmem::SIZE * 4],
+        }
+    }
+
     pub fn set_sprite(&mut self, id: u16, spr: Sprite) {
         assert!(id < 512);
         let off = id as usize * 8 * 4;
@@ -280,12 +560,12 @@ impl TAC70 {
         for c in 0..127 {
             for alt in [false, true] {
                 let ccode = c as usize + if !alt { 0 } else { 127 };
-                if c == ' ' as u8 {
+                if c == b' ' {
                     self.char_cache[ccode] = (if alt { 1 } else { 3 }, 0);
                     continue;
                 }
 
-                let fchar = self.char(c as u8 as char, alt).unwrap();
+                let fchar = self.char(c as char, alt).unwrap();
 
                 let mut padr = 8;
                 'col1: for i in (0..FontChar::WIDTH as i32).rev() {
@@ -324,7 +604,14 @@ pub struct Mouse<'a> {
     mem: &'a [Cell<u8>; 4],
 }
 
+/// Up to four held keys, as TIC-80 key codes (1 = A ... 26 = Z, 27 = 0 ... 36 = 9,
+/// 48 = space, 58..=61 = arrows...). 0 means no key.
+pub struct Keyboard<'a> {
+    mem: &'a [Cell<u8>; 4],
+}
+
 impl Mouse<'_> {
+    #[allow(clippy::too_many_arguments)]
     pub fn set(&self, mx: u8, my: u8, ml: bool, mm: bool, mr: bool, scrollx: i8, scrolly: i8) {
         assert!(scrollx > -33 && scrollx < 32);
         assert!(scrolly > -33 && scrolly < 32);
@@ -372,8 +659,54 @@ impl Mouse<'_> {
     }
 }
 
+/// 256 little-endian `u32`s that TIC-80 keeps across runs of a cart.
+pub struct Pmem<'a> {
+    mem: &'a [Cell<u8>],
+}
+
+impl Pmem<'_> {
+    pub const SIZE: usize = 256;
+
+    pub fn get(&self, index: usize) -> Option<u32> {
+        let bytes = self.mem.get(index * 4..index * 4 + 4)?;
+        Some(u32::from_le_bytes(std::array::from_fn(|i| bytes[i].get())))
+    }
+
+    /// Returns the previous value, `None` when `index` is out of range.
+    pub fn set(&self, index: usize, value: u32) -> Option<u32> {
+        let old = self.get(index)?;
+        for (cell, byte) in self.mem[index * 4..].iter().zip(value.to_le_bytes()) {
+            cell.set(byte);
+        }
+        Some(old)
+    }
+}
+
+impl Keyboard<'_> {
+    pub const KEY_COUNT: u8 = 65;
+
+    /// Replaces the held keys. Codes past the fourth are dropped, as are invalid ones.
+    pub fn set(&self, keys: &[u8]) {
+        let mut held = keys
+            .iter()
+            .copied()
+            .filter(|&k| k > 0 && k <= Self::KEY_COUNT);
+        for byte in self.mem {
+            byte.set(held.next().unwrap_or(0));
+        }
+    }
+
+    pub fn held(&self, key: u8) -> bool {
+        key != 0 && self.mem.iter().any(|b| b.get() == key)
+    }
+
+    pub fn any(&self) -> bool {
+        self.mem.iter().any(|b| b.get() != 0)
+    }
+}
+
 impl<'a> Gamepads<'a> {
-    pub fn player(&self, id: u8) -> Gamepad {
+    pub fn player(&self, id: u8) -> Gamepad<'_> {
         Gamepad {
             byte: &self.mem[id as usize],
         }
@@ -412,11 +745,16 @@ impl Palette<'_> {
 
     pub fn get(&self, idx: u8) -> Option<RGB8> {
         Some(RGB8::new(
-            self.mem[idx as usize * 3 + 0].get(),
+            self.mem[idx as usize * 3].get(),
             self.mem[idx as usize * 3 + 1].get(),
             self.mem[idx as usize * 3 + 2].get(),
         ))
     }
+
+    /// All 16 colors as opaque RGBA, indexed by color.
+    pub fn rgba(&self) -> [RGBA8; 16] {
+        std::array::from_fn(|i| self.get(i as u8).unwrap().alpha(255))
+    }
 }
 
 pub struct Map<'a> {
@@ -487,7 +825,6 @@ impl PixBuf for FontChar {
 }
 
 pub struct Colorized<T: PixBuf>(pub u8, pub T);
-pub struct Rotated<T: PixBuf>(pub u32, pub T);
 
 impl<T: PixBuf> PixBuf for Colorized<T> {
     const WIDTH: usize = T::WIDTH;
@@ -496,6 +833,7 @@ impl<T: PixBuf> PixBuf for Colorized<T> {
     const BPP: usize = T::BPP;
 
     const MASK: u8 = pix_mask(Self::BPP);
+    const PACKED: bool = false;
 
     fn get_pix(&self, x: i32, y: i32) -> u8 {
         match self.1.get_pix(x, y) {
@@ -517,91 +855,203 @@ impl<T: PixBuf> PixBuf for Colorized<T> {
     }
 }
 
-impl<T: PixBuf> PixBuf for Rotated<T> {
-    const WIDTH: usize = T::HEIGHT;
-    const HEIGHT: usize = T::WIDTH; // NOTE: This is wrong if rot == 0 or 2
+impl Screen<'_> {
+    const PX_BUFFER_SIZE: usize = (Self::WIDTH * Self::HEIGHT) / 2;
+}
 
-    const BPP: usize = T::BPP;
+impl TryFrom<Cartridge> for TAC70 {
+    type Error = CartError;
 
-    const MASK: u8 = pix_mask(Self::BPP);
+    #[allow(deprecated)]
+    fn try_from(mut cart: Cartridge) -> Result<Self, Self::Error> {
+        cart.upgrade()?;
+        cart.validate()?;
+        let mut mem = Box::new([0u8; 0x18000]);
+        let code = cart.code()?;
 
-    fn get_pix(&self, x: i32, y: i32) -> u8 {
-        match self.0 % 4 {
-            0 => self.1.get_pix(x, y),
-            1 => self.1.get_pix(y, (Self::HEIGHT as i32 - 1) - x),
-            2 => self
-                .1
-                .get_pix((Self::WIDTH as i32 - 1) - x, (Self::HEIGHT as i32 - 1) - y),
-            3 => self.1.get_pix((Self::WIDTH as i32 - 1) - y, x),
-            _ => unreachable!(),
+        // RAM holds bank 0, the other banks only add code
+        for chunk in cart.chunks.iter().filter(|chunk| chunk.info.bank() == 0) {
+            use tac_cart::ChunkType::*;
+            let kind = chunk.info.chunk_type();
+            let region = match kind {
+                Tiles => 0x4000..0x6000,
+                Sprites => 0x6000..0x8000,
+                Map => 0x8000..0xFF80,
+                Samples => 0x100E4..0x11164,
+                Waveform => 0x0FFE4..0x100E4,
+                Flags => 0x14404..0x14604,
+                Music => 0x13E64..0x13FFC,
+                Patterns => 0x11164..0x13E64,
+                Palette => {
+                    // TODO: OVR PALETTE, in the second half of 96 byte chunks
+                    let len = chunk.data.len().min(48);
+                    mem[0x3FC0..0x3FC0 + len].copy_from_slice(&chunk.data[..len]);
+                    continue;
+                }
+                Screen | Code | Default | Dummy | Binary | CoverDep => continue,
+                CodeZip | PatternsDep => unreachable!("upgraded to their current chunks"),
+            };
+            mem[region][..chunk.data.len()].copy_from_slice(&chunk.data);
         }
+        Ok(TAC70::new(mem.as_ref(), code))
     }
+}
 
-    fn set_pix(&mut self, _x: i32, _y: i32, _pix: u8) {
-        unimplemented!()
+impl TAC70 {
+    /// Loads the cart in `bytes`, read from the file at `path`. A cart saved as source
+    /// is drawn in the system palette unless it has its own.
+    pub fn from_file(path: &Path, bytes: &[u8]) -> Result<Self, CartError> {
+        let cart = Cartridge::from_file(path, bytes)?;
+        let palette = cart.chunk(ChunkType::Palette, 0)?.is_some();
+        let mut tac = TAC70::try_from(cart)?;
+        if Cartridge::is_text(path) && !palette {
+            tac.load_system_palette();
+        }
+        Ok(tac)
     }
+}
 
-    fn set_buf(&mut self, _i: usize, _to: u8) {
-        unimplemented!()
-    }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    fn get_buf(&self, _i: usize) -> u8 {
-        unimplemented!()
+    fn cart(chunks: &[(u8, &[u8])]) -> Result<TAC70, CartError> {
+        let mut bytes = vec![];
+        for &(kind, data) in chunks {
+            bytes.extend([kind, data.len() as u8, (data.len() >> 8) as u8, 0]);
+            bytes.extend(data);
+        }
+        TAC70::try_from(Cartridge::try_from(bytes.as_slice())?)
     }
-}
-
-impl Screen<'_> {
-    const PX_BUFFER_SIZE: usize = (Self::WIDTH * Self::HEIGHT) / 2;
-}
 
+    #[test]
+    fn from_cartridge() {
+        let tac = cart(&[(1, &[0x21; 4]), (5, b"x=1")]).unwrap();
+        assert_eq!(tac.code, "x=1");
+        assert_eq!(tac.mem[0x4003].get(), 0x21);
+        // only bank 0 is in RAM
+        let tac = cart(&[(1, &[0x21; 4]), (1 | 1 << 5, &[0x43; 8]), (5, b"x=1")]).unwrap();
+        assert_eq!(tac.mem[0x4003].get(), 0x21);
+        assert_eq!(tac.mem[0x4004].get(), 0);
+
+        assert!(matches!(cart(&[(1, &[0; 4])]), Err(CartError::NoCode)));
+        assert_eq!(cart(&[(5, b"x=\xFF")]).unwrap().code, "x=\u{FFFD}");
+        // code over two banks, the first one full
+        let mut full = vec![b'-'; 0x10000];
+        full[..6].copy_from_slice(b"x=1 --");
+        let mut bytes = vec![5 | 1 << 5, 0, 0, 0];
+        bytes.extend(&full);
+        bytes.extend([5, 4, 0, 0]);
+        bytes.extend(b"\nx=2");
+        let tac = TAC70::try_from(Cartridge::try_from(bytes.as_slice()).unwrap()).unwrap();
+        assert!(tac.code.starts_with("x=1 --") && tac.code.ends_with("-\nx=2"));
+        assert_eq!(tac.code.len(), 0x10004);
+        assert!(matches!(
+            cart(&[(5, b"x=1"), (6, &[0; 513])]),
+            Err(CartError::ChunkTooLarge {
+                size: 513,
+                max: 512,
+                ..
+            })
+        ));
+        // an old cart, its code compressed
+        let zipped = [
+            0x78, 0x9C, 0xAB, 0xB0, 0x35, 0x04, 0x00, 0x02, 0x16, 0x00, 0xE7,
+        ];
+        assert_eq!(cart(&[(16, &zipped)]).unwrap().code, "x=1");
+    }
 
+    #[test]
+    fn from_file() {
+        let white = Some(RGB8::new(0xf4, 0xf4, 0xf4));
+        let tac = TAC70::from_file(Path::new("a.lua"), b"x=1\n").unwrap();
+        assert_eq!(tac.code, "x=1\n");
+        assert_eq!(tac.palette().get(12), white);
+
+        let text = "x=1\n-- <PALETTE>\n-- 000:ff0000\n-- </PALETTE>\n";
+        let tac = TAC70::from_file(Path::new("a.lua"), text.as_bytes()).unwrap();
+        assert_eq!(tac.palette().get(0), Some(RGB8::new(0xff, 0, 0)));
+        assert_eq!(tac.palette().get(12), Some(RGB8::new(0, 0, 0)));
+
+        // a binary cart is in the palette it has, if any
+        let tac = TAC70::from_file(Path::new("a.tic"), &[5, 3, 0, 0, b'x', b'=', b'1']).unwrap();
+        assert_eq!(tac.palette().get(12), Some(RGB8::new(0, 0, 0)));
+    }
 
-impl From<Cartridge> for TAC70 {
-    fn from(cart: Cartridge) -> Self {
-        let mut mem = Box::new([0u8; 0x18000]);
-        let mut code = None;
+    /// Asserts `draw` leaves the same bytes on a patterned screen whether told to take
+    /// the fast path or to go pixel by pixel.
+    fn same_bytes(draw: impl Fn(&mut Screen, bool)) {
+        let [fast, slow] = [true, false].map(|fast| {
+            let tac = TAC70::new(&[0u8; 0x18000], String::new());
+            let mut screen = tac.screen();
+            for (i, cell) in screen.pixels.iter().enumerate() {
+                cell.set(i as u8 ^ 0x5A);
+            }
+            draw(&mut screen, fast);
+            let bytes: Vec<u8> = screen.pixels.iter().map(Cell::get).collect();
+            bytes
+        });
+        assert_eq!(fast, slow);
+    }
 
-        for chunk in cart.chunks {
-            use tac_cart::ChunkType::*;
-            match chunk.info.chunk_type() {
-                Tiles => {
-                    mem[0x4000..=0x5FFF][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Sprites => {
-                    mem[0x6000..=0x7FFF][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Map => {
-                    mem[0x8000..=0xFF7F][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Samples => {
-                    mem[0x100E4..=0x11163][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Waveform => {
-                    mem[0x0FFE4..=0x100E3][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Flags => {
-                    mem[0x14404..=0x14603][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Music => {
-                    mem[0x13E64..=0x13FFB][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Patterns => {
-                    mem[0x11164..=0x13E63][..chunk.data.len()].copy_from_slice(&chunk.data);
-                }
-                Palette => {
-                    mem[0x3FC0..=0x3FEF].copy_from_slice(&chunk.data[0..48]);
-                    if chunk.data.len() == 96 {
-                        // TODO: OVR PALETTE
-                    }
+    #[test]
+    fn fast_paths() {
+        let sprite = Sprite(std::array::from_fn(|i| Cell::new((i * 37) as u8)));
+        let xs = [-9, -8, -3, -2, 0, 1, 2, 7, 231, 232, 233, 238, 239, 240];
+        for x in xs {
+            for y in [-5, 0, 1, 60, 130, 135, 136] {
+                for w in [1, 2, 13, 14] {
+                    same_bytes(|screen, fast| {
+                        if fast {
+                            screen.fill_span(x, y, w, 9);
+                        } else {
+                            for i in x..x + w as i32 {
+                                screen.set_pix(i, y, 9);
+                            }
+                        }
+                    });
                 }
-                Code => {
-                    code = Some(std::str::from_utf8(&chunk.data).unwrap().to_string());
+                for alpha in [ColorKey::NONE, [0, 5].into_iter().collect()] {
+                    same_bytes(|screen, fast| {
+                        if fast {
+                            screen.blit_fast(x, y, &sprite, alpha);
+                            return;
+                        }
+                        for j in 0..8 {
+                            for i in 0..8 {
+                                let pix = sprite.get_pix(i, j);
+                                if !alpha.contains(pix) {
+                                    screen.set_pix(x + i, y + j, pix);
+                                }
+                            }
+                        }
+                    });
                 }
-                Screen => {} // dunno??
-                Default => {}
-                _ => unimplemented!(),
             }
         }
-        TAC70::new(mem.as_ref(), code.unwrap())
+    }
+
+    #[test]
+    fn system_font() {
+        let mut tac = TAC70::new(&[0u8; 0x18000], String::new());
+        let width = tac.print("Hi", 0, 0, 2, true, 1, false);
+        assert_eq!(width, 12);
+        let drawn = |tac: &TAC70| (0..8).any(|y| (0..6).any(|x| tac.screen().get_pix(x, y) == 2));
+        assert!(drawn(&tac));
+
+        // a cart blanks the font, the system one comes back
+        for cell in &tac.mem[0x14604..0x14604 + 8 * TAC70::CHAR_COUNT] {
+            cell.set(0);
+        }
+        tac.update_font_data();
+        tac.screen().clear(0);
+        tac.print("Hi", 0, 0, 2, true, 1, false);
+        assert!(!drawn(&tac));
+        tac.load_system_font();
+        tac.print("Hi\u{e9}", 0, 0, 2, true, 1, false);
+        assert!(drawn(&tac));
+
+        tac.load_system_palette();
+        assert_eq!(tac.palette().get(12), Some(RGB8::new(0xf4, 0xf4, 0xf4)));
     }
 }
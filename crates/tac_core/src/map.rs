@@ -0,0 +1,189 @@
+use crate::{ColorKey, Map, PixBuf, Screen, Sprite, TAC70};
+
+/// Which part of the map to draw and where, mirroring the arguments of TIC-80's `map`.
+#[derive(Clone, Copy, Debug)]
+pub struct MapRegion {
+    /// Top-left map cell. Cells outside the map wrap around.
+    pub x: i32,
+    pub y: i32,
+    /// Size in cells.
+    pub w: i32,
+    pub h: i32,
+    /// Screen position of the top-left cell.
+    pub sx: i32,
+    pub sy: i32,
+    pub alpha: ColorKey,
+    pub scale: u32,
+}
+
+impl Default for MapRegion {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            w: 30,
+            h: 17,
+            sx: 0,
+            sy: 0,
+            alpha: ColorKey::NONE,
+            scale: 1,
+        }
+    }
+}
+
+/// A map cell as seen by a remap callback: which sprite to draw and how.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapTile {
+    pub id: u16,
+    pub hflip: bool,
+    pub vflip: bool,
+    pub rot: u32,
+}
+
+impl Map<'_> {
+    /// Like `get`, but coordinates outside of the map wrap around.
+    pub fn get_wrapped(&self, x: i32, y: i32) -> u8 {
+        let x = x.rem_euclid(Map::WIDTH as i32) as usize;
+        let y = y.rem_euclid(Map::HEIGHT as i32) as usize;
+        self.tiles[x + y * Map::WIDTH].get()
+    }
+}
+
+impl TAC70 {
+    /// Draws `region` of the map to the screen. Only cells that end up on screen are
+    /// visited; `remap` gets each of them with its (wrapped) map coordinates and
+    /// decides what is actually drawn there.
+    pub fn draw_map<E>(
+        &self,
+        region: &MapRegion,
+        mut remap: impl FnMut(MapTile, i32, i32) -> Result<MapTile, E>,
+    ) -> Result<(), E> {
+        if region.scale == 0 || region.w <= 0 || region.h <= 0 {
+            return Ok(());
+        }
+        let step = (Sprite::WIDTH as u32 * region.scale) as i32;
+
+        // cells whose squares overlap the screen
+        let visible = |s: i32, len: i32, size: usize| {
+            let first = (-s).div_euclid(step).max(0);
+            let last = (size as i32 - s + step - 1).div_euclid(step).min(len);
+            first..last
+        };
+        let cols = visible(region.sx, region.w, Screen::WIDTH);
+        let rows = visible(region.sy, region.h, Screen::HEIGHT);
+
+        let map = self.map();
+        let mut screen = self.screen();
+        for j in rows {
+            for i in cols.clone() {
+                let (mx, my) = (
+                    (region.x + i).rem_euclid(Map::WIDTH as i32),
+                    (region.y + j).rem_euclid(Map::HEIGHT as i32),
+                );
+                let tile = MapTile {
+                    id: map.get_wrapped(mx, my) as u16,
+                    ..Default::default()
+                };
+                let tile = remap(tile, mx, my)?;
+
+                let view = self
+                    .sprite_view(tile.id, 1, 1)
+                    .flip(tile.hflip, tile.vflip)
+                    .rotate(tile.rot);
+                screen.draw_sprite(
+                    region.sx + i * step,
+                    region.sy + j * step,
+                    &view,
+                    region.alpha,
+                    region.scale,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Tile n is filled with color n, the map counts up 1, 2, 3... along each row.
+    fn console() -> TAC70 {
+        let mut mem = vec![0u8; 0x18000];
+        for id in 0..16 {
+            mem[0x4000 + id * 32..0x4000 + id * 32 + 32].fill(id as u8 * 0x11);
+        }
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH {
+                mem[0x8000 + x + y * Map::WIDTH] = (x % 15 + 1) as u8;
+            }
+        }
+        TAC70::new(&mem, String::new())
+    }
+
+    fn draw(tac: &TAC70, region: MapRegion) -> Vec<(i32, i32)> {
+        let mut visited = vec![];
+        tac.draw_map(&region, |tile, x, y| {
+            visited.push((x, y));
+            Ok::<_, Infallible>(tile)
+        })
+        .unwrap();
+        visited
+    }
+
+    #[test]
+    fn wraparound() {
+        let tac = console();
+        let visited = draw(
+            &tac,
+            MapRegion {
+                x: 239,
+                y: -1,
+                w: 2,
+                h: 1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(visited, [(239, 135), (0, 135)]);
+        assert_eq!(tac.screen().get_pix(0, 0), 239 % 15 + 1);
+        assert_eq!(tac.screen().get_pix(8, 0), 1);
+    }
+
+    #[test]
+    fn culls_offscreen_cells() {
+        let tac = console();
+        let visited = draw(
+            &tac,
+            MapRegion {
+                w: 240,
+                h: 136,
+                sx: -20,
+                sy: 130,
+                scale: 2,
+                ..Default::default()
+            },
+        );
+        // 16px cells: columns 1..=16 reach x in [-4, 236), row 0 alone reaches y 130
+        assert_eq!(visited.len(), 16);
+        assert_eq!(visited.first(), Some(&(1, 0)));
+        assert_eq!(visited.last(), Some(&(16, 0)));
+        assert_eq!(tac.screen().get_pix(0, 135), 2);
+        assert_eq!(tac.screen().get_pix(12, 135), 3);
+    }
+
+    #[test]
+    fn remap() {
+        let tac = console();
+        tac.draw_map(&MapRegion::default(), |tile, x, _| {
+            Ok::<_, Infallible>(MapTile {
+                id: if x == 0 { 9 } else { tile.id },
+                ..tile
+            })
+        })
+        .unwrap();
+        assert_eq!(tac.screen().get_pix(0, 0), 9);
+        assert_eq!(tac.screen().get_pix(8, 0), 2);
+    }
+}
@@ -0,0 +1,272 @@
+//! Input recordings that play a cart back exactly as it was played.
+//!
+//! A replay stores the input registers (gamepads, mouse and keyboard) as they were
+//! right before each `TIC`, along with the cart they were recorded on and the
+//! seed its random number generator started from. On disk, only the bytes that
+//! changed are kept:
+//!
+//! ```text
+//! "TACR" version:u8 cart_hash:u64 seed:u64 frames:u32     (little endian)
+//! then, for every frame whose input differs from the previous one:
+//!     gap:varint  mask:u16  changed bytes...
+//! ```
+//!
+//! `gap` counts the frames since the previous change (or since frame 0), bit `n`
+//! of `mask` says byte `n` of the input changed. Input starts out all zeroes.
+
+use std::io::{self, Read, Write};
+
+use crate::{ReplayError, TAC70};
+
+/// The input registers at 0xFF80: 4 gamepads, the mouse, then the keyboard.
+pub type InputState = [u8; 12];
+
+const MAGIC: &[u8; 4] = b"TACR";
+const VERSION: u8 = 1;
+const INPUT_ADDR: usize = 0xFF80;
+
+impl TAC70 {
+    pub fn input_state(&self) -> InputState {
+        std::array::from_fn(|i| self.mem[INPUT_ADDR + i].get())
+    }
+
+    pub fn set_input_state(&self, input: &InputState) {
+        for (cell, &byte) in self.mem[INPUT_ADDR..].iter().zip(input) {
+            cell.set(byte);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// [`Replay::hash`] of the cart file this was recorded with.
+    pub cart_hash: u64,
+    pub seed: u64,
+    frames: Vec<InputState>,
+}
+
+impl Replay {
+    pub fn new(cart_hash: u64, seed: u64) -> Self {
+        Self {
+            cart_hash,
+            seed,
+            frames: vec![],
+        }
+    }
+
+    /// 64-bit FNV-1a, stable across platforms and releases, unlike `std`'s hashers.
+    pub fn hash(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Appends the input of the next frame.
+    pub fn push(&mut self, input: InputState) {
+        self.frames.push(input);
+    }
+
+    /// Forgets the input of frame `len` and on.
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    /// The input to apply right before `TIC` number `frame` runs.
+    pub fn frame(&self, frame: usize) -> Option<&InputState> {
+        self.frames.get(frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn write(&self, mut w: impl Write) -> Result<(), ReplayError> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&self.cart_hash.to_le_bytes())?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(
+            &u32::try_from(self.len())
+                .map_err(|_| ReplayError::TooLong)?
+                .to_le_bytes(),
+        )?;
+
+        let mut prev = InputState::default();
+        let mut gap = 0u32;
+        for input in &self.frames {
+            let mask = (0..prev.len())
+                .filter(|&i| input[i] != prev[i])
+                .fold(0u16, |mask, i| mask | 1 << i);
+            if mask != 0 {
+                write_varint(&mut w, gap)?;
+                w.write_all(&mask.to_le_bytes())?;
+                for i in (0..input.len()).filter(|i| mask & 1 << i != 0) {
+                    w.write_all(&[input[i]])?;
+                }
+                prev = *input;
+                gap = 0;
+            }
+            gap += 1;
+        }
+        Ok(())
+    }
+
+    pub fn read(mut r: impl Read) -> Result<Self, ReplayError> {
+        let mut header = [0u8; 25];
+        r.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ReplayError::NotAReplay,
+            _ => ReplayError::Io(e),
+        })?;
+        if &header[..4] != MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        if header[4] != VERSION {
+            return Err(ReplayError::Version(header[4]));
+        }
+        let cart_hash = u64::from_le_bytes(header[5..13].try_into().unwrap());
+        let seed = u64::from_le_bytes(header[13..21].try_into().unwrap());
+        let len = u32::from_le_bytes(header[21..25].try_into().unwrap()) as usize;
+
+        let mut replay = Self::new(cart_hash, seed);
+        let mut input = InputState::default();
+        let mut byte = [0u8];
+        while let Some(gap) = read_varint(&mut r)? {
+            let mut mask = [0u8; 2];
+            r.read_exact(&mut mask).map_err(truncated)?;
+            let mask = u16::from_le_bytes(mask);
+            if mask >> input.len() != 0 {
+                return Err(ReplayError::Corrupt("bad change mask"));
+            }
+
+            // frames up to this change keep the previous input
+            let changed_at = match replay.len() {
+                0 => gap as usize,
+                _ if gap == 0 => return Err(ReplayError::Corrupt("change out of order")),
+                n => n - 1 + gap as usize,
+            };
+            if changed_at >= len {
+                return Err(ReplayError::Corrupt("more changes than frames"));
+            }
+            replay.frames.resize(changed_at, input);
+
+            for (i, value) in input.iter_mut().enumerate() {
+                if mask & 1 << i != 0 {
+                    r.read_exact(&mut byte).map_err(truncated)?;
+                    *value = byte[0];
+                }
+            }
+            replay.push(input);
+        }
+        replay.frames.resize(len, input);
+        Ok(replay)
+    }
+}
+
+fn write_varint(w: &mut impl Write, mut n: u32) -> std::io::Result<()> {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// `None` at a clean end of stream.
+fn read_varint(r: &mut impl Read) -> Result<Option<u32>, ReplayError> {
+    let mut n = 0u32;
+    let mut byte = [0u8];
+    for shift in (0..32).step_by(7) {
+        if r.read(&mut byte)? == 0 {
+            return match shift {
+                0 => Ok(None),
+                _ => Err(ReplayError::Corrupt("truncated")),
+            };
+        }
+        n |= ((byte[0] & 0x7F) as u32) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(n));
+        }
+    }
+    Err(ReplayError::Corrupt("varint too long"))
+}
+
+fn truncated(e: io::Error) -> ReplayError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => ReplayError::Corrupt("truncated"),
+        _ => ReplayError::Io(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut replay = Replay::new(Replay::hash(b"cart"), 42);
+        let mut input = InputState::default();
+        for frame in 0..300 {
+            input[0] = (frame / 50) as u8; // gamepad changes every 50 frames
+            input[4] = if frame > 100 { 120 } else { 0 }; // mouse x, once
+            input[8] = if frame == 200 { 48 } else { 0 }; // space, tapped
+            replay.push(input);
+        }
+        replay.push(InputState::default());
+        replay.push(InputState::default());
+
+        let mut bytes = vec![];
+        replay.write(&mut bytes).unwrap();
+        // header, then 8 changes of 1 gap + 2 mask bytes, changing 10 bytes in all
+        assert_eq!(bytes.len(), 25 + 8 * 3 + 10);
+        assert_eq!(Replay::read(bytes.as_slice()).unwrap(), replay);
+    }
+
+    #[test]
+    fn empty_and_idle() {
+        for len in [0, 1, 1000] {
+            let mut replay = Replay::new(1, 2);
+            for _ in 0..len {
+                replay.push(InputState::default());
+            }
+            let mut bytes = vec![];
+            replay.write(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), 25);
+            assert_eq!(Replay::read(bytes.as_slice()).unwrap(), replay);
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Replay::read(&b"TACR"[..]).is_err());
+        assert!(Replay::read(&[0u8; 25][..]).is_err());
+
+        let mut bytes = vec![];
+        let mut replay = Replay::new(0, 0);
+        replay.push([1; 12]);
+        replay.write(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Replay::read(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn input_registers() {
+        let tac = TAC70::new(&[0u8; 0x18000], String::new());
+        tac.gamepads().player(1).set_btn(4, true);
+        tac.mouse().set(10, 20, true, false, false, 0, 0);
+        tac.keyboard().set(&[1, 0, 99, 48]);
+
+        let input = tac.input_state();
+        assert_eq!(input, [0, 16, 0, 0, 10, 20, 1, 0, 1, 48, 0, 0]);
+        assert!(tac.keyboard().held(48) && !tac.keyboard().held(99));
+
+        let other = TAC70::new(&[0u8; 0x18000], String::new());
+        other.set_input_state(&input);
+        assert_eq!(other.input_state(), input);
+    }
+}
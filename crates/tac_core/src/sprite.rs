@@ -0,0 +1,255 @@
+use std::cell::Cell;
+
+use crate::{PixBuf, Sprite};
+
+/// A `w`×`h` block of tiles from the sprite sheet seen as one image.
+///
+/// Like TIC-80, the whole block is rotated clockwise by `rot` quarter turns and
+/// then flipped, so multi-tile sprites keep their shape instead of every tile
+/// turning in place.
+#[derive(Clone)]
+pub struct SpriteView<'a> {
+    sheet: &'a [Cell<u8>],
+    id: u16,
+    w: u32,
+    h: u32,
+    hflip: bool,
+    vflip: bool,
+    rot: u32,
+}
+
+impl<'a> SpriteView<'a> {
+    /// Tiles per sheet row; `id + 16` is the tile right below `id`.
+    pub const SHEET_COLUMNS: u32 = 16;
+    /// Background tiles followed by foreground sprites.
+    pub const TILE_COUNT: u32 = 512;
+
+    pub(crate) fn new(sheet: &'a [Cell<u8>], id: u16, w: u32, h: u32) -> Self {
+        Self {
+            sheet,
+            id,
+            w,
+            h,
+            hflip: false,
+            vflip: false,
+            rot: 0,
+        }
+    }
+
+    pub fn flip(self, hflip: bool, vflip: bool) -> Self {
+        Self {
+            hflip,
+            vflip,
+            ..self
+        }
+    }
+
+    /// Rotates clockwise by `rot` quarter turns.
+    pub fn rotate(self, rot: u32) -> Self {
+        Self {
+            rot: rot % 4,
+            ..self
+        }
+    }
+
+    /// Width in pixels after rotation.
+    pub fn width(&self) -> u32 {
+        if matches!(self.rot, 0 | 2) {
+            self.w * Sprite::WIDTH as u32
+        } else {
+            self.h * Sprite::HEIGHT as u32
+        }
+    }
+
+    /// Height in pixels after rotation.
+    pub fn height(&self) -> u32 {
+        if matches!(self.rot, 0 | 2) {
+            self.h * Sprite::HEIGHT as u32
+        } else {
+            self.w * Sprite::WIDTH as u32
+        }
+    }
+
+    /// True when the view is drawn as-is, tile by tile.
+    pub fn is_plain(&self) -> bool {
+        !self.hflip && !self.vflip && self.rot == 0
+    }
+
+    /// Size of the view in tiles before rotation.
+    pub fn tiles(&self) -> (u32, u32) {
+        (self.w, self.h)
+    }
+
+    /// Tile at column `i`, row `j` of the unrotated block. Ids past the end of the
+    /// sheet wrap around.
+    pub fn tile(&self, i: u32, j: u32) -> Sprite {
+        let id = (self.id as u32 + i + j * Self::SHEET_COLUMNS) % Self::TILE_COUNT;
+        let off = id as usize * 32;
+        Sprite(std::array::from_fn(|k| self.sheet[off + k].clone()))
+    }
+
+    /// Pixel at (`x`, `y`) of the flipped and rotated image.
+    pub fn get_pix(&self, x: i32, y: i32) -> u8 {
+        let (w, h) = (self.width() as i32, self.height() as i32);
+        assert!(x >= 0 && x < w && y >= 0 && y < h);
+        let x = if self.hflip { w - 1 - x } else { x };
+        let y = if self.vflip { h - 1 - y } else { y };
+
+        // undo the rotation, `sw`/`sh` being the unrotated size
+        let (sw, sh) = (
+            (self.w * Sprite::WIDTH as u32) as i32,
+            (self.h * Sprite::HEIGHT as u32) as i32,
+        );
+        let (sx, sy) = match self.rot {
+            0 => (x, y),
+            1 => (y, sh - 1 - x),
+            2 => (sw - 1 - x, sh - 1 - y),
+            3 => (sw - 1 - y, x),
+            _ => unreachable!(),
+        };
+        self.sheet_pix(sx as u32, sy as u32)
+    }
+
+    fn sheet_pix(&self, sx: u32, sy: u32) -> u8 {
+        let (tw, th) = (Sprite::WIDTH as u32, Sprite::HEIGHT as u32);
+        let id = (self.id as u32 + sx / tw + (sy / th) * Self::SHEET_COLUMNS) % Self::TILE_COUNT;
+        let i = ((sx % tw) + (sy % th) * tw) as usize;
+        let byte = self.sheet[id as usize * 32 + i / 2].get();
+        (byte >> ((i % 2) * 4)) & 0xF
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ColorKey, PixBuf, TAC70};
+
+    /// A 2x1 sprite: tile 0 is filled with 1 and has a 3 in its top-left corner,
+    /// tile 1 is filled with 2.
+    fn console() -> TAC70 {
+        let mut mem = vec![0u8; 0x18000];
+        mem[0x4000..0x4020].fill(0x11);
+        mem[0x4020..0x4040].fill(0x22);
+        mem[0x4000] = 0x13;
+        TAC70::new(&mem, String::new())
+    }
+
+    fn render(flip: (bool, bool), rot: u32) -> Vec<String> {
+        let tac = console();
+        let view = tac.sprite_view(0, 2, 1).flip(flip.0, flip.1).rotate(rot);
+        tac.screen().draw_sprite(0, 0, &view, ColorKey::NONE, 1);
+
+        let screen = tac.screen();
+        (0..view.height() as i32)
+            .map(|y| {
+                (0..view.width() as i32)
+                    .map(|x| char::from_digit(screen.get_pix(x, y) as u32, 16).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    const WIDE: [&str; 4] = [
+        // no flip
+        "3111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222",
+        // hflip
+        "2222222211111113\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111",
+        // vflip
+        "1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         1111111122222222\n\
+         3111111122222222",
+        // hflip + vflip
+        "2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111111\n\
+         2222222211111113",
+    ];
+
+    const TALL: [&str; 4] = [
+        // marker top-right, tile 0 on top
+        "11111113\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n\
+         22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222",
+        // marker top-left, tile 0 on top
+        "31111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n\
+         22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222",
+        // marker bottom-right, tile 0 at the bottom
+        "22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n\
+         11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111113",
+        // marker bottom-left, tile 0 at the bottom
+        "22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n22222222\n\
+         11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n11111111\n31111111",
+    ];
+
+    fn golden(s: &str) -> Vec<String> {
+        s.split('\n').map(|l| l.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn flip_and_rotate() {
+        // (flip, rot) -> expected image; rotation happens first, then the flip
+        let cases = [
+            (0, 0, WIDE[0]),
+            (1, 0, WIDE[1]),
+            (2, 0, WIDE[2]),
+            (3, 0, WIDE[3]),
+            (0, 1, TALL[0]),
+            (1, 1, TALL[1]),
+            (2, 1, TALL[2]),
+            (3, 1, TALL[3]),
+            (0, 2, WIDE[3]),
+            (1, 2, WIDE[2]),
+            (2, 2, WIDE[1]),
+            (3, 2, WIDE[0]),
+            (0, 3, TALL[3]),
+            (1, 3, TALL[2]),
+            (2, 3, TALL[1]),
+            (3, 3, TALL[0]),
+        ];
+        for (flip, rot, expected) in cases {
+            assert_eq!(
+                render((flip & 1 != 0, flip & 2 != 0), rot),
+                golden(expected),
+                "flip {} rot {}",
+                flip,
+                rot
+            );
+        }
+    }
+
+    #[test]
+    fn scale_and_alpha() {
+        let tac = console();
+        let view = tac.sprite_view(0, 2, 1).rotate(1);
+        tac.screen()
+            .draw_sprite(4, 2, &view, ColorKey::single(2), 2);
+
+        let screen = tac.screen();
+        assert_eq!(screen.get_pix(4 + 14, 2), 3);
+        assert_eq!(screen.get_pix(4 + 15, 3), 3);
+        assert_eq!(screen.get_pix(4 + 13, 2), 1);
+        assert_eq!(screen.get_pix(4, 2 + 16), 0); // tile 1 is transparent
+        assert_eq!(screen.get_pix(3, 2), 0);
+    }
+}
@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+use crate::{ColorKey, PixBuf, TAC70};
+
+/// Where a textured triangle takes its pixels from, mirroring TIC-80's `texsrc`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Texture {
+    /// The 128×128 sheet of background tiles.
+    #[default]
+    Tiles,
+    /// The whole map, 8×8 pixels a cell, drawn with the background tiles.
+    Map,
+}
+
+/// A corner of a textured triangle: where it goes on screen, and the point of the
+/// texture it shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TexVertex {
+    pub x: f64,
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+    /// Depth for perspective correct texturing. Ignored unless all three corners have
+    /// one above zero.
+    pub z: f64,
+}
+
+/// The pixels of each row covered by the triangle `pos`, top to bottom, clipped to
+/// rows `0..height`. A pixel is covered if its center is, left and top edges included.
+pub(crate) fn spans(
+    pos: [(f64, f64); 3],
+    height: usize,
+) -> impl Iterator<Item = (i32, Range<i32>)> {
+    let top = pos.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let bottom = pos.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let first = ((top - 0.5).ceil().max(0.0) as i32).min(height as i32);
+    let last = ((bottom - 0.5).ceil().max(0.0) as i32).min(height as i32);
+
+    (first..last).filter_map(move |y| {
+        let cy = y as f64 + 0.5;
+        let (mut left, mut right) = (f64::INFINITY, f64::NEG_INFINITY);
+        for (a, b) in [(pos[0], pos[1]), (pos[1], pos[2]), (pos[2], pos[0])] {
+            let (a, b) = if a.1 <= b.1 { (a, b) } else { (b, a) };
+            if a.1 == b.1 || cy < a.1 || cy > b.1 {
+                continue;
+            }
+            let x = a.0 + (cy - a.1) * (b.0 - a.0) / (b.1 - a.1);
+            left = left.min(x);
+            right = right.max(x);
+        }
+        let xs = (left - 0.5).ceil() as i32..(right - 0.5).ceil() as i32;
+        (!xs.is_empty()).then_some((y, xs))
+    })
+}
+
+/// Weights of the corners of `pos` at `p`, summing up to 1. `None` if the triangle
+/// has no area.
+fn barycentric(pos: [(f64, f64); 3], p: (f64, f64)) -> Option<[f64; 3]> {
+    let [a, b, c] = pos;
+    let det = (b.1 - c.1) * (a.0 - c.0) + (c.0 - b.0) * (a.1 - c.1);
+    if det == 0.0 {
+        return None;
+    }
+    let wa = ((b.1 - c.1) * (p.0 - c.0) + (c.0 - b.0) * (p.1 - c.1)) / det;
+    let wb = ((c.1 - a.1) * (p.0 - c.0) + (a.0 - c.0) * (p.1 - c.1)) / det;
+    Some([wa, wb, 1.0 - wa - wb])
+}
+
+/// Fills triangle `verts` with the pixels `tex` has at the texture coordinates of
+/// each, leaving out those in `alpha`.
+pub(crate) fn draw<P: PixBuf>(
+    buf: &mut P,
+    verts: [TexVertex; 3],
+    alpha: ColorKey,
+    tex: impl Fn(i32, i32) -> u8,
+) {
+    let pos = verts.map(|v| (v.x, v.y));
+    let depth = verts.iter().all(|v| v.z > 0.0);
+    for (y, xs) in spans(pos, P::HEIGHT) {
+        for x in xs.start.max(0)..xs.end.min(P::WIDTH as i32) {
+            let Some(mut w) = barycentric(pos, (x as f64 + 0.5, y as f64 + 0.5)) else {
+                return;
+            };
+            if depth {
+                // interpolate u/z and v/z along with 1/z
+                for (w, v) in w.iter_mut().zip(&verts) {
+                    *w /= v.z;
+                }
+                let sum: f64 = w.iter().sum();
+                w = w.map(|w| w / sum);
+            }
+            let u = w[0] * verts[0].u + w[1] * verts[1].u + w[2] * verts[2].u;
+            let v = w[0] * verts[0].v + w[1] * verts[1].v + w[2] * verts[2].v;
+            let pix = tex(u.floor() as i32, v.floor() as i32);
+            if !alpha.contains(pix) {
+                buf.set_pix(x, y, pix);
+            }
+        }
+    }
+}
+
+impl TAC70 {
+    /// Draws triangle `verts` on the screen textured from `src`, like TIC-80's
+    /// `ttri`. Texture coordinates wrap around.
+    pub fn ttri(&self, verts: [TexVertex; 3], src: Texture, alpha: ColorKey) {
+        let sheet = self.sprite_view(0, 16, 16);
+        let (w, h) = (sheet.width() as i32, sheet.height() as i32);
+        let map = self.map();
+        let mut screen = self.screen();
+        match src {
+            Texture::Tiles => draw(&mut screen, verts, alpha, |u, v| {
+                sheet.get_pix(u.rem_euclid(w), v.rem_euclid(h))
+            }),
+            Texture::Map => draw(&mut screen, verts, alpha, |u, v| {
+                let tile = map.get_wrapped(u.div_euclid(8), v.div_euclid(8));
+                let cell = self.sprite_view(tile as u16, 1, 1);
+                cell.get_pix(u.rem_euclid(8), v.rem_euclid(8))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// How many pixels of each row a triangle covers.
+    fn widths(pos: [(f64, f64); 3]) -> Vec<(i32, i32)> {
+        spans(pos, 136)
+            .map(|(y, xs)| (y, xs.len() as i32))
+            .collect()
+    }
+
+    #[test]
+    fn coverage() {
+        // pixel centers on the right edge are left out
+        assert_eq!(
+            widths([(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]),
+            [(0, 3), (1, 2), (2, 1)]
+        );
+        // two triangles sharing an edge don't both draw it
+        let a: i32 = widths([(0.0, 0.0), (8.0, 0.0), (0.0, 8.0)])
+            .iter()
+            .map(|r| r.1)
+            .sum();
+        let b: i32 = widths([(8.0, 0.0), (8.0, 8.0), (0.0, 8.0)])
+            .iter()
+            .map(|r| r.1)
+            .sum();
+        assert_eq!(a + b, 64);
+        assert_eq!(widths([(0.0, 0.0), (4.0, 4.0), (8.0, 8.0)]), []);
+        assert_eq!(
+            widths([(0.0, -10.0), (100.0, 500.0), (-100.0, 500.0)]).len(),
+            136
+        );
+    }
+
+    #[test]
+    fn textured() {
+        let mut mem = vec![0u8; 0x18000];
+        // tile 1 has columns colored 0, 1, 2 ... 7
+        for row in 0..8 {
+            mem[0x4020 + row * 4..0x4020 + row * 4 + 4].copy_from_slice(&[0x10, 0x32, 0x54, 0x76]);
+        }
+        mem[0x8000] = 1;
+        let tac = TAC70::new(&mem, String::new());
+        let corner = |x: f64, y: f64, u: f64, v: f64| TexVertex { x, y, u, v, z: 0.0 };
+        // tile 1 stretched twice as wide, over a rectangle of two triangles
+        let quad = |src: Texture, (u, v): (f64, f64), alpha: ColorKey| {
+            tac.screen().clear(15);
+            tac.ttri(
+                [
+                    corner(0.0, 0.0, u, v),
+                    corner(16.0, 0.0, u + 8.0, v),
+                    corner(0.0, 8.0, u, v + 8.0),
+                ],
+                src,
+                alpha,
+            );
+            tac.ttri(
+                [
+                    corner(16.0, 0.0, u + 8.0, v),
+                    corner(16.0, 8.0, u + 8.0, v + 8.0),
+                    corner(0.0, 8.0, u, v + 8.0),
+                ],
+                src,
+                alpha,
+            );
+            let screen = tac.screen();
+            (0..8)
+                .map(|y| (0..17).map(|x| screen.get_pix(x, y)).collect())
+                .collect::<Vec<Vec<u8>>>()
+        };
+        let row = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 15];
+        assert_eq!(quad(Texture::Tiles, (8.0, 0.0), ColorKey::NONE), [row; 8]);
+        assert_eq!(quad(Texture::Map, (0.0, 0.0), ColorKey::NONE), [row; 8]);
+        // wrapped around to tile 1 again
+        assert_eq!(
+            quad(Texture::Tiles, (-120.0, 128.0), ColorKey::NONE),
+            [row; 8]
+        );
+
+        let keyed = quad(Texture::Tiles, (8.0, 0.0), [1, 3, 5].into_iter().collect());
+        assert_eq!(
+            keyed[3],
+            [0, 0, 15, 15, 2, 2, 15, 15, 4, 4, 15, 15, 6, 6, 7, 7, 15]
+        );
+    }
+}
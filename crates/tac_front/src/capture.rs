@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use tac_core::{CaptureError, GifRecorder, IndexedFrame, TAC70};
+
+/// Upscale applied to screenshots and recordings.
+const SCALE: usize = 2;
+
+/// F8 takes a screenshot, F9 starts and stops a GIF recording, like in TIC-80.
+/// Files go next to the cartridge, numbered after it.
+pub struct Capture {
+    base: PathBuf,
+    recorder: Option<GifRecorder<BufWriter<File>>>,
+}
+
+impl Capture {
+    pub fn new(cart_path: &Path) -> Self {
+        Self {
+            base: cart_path.with_extension(""),
+            recorder: None,
+        }
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Handles the hotkeys, once per rendered frame.
+    pub fn hotkeys(&mut self, tac: &TAC70) {
+        let result = self.try_hotkeys(tac);
+        self.report(result);
+    }
+
+    /// Adds the frame `tac` just drew to the recording, if any. Call after every `TIC`.
+    pub fn record(&mut self, tac: &TAC70) {
+        let result = match &mut self.recorder {
+            Some(recorder) => recorder.push(&IndexedFrame::capture(&tac.screen(), &tac.palette())),
+            None => Ok(()),
+        };
+        self.report(result);
+    }
+
+    fn report(&mut self, result: Result<(), CaptureError>) {
+        if let Err(e) = result {
+            eprintln!("Capture failed: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    fn try_hotkeys(&mut self, tac: &TAC70) -> Result<(), CaptureError> {
+        let frame = || IndexedFrame::capture(&tac.screen(), &tac.palette());
+
+        if is_key_pressed(KeyCode::F8) {
+            let path = self.next_free("png");
+            frame()
+                .scaled(SCALE)
+                .write_png(BufWriter::new(File::create(&path)?))?;
+            println!("Saved screenshot {}", path.display());
+        }
+
+        if is_key_pressed(KeyCode::F9) {
+            match self.recorder.take() {
+                Some(recorder) => println!("Stopped recording ({} frames)", recorder.frames()),
+                None => {
+                    let path = self.next_free("gif");
+                    let file = BufWriter::new(File::create(&path)?);
+                    self.recorder = Some(GifRecorder::new(file, SCALE, frame().palette)?);
+                    println!("Recording to {}", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_free(&self, ext: &str) -> PathBuf {
+        let stem = self.base.file_name().unwrap_or_default().to_string_lossy();
+        (1..)
+            .map(|n| self.base.with_file_name(format!("{}-{}.{}", stem, n, ext)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+}
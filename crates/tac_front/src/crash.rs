@@ -0,0 +1,67 @@
+use std::error::Error;
+
+use tac_core::{PixBuf, Screen, TAC70};
+use tac_runtime::RuntimeError;
+
+/// Characters per line and lines per screen, in the fixed-width system font.
+const COLUMNS: usize = Screen::WIDTH / 6;
+const ROWS: usize = Screen::HEIGHT / 8;
+
+const BACKGROUND: u8 = 0;
+const MESSAGE: u8 = 2;
+const TRACEBACK: u8 = 13;
+const HINT: u8 = 12;
+
+/// Replaces the screen of a cart that failed with the error and, for errors in its
+/// code, the call stack that led to it, like TIC-80 does. It's drawn in the system
+/// font and palette, since the cart's own may be anything by then; the cart is
+/// restarted or reloaded anyway.
+pub fn draw(error: &(dyn Error + 'static), tac: &mut TAC70) {
+    let runtime_error = error.downcast_ref::<RuntimeError>();
+    match runtime_error {
+        Some(e) => eprintln!("{}", e.report()),
+        None => eprintln!("{}", error),
+    }
+    tac.load_system_font();
+    tac.load_system_palette();
+    tac.screen().clear(BACKGROUND);
+
+    let mut lines: Vec<(u8, String)> = wrap(&error.to_string())
+        .map(|line| (MESSAGE, line))
+        .collect();
+    if let Some(traceback) = runtime_error.and_then(RuntimeError::traceback) {
+        lines.push((TRACEBACK, String::new()));
+        lines.push((TRACEBACK, "stack traceback:".to_string()));
+        lines.extend(wrap(traceback).map(|line| (TRACEBACK, format!(" {}", line))));
+    }
+
+    // the last row is for the hint
+    lines.truncate(ROWS - 1);
+    for (row, (color, line)) in lines.iter().enumerate() {
+        tac.print(line, 0, row as i32 * 8, *color, true, 1, false);
+    }
+    tac.print(
+        "Press R to restart",
+        0,
+        (ROWS as i32 - 1) * 8,
+        HINT,
+        true,
+        1,
+        false,
+    );
+}
+
+/// Splits `text` into lines that fit on the screen, breaking long ones anywhere.
+fn wrap(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines().flat_map(|line| {
+        let chars: Vec<char> = line.chars().collect();
+        let lines: Vec<String> = match chars.is_empty() {
+            true => vec![String::new()],
+            false => chars
+                .chunks(COLUMNS - 1)
+                .map(|chunk| chunk.iter().collect())
+                .collect(),
+        };
+        lines
+    })
+}
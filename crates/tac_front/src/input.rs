@@ -0,0 +1,139 @@
+use macroquad::prelude::*;
+use tac_cart::Input;
+use tac_core::TAC70;
+
+/// Host keys and the TIC-80 key codes they stand for.
+const KEYS: [(KeyCode, u8); 68] = [
+    (KeyCode::A, 1),
+    (KeyCode::B, 2),
+    (KeyCode::C, 3),
+    (KeyCode::D, 4),
+    (KeyCode::E, 5),
+    (KeyCode::F, 6),
+    (KeyCode::G, 7),
+    (KeyCode::H, 8),
+    (KeyCode::I, 9),
+    (KeyCode::J, 10),
+    (KeyCode::K, 11),
+    (KeyCode::L, 12),
+    (KeyCode::M, 13),
+    (KeyCode::N, 14),
+    (KeyCode::O, 15),
+    (KeyCode::P, 16),
+    (KeyCode::Q, 17),
+    (KeyCode::R, 18),
+    (KeyCode::S, 19),
+    (KeyCode::T, 20),
+    (KeyCode::U, 21),
+    (KeyCode::V, 22),
+    (KeyCode::W, 23),
+    (KeyCode::X, 24),
+    (KeyCode::Y, 25),
+    (KeyCode::Z, 26),
+    (KeyCode::Key0, 27),
+    (KeyCode::Key1, 28),
+    (KeyCode::Key2, 29),
+    (KeyCode::Key3, 30),
+    (KeyCode::Key4, 31),
+    (KeyCode::Key5, 32),
+    (KeyCode::Key6, 33),
+    (KeyCode::Key7, 34),
+    (KeyCode::Key8, 35),
+    (KeyCode::Key9, 36),
+    (KeyCode::Minus, 37),
+    (KeyCode::Equal, 38),
+    (KeyCode::LeftBracket, 39),
+    (KeyCode::RightBracket, 40),
+    (KeyCode::Backslash, 41),
+    (KeyCode::Semicolon, 42),
+    (KeyCode::Apostrophe, 43),
+    (KeyCode::GraveAccent, 44),
+    (KeyCode::Comma, 45),
+    (KeyCode::Period, 46),
+    (KeyCode::Slash, 47),
+    (KeyCode::Space, 48),
+    (KeyCode::Tab, 49),
+    (KeyCode::Enter, 50),
+    (KeyCode::Backspace, 51),
+    (KeyCode::Delete, 52),
+    (KeyCode::Insert, 53),
+    (KeyCode::PageUp, 54),
+    (KeyCode::PageDown, 55),
+    (KeyCode::Home, 56),
+    (KeyCode::End, 57),
+    (KeyCode::Up, 58),
+    (KeyCode::Down, 59),
+    (KeyCode::Left, 60),
+    (KeyCode::Right, 61),
+    (KeyCode::CapsLock, 62),
+    (KeyCode::LeftControl, 63),
+    (KeyCode::RightControl, 63),
+    (KeyCode::LeftShift, 64),
+    (KeyCode::RightShift, 64),
+    (KeyCode::LeftAlt, 65),
+    (KeyCode::RightAlt, 65),
+];
+
+/// Where the screen is drawn in the window, to map the mouse back onto it.
+pub struct Layout {
+    pub offx: f32,
+    pub offy: f32,
+    pub upscale: f32,
+}
+
+/// Copies the state of the host's keyboard and mouse into the console's input registers.
+/// A cart that asks for one kind of `input` only gets that one: the keys stop working
+/// as a gamepad for a keyboard or mouse cart, and only a mouse cart sees the mouse.
+/// Carts that don't say get everything.
+pub fn poll(tac: &TAC70, layout: &Layout, input: Option<Input>) {
+    let (gamepad, mouse, keyboard) = match input {
+        None => (true, true, true),
+        Some(Input::Gamepad) => (true, false, false),
+        Some(Input::Mouse) => (false, true, false),
+        Some(Input::Keyboard) => (false, false, true),
+    };
+    let gamepads = tac.gamepads();
+    let down = |key| gamepad && is_key_down(key);
+
+    gamepads.player(0).set_btn(0, down(KeyCode::Up));
+    gamepads.player(0).set_btn(1, down(KeyCode::Down));
+    gamepads.player(0).set_btn(2, down(KeyCode::Left));
+    gamepads.player(0).set_btn(3, down(KeyCode::Right));
+    gamepads.player(0).set_btn(4, down(KeyCode::Z));
+    gamepads.player(0).set_btn(5, down(KeyCode::X));
+    gamepads.player(0).set_btn(6, down(KeyCode::A));
+    gamepads.player(0).set_btn(7, down(KeyCode::S));
+
+    if mouse {
+        poll_mouse(tac, layout);
+    } else {
+        tac.mouse().set(0, 0, false, false, false, 0, 0);
+    }
+
+    let mut held: Vec<u8> = KEYS
+        .iter()
+        .filter(|(key, _)| keyboard && is_key_down(*key))
+        .map(|&(_, code)| code)
+        .collect();
+    held.dedup(); // left and right modifiers
+    tac.keyboard().set(&held);
+}
+
+fn poll_mouse(tac: &TAC70, layout: &Layout) {
+    let (mx, my) = mouse_position();
+    let (ml, mm, mr) = (
+        is_mouse_button_down(MouseButton::Left),
+        is_mouse_button_down(MouseButton::Middle),
+        is_mouse_button_down(MouseButton::Right),
+    );
+    let (scrollx, scrolly) = mouse_wheel();
+    let (mx, my) = (
+        ((mx - layout.offx) / layout.upscale).max(0.0) as u8,
+        ((my - layout.offy) / layout.upscale).max(0.0) as u8,
+    );
+    // the registers only hold 6 bits of scroll
+    let scroll = |s: f32| s.round().clamp(-32.0, 31.0) as i8;
+
+    tac.mouse()
+        .set(mx, my, ml, mm, mr, scroll(scrollx), scroll(scrolly));
+}
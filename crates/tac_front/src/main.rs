@@ -1,23 +1,204 @@
-use std::env;
+mod capture;
+mod crash;
+mod input;
+mod pmem;
+mod states;
+mod watch;
 
-use rgb::ComponentBytes;
-use tac_cart::Cartridge;
-use tac_core::{PixBuf, Screen};
-use tac_runtime::TAC70Runtime;
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rgb::FromSlice;
+use tac_cart::{CartMetadata, Cartridge, Language};
+use tac_core::{PixBuf, Replay, Screen, TAC70};
+use tac_runtime::{FrameScheduler, Limits, Preserve, Rewind, RuntimeError, TAC70Runtime};
 
 use macroquad::prelude::*;
 
-#[macroquad::main("TAC-70")]
+use capture::Capture;
+use input::Layout;
+use pmem::PmemFile;
+use states::SaveStates;
+use watch::Watcher;
+
+#[macroquad::main(window_conf)]
 async fn main() {
-    let cartridge_path = env::args().nth(1).unwrap();
-    println!("Loading {}..", &cartridge_path);
-    let cart = Cartridge::load(cartridge_path).unwrap();
-    dbg!(&cart);
-    let mut runtime = TAC70Runtime::new(cart.into()).unwrap();
+    if let Err(e) = run().await {
+        match e.downcast_ref::<RuntimeError>() {
+            Some(e) => eprintln!("Error: {}", e.report()),
+            None => eprintln!("Error: {}", e),
+        }
+        std::process::exit(1);
+    }
+}
+
+/// The window is named after the cart, when it gives itself a title.
+fn window_conf() -> Conf {
+    let title = Options::parse()
+        .ok()
+        .and_then(|options| Cartridge::load(options.cart).ok())
+        .and_then(|cart| cart.metadata().title);
+    Conf {
+        window_title: title.unwrap_or_else(|| "TAC-70".to_string()),
+        ..Default::default()
+    }
+}
+
+struct Options {
+    cart: PathBuf,
+    /// --deterministic: time() counts emulated frames instead of wall time
+    deterministic: bool,
+    /// --record FILE: saves every frame's input to FILE on exit, --replay FILE plays it back
+    /// on the cart it was recorded with
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    /// --rewind SECONDS: how far back holding F4 goes, 0 turns rewinding off
+    rewind_seconds: usize,
+    /// --budget INSTRUCTIONS: how long a TIC may run before it's stopped, 0 for no limit
+    limits: Limits,
+    /// --reload all|ram|globals|none|off: what the cart keeps when it changes on disk
+    reload: Option<Preserve>,
+}
+
+impl Options {
+    fn parse() -> Result<Self, Box<dyn Error>> {
+        let mut args = env::args().skip(1);
+        let mut cart = None;
+        let mut options = Options {
+            cart: PathBuf::new(),
+            deterministic: false,
+            record: None,
+            replay: None,
+            rewind_seconds: 10,
+            limits: Limits::default(),
+            reload: Some(Preserve::ALL),
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--deterministic" => options.deterministic = true,
+                "--record" => options.record = Some(PathBuf::from(value(&mut args, "--record")?)),
+                "--replay" => options.replay = Some(PathBuf::from(value(&mut args, "--replay")?)),
+                "--rewind" => options.rewind_seconds = value(&mut args, "--rewind")?.parse()?,
+                "--budget" => {
+                    options.limits.instructions = match value(&mut args, "--budget")?.parse()? {
+                        0 => None,
+                        instructions => Some(instructions),
+                    }
+                }
+                "--reload" => {
+                    options.reload = match value(&mut args, "--reload")?.as_str() {
+                        "all" => Some(Preserve::ALL),
+                        "ram" => Some(Preserve {
+                            ram: true,
+                            globals: false,
+                        }),
+                        "globals" => Some(Preserve {
+                            ram: false,
+                            globals: true,
+                        }),
+                        "none" => Some(Preserve::NOTHING),
+                        "off" => None,
+                        what => return Err(format!("can't --reload {}", what).into()),
+                    }
+                }
+                _ => cart = Some(arg),
+            }
+        }
+        options.cart = PathBuf::from(cart.ok_or("usage: tac_front [options] CART")?);
+        Ok(options)
+    }
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    let Options {
+        cart: cartridge_path,
+        deterministic,
+        record: record_path,
+        replay: replay_path,
+        rewind_seconds,
+        limits,
+        reload,
+    } = Options::parse()?;
+
+    println!("Loading {}..", cartridge_path.display());
+    let (tac, meta, cart_hash) = load(&cartridge_path)?;
+
+    let replay = match replay_path {
+        Some(path) => Some(Replay::read(BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+    if let Some(replay) = &replay {
+        if replay.cart_hash != cart_hash {
+            return Err("the replay was recorded with a different cart".into());
+        }
+    }
+    // pmem from other runs would make the recording play back differently
+    let mut pmem = match (&record_path, &replay) {
+        (None, None) => PmemFile::new(&meta, cart_hash),
+        _ => None,
+    };
+    // in RAM before the cart's code runs, which may read it
+    if let Some(pmem) = &mut pmem {
+        pmem.restore(&tac);
+    }
+
+    // replays only play back the same with time() counting frames
+    let deterministic = deterministic || record_path.is_some() || replay.is_some();
+    let mut runtime = start(
+        tac,
+        replay.as_ref().map(|replay| replay.seed),
+        deterministic,
+        limits,
+    )?;
+
+    let mut recording = record_path.map(|path| (path, Replay::new(cart_hash, runtime.seed())));
+    if recording.is_some() {
+        // window close must go through the loop to save the recording
+        prevent_quit();
+    }
+
+    // set when the cart's code fails, until it's restarted
+    let mut crashed = false;
+    // set while F4 steps back, until the cart is settled on a keyframe
+    let mut rewound = false;
+    if let Err(e) = runtime.boot() {
+        crash::draw(&e, &mut runtime.state());
+        crashed = true;
+    }
+
+    // a recording or replay only holds for the cart it started with
+    let mut watcher = match (reload, &recording, &replay) {
+        (Some(preserve), None, None) => Some((Watcher::new(&cartridge_path), preserve)),
+        _ => None,
+    };
+
+    let mut capture = Capture::new(&cartridge_path);
+    let states = SaveStates::new(&cartridge_path);
+    let mut scheduler = FrameScheduler::default();
+    // a recording or replay can't go on from a frame whose globals weren't kept
+    let exact = recording.is_some() || replay.is_some();
+    let mut rewind = match rewind_seconds {
+        0 => None,
+        seconds => Some(Rewind::new(seconds * FrameScheduler::FPS as usize)),
+    };
+    record_rewind(&mut rewind, &runtime, exact);
+
+    // one texture for the whole session, refreshed in place every frame
+    let mut image = Image::gen_image_color(Screen::WIDTH as u16, Screen::HEIGHT as u16, BLACK);
+    let tex = Texture2D::from_image(&image);
+    tex.set_filter(FilterMode::Nearest);
 
-    runtime.boot().unwrap();
     loop {
-        runtime.step().unwrap();
+        if is_quit_requested() {
+            save_recording(&recording);
+            break;
+        }
 
         let (width, height) = (screen_width(), screen_height());
 
@@ -31,62 +212,227 @@ async fn main() {
             ((height - Screen::HEIGHT as f32 * upscale) / 2.0).ceil(),
         );
 
-        let state = runtime.state();
-        let gamepads = state.gamepads();
-
-        gamepads.player(0).set_btn(0, is_key_down(KeyCode::Up));
-        gamepads.player(0).set_btn(1, is_key_down(KeyCode::Down));
-        gamepads.player(0).set_btn(2, is_key_down(KeyCode::Left));
-        gamepads.player(0).set_btn(3, is_key_down(KeyCode::Right));
-        gamepads.player(0).set_btn(4, is_key_down(KeyCode::Z));
-        gamepads.player(0).set_btn(5, is_key_down(KeyCode::X));
-        gamepads.player(0).set_btn(6, is_key_down(KeyCode::A));
-        gamepads.player(0).set_btn(7, is_key_down(KeyCode::S));
-
-        let (mx, my) = mouse_position();
-        let (ml, mm, mr) = (
-            is_mouse_button_down(MouseButton::Left),
-            is_mouse_button_down(MouseButton::Middle),
-            is_mouse_button_down(MouseButton::Right),
-        );
-        let (scrollx, scrolly) = mouse_wheel();
-        let (mx, my) = (
-            ((mx - offx) / upscale).max(0.0) as u8,
-            ((my - offy) / upscale).max(0.0) as u8,
+        input::poll(
+            &runtime.state(),
+            &Layout {
+                offx,
+                offy,
+                upscale,
+            },
+            meta.input,
         );
 
-        state.mouse().set(
-            mx,
-            my,
-            ml,
-            mm,
-            mr,
-            scrollx.round() as i8,
-            scrolly.round() as i8,
-        );
+        if crashed && is_key_pressed(KeyCode::R) {
+            println!("Restarting..");
+            // a recording or replay goes on with the same random numbers
+            let seed = (recording.is_some() || replay.is_some()).then(|| runtime.seed());
+            match load(&cartridge_path).and_then(|(tac, _, _)| {
+                if let Some(pmem) = &mut pmem {
+                    pmem.restore(&tac);
+                }
+                start(tac, seed, deterministic, limits)
+            }) {
+                Ok(restarted) => {
+                    runtime = restarted;
+                    crashed = false;
+                    if let Err(e) = runtime.boot() {
+                        crash::draw(&e, &mut runtime.state());
+                        crashed = true;
+                    }
+                    if let Some((_, recording)) = &mut recording {
+                        recording.truncate(0);
+                    }
+                }
+                Err(e) => crash::draw(e.as_ref(), &mut runtime.state()),
+            }
+            if let Some(buffer) = &mut rewind {
+                buffer.clear();
+            }
+            record_rewind(&mut rewind, &runtime, exact);
+        }
+
+        if let Some((watcher, preserve)) = &mut watcher {
+            if watcher.changed() {
+                println!("Reloading {}..", cartridge_path.display());
+                let result = load(&cartridge_path).and_then(|(tac, _, _)| {
+                    // the new RAM doesn't have it, but the cart's pmem must not be lost
+                    if let Some(pmem) = &mut pmem {
+                        pmem.sync(&runtime.state());
+                        pmem.restore(&tac);
+                    }
+                    Ok(runtime.reload(tac, *preserve)?)
+                });
+                crashed = false;
+                if let Err(e) = result {
+                    crash::draw(e.as_ref(), &mut runtime.state());
+                    crashed = true;
+                }
+                // the frames recorded so far belong to the old code
+                if let Some(buffer) = &mut rewind {
+                    buffer.clear();
+                }
+                record_rewind(&mut rewind, &runtime, exact);
+            }
+        }
+
+        // run as many frames as are due at 60 Hz, however fast we are rendering
+        let rewinding = is_key_down(KeyCode::F4);
+        if rewound && !rewinding {
+            rewound = false;
+            // play on from a keyframe, a state the recording plays back to
+            if let Some(buffer) = &mut rewind {
+                match buffer.settle(&mut runtime) {
+                    Ok(stepped) => crashed &= !stepped,
+                    Err(e) => {
+                        eprintln!("Rewinding failed: {}", e);
+                        rewind = None;
+                    }
+                }
+            }
+            if let Some((_, recording)) = &mut recording {
+                recording.truncate(runtime.frame() as usize);
+            }
+        }
+        for _ in 0..scheduler.advance(Duration::from_secs_f32(get_frame_time())) {
+            if let (true, Some(buffer)) = (rewinding, &mut rewind) {
+                match buffer.step_back(&mut runtime) {
+                    // back to before the error
+                    Ok(stepped) => crashed &= !stepped,
+                    Err(e) => {
+                        eprintln!("Rewinding failed: {}", e);
+                        rewind = None;
+                    }
+                }
+                // the recording goes on from the frame we are back at
+                if let Some((_, recording)) = &mut recording {
+                    recording.truncate(runtime.frame() as usize);
+                }
+                rewound = true;
+                continue;
+            }
+
+            if crashed {
+                continue;
+            }
+
+            let frame = runtime.frame() as usize;
+            if let Some(replay) = &replay {
+                match replay.frame(frame) {
+                    Some(input) => runtime.state().set_input_state(input),
+                    None if frame == replay.len() => println!("Replay finished"),
+                    None => {}
+                }
+            }
+            if let Some((_, recording)) = &mut recording {
+                recording.push(runtime.state().input_state());
+            }
+
+            if let Err(e) = runtime.step() {
+                save_recording(&recording);
+                crash::draw(&e, &mut runtime.state());
+                crashed = true;
+                continue;
+            }
+            record_rewind(&mut rewind, &runtime, exact);
+            capture.record(&runtime.state());
+        }
+        if let Some(pmem) = &mut pmem {
+            pmem.sync(&runtime.state());
+        }
+        capture.hotkeys(&runtime.state());
+        if states.hotkeys(&mut runtime) {
+            crashed = false;
+        }
 
         // ==== DRAW ====
         clear_background(BLACK);
+        {
+            let state = runtime.state();
+            state
+                .screen()
+                .render_into(&state.palette(), image.bytes.as_rgba_mut());
+            tex.update(&image);
+            draw_texture_ex(
+                tex,
+                offx,
+                offy,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(Vec2::new(
+                        Screen::WIDTH as f32 * upscale,
+                        Screen::HEIGHT as f32 * upscale,
+                    )),
+                    ..Default::default()
+                },
+            );
+        }
 
-        let screen = state.screen().to_rgba(&state.palette());
-        let screen = screen.as_bytes();
-
-        let tex = Texture2D::from_rgba8(Screen::WIDTH as u16, Screen::HEIGHT as u16, screen);
-        tex.set_filter(FilterMode::Nearest);
-        draw_texture_ex(
-            tex,
-            offx,
-            offy,
-            WHITE,
-            DrawTextureParams {
-                dest_size: Some(Vec2::new(
-                    Screen::WIDTH as f32 * upscale,
-                    Screen::HEIGHT as f32 * upscale,
-                )),
-                ..Default::default()
-            },
-        );
+        if capture.recording() {
+            draw_circle(
+                offx + 4.0 * upscale,
+                offy + 4.0 * upscale,
+                2.0 * upscale,
+                RED,
+            );
+        }
 
         next_frame().await
     }
+    Ok(())
+}
+
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} needs a value", option))
+}
+
+/// Reads the cart at `path`, see [`TAC70::from_file`], and hashes it to tell replays
+/// of it apart. Fails for carts in languages other than Lua.
+fn load(path: &Path) -> Result<(TAC70, CartMetadata, u64), Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let tac = TAC70::from_file(path, &bytes)?;
+    let meta = CartMetadata::parse(&tac.code);
+    if meta.script != Language::Lua {
+        return Err(format!("{} carts are not supported, only lua", meta.script).into());
+    }
+    Ok((tac, meta, Replay::hash(&bytes)))
+}
+
+/// Loads the cart's code into a fresh runtime. `seed` fixes `math.random`'s sequence.
+fn start(
+    tac: TAC70,
+    seed: Option<u64>,
+    deterministic: bool,
+    limits: Limits,
+) -> Result<TAC70Runtime, Box<dyn Error>> {
+    let mut runtime = match seed {
+        Some(seed) => TAC70Runtime::with_seed(tac, seed),
+        None => TAC70Runtime::new(tac),
+    }?;
+    runtime.set_deterministic(deterministic);
+    runtime.set_limits(limits)?;
+    Ok(runtime)
+}
+
+fn record_rewind(rewind: &mut Option<Rewind>, runtime: &TAC70Runtime, exact: bool) {
+    if let Some(Err(e)) = rewind.as_mut().map(|buffer| buffer.record(runtime)) {
+        if exact {
+            eprintln!("Rewinding off: {}", e);
+            *rewind = None;
+        } else {
+            eprintln!("Rewinding RAM only: {}", e);
+        }
+    }
+}
+
+fn save_recording(recording: &Option<(PathBuf, Replay)>) {
+    if let Some((path, replay)) = recording {
+        let result = File::create(path)
+            .map_err(Into::into)
+            .and_then(|file| replay.write(BufWriter::new(file)));
+        match result {
+            Ok(()) => println!("Saved replay {} ({} frames)", path.display(), replay.len()),
+            Err(e) => eprintln!("Saving replay failed: {}", e),
+        }
+    }
 }
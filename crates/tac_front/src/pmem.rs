@@ -0,0 +1,72 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use tac_cart::CartMetadata;
+use tac_core::{Pmem, TAC70};
+
+/// Keeps pmem on disk between runs, under the cart's `saveid`, or its hash if it has
+/// none, like TIC-80 does.
+pub struct PmemFile {
+    path: PathBuf,
+    saved: Vec<u32>,
+}
+
+impl PmemFile {
+    /// `None` if there's nowhere to keep it.
+    pub fn new(meta: &CartMetadata, cart_hash: u64) -> Option<Self> {
+        let key = match &meta.saveid {
+            Some(id) => id
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                    true => c,
+                    false => '_',
+                })
+                .collect(),
+            None => format!("{:016x}", cart_hash),
+        };
+        Some(Self {
+            path: data_dir()?.join("pmem").join(key),
+            saved: vec![0; Pmem::SIZE],
+        })
+    }
+
+    /// Puts the saved pmem into the cart's RAM, zeroes if there's none yet.
+    pub fn restore(&mut self, tac: &TAC70) {
+        if let Ok(bytes) = fs::read(&self.path) {
+            for (value, bytes) in self.saved.iter_mut().zip(bytes.chunks_exact(4)) {
+                *value = u32::from_le_bytes(bytes.try_into().unwrap());
+            }
+        }
+        let pmem = tac.pmem();
+        for (index, &value) in self.saved.iter().enumerate() {
+            pmem.set(index, value);
+        }
+    }
+
+    /// Writes pmem to disk if the cart changed it.
+    pub fn sync(&mut self, tac: &TAC70) {
+        let pmem = tac.pmem();
+        let current: Vec<u32> = (0..Pmem::SIZE).map(|i| pmem.get(i).unwrap()).collect();
+        if current == self.saved {
+            return;
+        }
+        let bytes: Vec<u8> = current.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let result = fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| fs::write(&self.path, bytes));
+        if let Err(e) = result {
+            eprintln!("Saving pmem to {} failed: {}", self.path.display(), e);
+        }
+        self.saved = current;
+    }
+}
+
+fn data_dir() -> Option<PathBuf> {
+    let base = match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+        (Some(data), _) => PathBuf::from(data),
+        (None, Some(home)) => Path::new(&home).join(".local/share"),
+        (None, None) => PathBuf::from(env::var_os("APPDATA")?),
+    };
+    Some(base.join("tac-70"))
+}
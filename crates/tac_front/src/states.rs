@@ -0,0 +1,51 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use tac_runtime::TAC70Runtime;
+
+/// F5 saves the state of the running cart next to it, F6 brings it back.
+pub struct SaveStates {
+    path: PathBuf,
+}
+
+impl SaveStates {
+    pub fn new(cart_path: &Path) -> Self {
+        Self {
+            path: cart_path.with_extension("state"),
+        }
+    }
+
+    /// Returns whether a state was loaded.
+    pub fn hotkeys(&self, runtime: &mut TAC70Runtime) -> bool {
+        if is_key_pressed(KeyCode::F5) {
+            match self.save(runtime) {
+                Ok(()) => println!("Saved state to {}", self.path.display()),
+                Err(e) => eprintln!("Saving state failed: {}", e),
+            }
+        }
+        if is_key_pressed(KeyCode::F6) {
+            match self.load(runtime) {
+                Ok(()) => {
+                    println!("Loaded state from {}", self.path.display());
+                    return true;
+                }
+                Err(e) => eprintln!("Loading state failed: {}", e),
+            }
+        }
+        false
+    }
+
+    fn save(&self, runtime: &TAC70Runtime) -> Result<(), Box<dyn Error>> {
+        fs::write(&self.path, runtime.save_state()?)?;
+        Ok(())
+    }
+
+    fn load(&self, runtime: &mut TAC70Runtime) -> Result<(), Box<dyn Error>> {
+        runtime.load_state(&fs::read(&self.path)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use macroquad::prelude::*;
+
+/// Notices when a file changes on disk, by polling its modification time.
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: f64,
+}
+
+impl Watcher {
+    /// Seconds between two looks at the file.
+    const INTERVAL: f64 = 0.5;
+
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            next_check: get_time() + Self::INTERVAL,
+        }
+    }
+
+    /// Whether the file was written to since the last time it changed. Editors that
+    /// save by deleting and recreating it are waited for until it's back.
+    pub fn changed(&mut self) -> bool {
+        let now = get_time();
+        if now < self.next_check {
+            return false;
+        }
+        self.next_check = now + Self::INTERVAL;
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
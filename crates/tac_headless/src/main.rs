@@ -0,0 +1,264 @@
+//! Runs a cart without a window, for CI.
+//!
+//! ```text
+//! tac_headless <cart.tic> [--frames N] [--input FILE | --replay FILE] [--seed N]
+//!              [--record FILE] [--screenshot FILE.png] [--gif FILE.gif] [--ram FILE]
+//!              [--trace FILE]
+//! ```
+//!
+//! Boots the cart, runs `TIC` N times (60 by default) feeding it the input script,
+//! then writes whatever outputs were asked for; `--gif` records every frame.
+//! Outputs are written even when the cart errors, so they show the state it failed
+//! in. `time()` counts emulated frames and `math.random` starts from `--seed` (0 by
+//! default), so runs are reproducible.
+//!
+//! `--replay` plays back a replay recorded by tac_front or `--record`, for as many
+//! frames as it holds unless `--frames` says otherwise, with the seed it was
+//! recorded with. The cart must be the one it was recorded on.
+//!
+//! Exit codes: 0 on success, 1 when the cart raised a Lua error, 2 for anything
+//! else (bad arguments, unreadable cart, I/O errors).
+
+mod script;
+
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    process::ExitCode,
+    rc::Rc,
+};
+
+use tac_cart::{CartMetadata, Language};
+use tac_core::{GifRecorder, IndexedFrame, Replay, TAC70};
+use tac_runtime::TAC70Runtime;
+
+use script::{Event, Script};
+
+#[derive(Default)]
+struct Options {
+    cart: PathBuf,
+    frames: Option<u32>,
+    input: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    record: Option<PathBuf>,
+    seed: u64,
+    screenshot: Option<PathBuf>,
+    gif: Option<PathBuf>,
+    ram: Option<PathBuf>,
+    trace: Option<PathBuf>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut opts = Options::default();
+        let mut cart = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--frames" => {
+                    opts.frames = Some(value()?.parse().map_err(|e| format!("--frames: {}", e))?)
+                }
+                "--seed" => opts.seed = value()?.parse().map_err(|e| format!("--seed: {}", e))?,
+                "--input" => opts.input = Some(value()?.into()),
+                "--replay" => opts.replay = Some(value()?.into()),
+                "--record" => opts.record = Some(value()?.into()),
+                "--screenshot" => opts.screenshot = Some(value()?.into()),
+                "--gif" => opts.gif = Some(value()?.into()),
+                "--ram" => opts.ram = Some(value()?.into()),
+                "--trace" => opts.trace = Some(value()?.into()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if cart.is_none() => cart = Some(arg.into()),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        opts.cart = cart.ok_or("no cartridge given")?;
+        if opts.input.is_some() && opts.replay.is_some() {
+            return Err("--input and --replay can't be used together".into());
+        }
+        Ok(opts)
+    }
+}
+
+enum Failure {
+    /// The cart itself failed: syntax error, error in `BOOT` or `TIC`...
+    Lua(String),
+    Setup(Box<dyn Error>),
+}
+
+impl<E: Into<Box<dyn Error>>> From<E> for Failure {
+    fn from(e: E) -> Self {
+        Failure::Setup(e.into())
+    }
+}
+
+fn main() -> ExitCode {
+    let opts = match Options::parse(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: tac_headless <cart.tic> [--frames N] [--input FILE | --replay FILE] \
+                 [--seed N] [--record FILE] [--screenshot FILE.png] [--gif FILE.gif] \
+                 [--ram FILE] [--trace FILE]"
+            );
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&opts) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Lua(e)) => {
+            eprintln!("{}", e);
+            ExitCode::from(1)
+        }
+        Err(Failure::Setup(e)) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(opts: &Options) -> Result<(), Failure> {
+    let script = match &opts.input {
+        Some(path) => Script::parse(&fs::read_to_string(path)?)?,
+        None => Script::default(),
+    };
+    let replay = match &opts.replay {
+        Some(path) => Some(Replay::read(BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+    let bytes = fs::read(&opts.cart)?;
+    let cart_hash = Replay::hash(&bytes);
+    if let Some(replay) = &replay {
+        if replay.cart_hash != cart_hash {
+            return Err("the replay was recorded with a different cart".into());
+        }
+    }
+    let frames = match (opts.frames, &replay) {
+        (Some(frames), _) => frames,
+        (None, Some(replay)) => replay.len() as u32,
+        (None, None) => 60,
+    };
+    let seed = replay.as_ref().map_or(opts.seed, |r| r.seed);
+
+    let tac = TAC70::from_file(&opts.cart, &bytes)?;
+    let language = CartMetadata::parse(&tac.code).script;
+    if language != Language::Lua {
+        return Err(format!("{} carts are not supported, only lua", language).into());
+    }
+    let mut runtime = TAC70Runtime::with_seed(tac, seed).map_err(|e| Failure::Lua(e.report()))?;
+
+    runtime.set_deterministic(true);
+
+    let trace = Rc::new(RefCell::new(String::new()));
+    if opts.trace.is_some() {
+        let trace = trace.clone();
+        runtime.set_trace(move |msg| {
+            let mut trace = trace.borrow_mut();
+            trace.push_str(msg);
+            trace.push('\n');
+        });
+    }
+
+    let mut recorder = match &opts.gif {
+        Some(path) => {
+            let palette = capture(&mut runtime).palette;
+            Some(GifRecorder::new(
+                BufWriter::new(File::create(path)?),
+                1,
+                palette,
+            )?)
+        }
+        None => None,
+    };
+
+    let mut recording = Replay::new(cart_hash, seed);
+    let result = play(
+        &mut runtime,
+        frames,
+        |frame, state| {
+            match &replay {
+                Some(replay) => {
+                    if let Some(input) = replay.frame(frame as usize) {
+                        state.set_input_state(input);
+                    }
+                }
+                None => apply(&script, frame, state),
+            }
+            recording.push(state.input_state());
+        },
+        |runtime| {
+            if let Some(recorder) = &mut recorder {
+                recorder.push(&capture(runtime))?;
+            }
+            Ok(())
+        },
+    );
+    drop(recorder);
+
+    if let Some(path) = &opts.record {
+        recording.write(BufWriter::new(File::create(path)?))?;
+    }
+
+    if let Some(path) = &opts.trace {
+        fs::write(path, trace.borrow().as_bytes())?;
+    }
+    if let Some(path) = &opts.screenshot {
+        capture(&mut runtime).write_png(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = &opts.ram {
+        let state = runtime.state();
+        let ram: Vec<u8> = state.mem.iter().map(|b| b.get()).collect();
+        fs::write(path, ram)?;
+    }
+
+    result
+}
+
+/// Runs the cart, calling `before_frame` to set up the input of every `TIC` and
+/// `after_frame` once it ran.
+fn play(
+    runtime: &mut TAC70Runtime,
+    frames: u32,
+    mut before_frame: impl FnMut(u32, &TAC70),
+    mut after_frame: impl FnMut(&mut TAC70Runtime) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Failure> {
+    runtime.boot().map_err(|e| Failure::Lua(e.report()))?;
+    for frame in 0..frames {
+        before_frame(frame, &runtime.state());
+        runtime
+            .step()
+            .map_err(|e| Failure::Lua(format!("frame {}: {}", frame, e.report())))?;
+        after_frame(runtime)?;
+    }
+    Ok(())
+}
+
+fn apply(script: &Script, frame: u32, state: &TAC70) {
+    for event in script.events_at(frame) {
+        match *event {
+            Event::Buttons { player, held } => {
+                let gamepads = state.gamepads();
+                for btn in 0..8 {
+                    gamepads.player(player).set_btn(btn, held & (1 << btn) != 0);
+                }
+            }
+            Event::Mouse {
+                x,
+                y,
+                left,
+                middle,
+                right,
+            } => state.mouse().set(x, y, left, middle, right, 0, 0),
+        }
+    }
+}
+
+fn capture(runtime: &mut TAC70Runtime) -> IndexedFrame {
+    let state = runtime.state();
+    IndexedFrame::capture(&state.screen(), &state.palette())
+}
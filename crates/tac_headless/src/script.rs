@@ -0,0 +1,164 @@
+//! Scripted input: one event per line, applied right before the given frame runs
+//! and held until replaced.
+//!
+//! ```text
+//! # <frame> p<player> [up|down|left|right|a|b|x|y]...
+//! 0 p0 right
+//! 30 p0 right a
+//! 45 p0
+//! # <frame> mouse <x> <y> [left|middle|right]...
+//! 60 mouse 120 68 left
+//! ```
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Bit `n` set means button `n` (as in `btn(n)`) is held.
+    Buttons { player: u8, held: u8 },
+    Mouse {
+        x: u8,
+        y: u8,
+        left: bool,
+        middle: bool,
+        right: bool,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Script {
+    /// Sorted by frame.
+    events: Vec<(u32, Event)>,
+}
+
+const BUTTONS: [&str; 8] = ["up", "down", "left", "right", "a", "b", "x", "y"];
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let event =
+                Self::parse_line(line).map_err(|e| format!("input line {}: {}", n + 1, e))?;
+            events.push(event);
+        }
+        events.sort_by_key(|(frame, _)| *frame);
+        Ok(Self { events })
+    }
+
+    fn parse_line(line: &str) -> Result<(u32, Event), String> {
+        let mut words = line.split_whitespace();
+        let frame = words
+            .next()
+            .unwrap()
+            .parse::<u32>()
+            .map_err(|e| format!("bad frame: {}", e))?;
+        let target = words.next().ok_or("missing p<player> or mouse")?;
+
+        if target == "mouse" {
+            let mut coord = |name| {
+                words
+                    .next()
+                    .ok_or(format!("missing mouse {}", name))?
+                    .parse::<u8>()
+                    .map_err(|e| format!("bad mouse {}: {}", name, e))
+            };
+            let (x, y) = (coord("x")?, coord("y")?);
+            let (mut left, mut middle, mut right) = (false, false, false);
+            for word in words {
+                match word {
+                    "left" => left = true,
+                    "middle" => middle = true,
+                    "right" => right = true,
+                    _ => return Err(format!("unknown mouse button {}", word)),
+                }
+            }
+            return Ok((
+                frame,
+                Event::Mouse {
+                    x,
+                    y,
+                    left,
+                    middle,
+                    right,
+                },
+            ));
+        }
+
+        let player = target
+            .strip_prefix('p')
+            .and_then(|p| p.parse::<u8>().ok())
+            .filter(|&p| p < 4)
+            .ok_or(format!("expected p0..p3 or mouse, got {}", target))?;
+        let mut held = 0;
+        for word in words {
+            let btn = BUTTONS
+                .iter()
+                .position(|&b| b == word)
+                .ok_or(format!("unknown button {}", word))?;
+            held |= 1 << btn;
+        }
+        Ok((frame, Event::Buttons { player, held }))
+    }
+
+    /// Events that take effect right before `frame` runs.
+    pub fn events_at(&self, frame: u32) -> impl Iterator<Item = &Event> {
+        self.events
+            .iter()
+            .filter(move |(f, _)| *f == frame)
+            .map(|(_, e)| e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let script = Script::parse(
+            "# comment\n\
+             30 p1 right a\n\
+             0 p0 up # trailing comment\n\
+             \n\
+             30 mouse 10 20 left right\n",
+        )
+        .unwrap();
+        assert_eq!(
+            script.events_at(0).collect::<Vec<_>>(),
+            [&Event::Buttons {
+                player: 0,
+                held: 0b1
+            }]
+        );
+        assert_eq!(
+            script.events_at(30).collect::<Vec<_>>(),
+            [
+                &Event::Buttons {
+                    player: 1,
+                    held: 0b11000
+                },
+                &Event::Mouse {
+                    x: 10,
+                    y: 20,
+                    left: true,
+                    middle: false,
+                    right: true
+                }
+            ]
+        );
+        assert_eq!(script.events_at(1).count(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Script::parse("0 p0 up\n1 p4").unwrap_err(),
+            "input line 2: expected p0..p3 or mouse, got p4"
+        );
+        assert!(Script::parse("x p0").is_err());
+        assert!(Script::parse("0 p0 jump").is_err());
+        assert!(Script::parse("0 mouse 300 0").is_err());
+    }
+}
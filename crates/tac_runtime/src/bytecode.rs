@@ -0,0 +1,165 @@
+//! Finds the functions a chunk defines in its Lua 5.3 bytecode, so that save states
+//! can name a function of the cart by where it is in the cart's code rather than
+//! carry its bytecode: Lua runs bytecode without checking it, so loading some from a
+//! file could crash the host.
+//!
+//! The format is written by `ldump.c`: a header, the number of upvalues of the
+//! chunk, then the chunk's function with the functions it defines nested in it.
+
+use std::ops::Range;
+
+/// Every function of a chunk, the chunk first then depth first in the order they're
+/// defined.
+pub(crate) struct Prototypes {
+    dump: Vec<u8>,
+    header: Range<usize>,
+    /// The chunk's name, which its functions are loaded under.
+    source: Range<usize>,
+    /// The bytecode of each function after its name, and its number of upvalues.
+    functions: Vec<(Range<usize>, u8)>,
+}
+
+impl Prototypes {
+    /// Reads the functions out of `dump`, the unstripped `string.dump` of a chunk.
+    /// `None` if it isn't Lua 5.3 bytecode.
+    pub(crate) fn new(dump: Vec<u8>) -> Option<Self> {
+        let mut reader = Reader::open(&dump)?;
+        let header = 0..reader.pos - 1;
+        let start = reader.pos;
+        reader.string()?;
+        let source = start..reader.pos;
+        let mut functions = vec![];
+        reader.function(&mut functions)?;
+        Some(Self {
+            header,
+            source,
+            functions,
+            dump,
+        })
+    }
+
+    /// Which of the functions `dump` is, the unstripped `string.dump` of a function.
+    pub(crate) fn find(&self, dump: &[u8]) -> Option<u32> {
+        let mut reader = Reader::open(dump)?;
+        reader.string()?;
+        let body = &dump[reader.pos..];
+        self.functions
+            .iter()
+            .position(|(range, _)| &self.dump[range.clone()] == body)
+            .map(|index| index as u32)
+    }
+
+    /// Function `index` as a chunk of its own, to load as a closure of it.
+    pub(crate) fn chunk(&self, index: u32) -> Option<Vec<u8>> {
+        let (body, upvalues) = self.functions.get(index as usize)?;
+        let mut chunk = self.dump[self.header.clone()].to_vec();
+        chunk.push(*upvalues);
+        chunk.extend(&self.dump[self.source.clone()]);
+        chunk.extend(&self.dump[body.clone()]);
+        Some(chunk)
+    }
+}
+
+struct Reader<'a> {
+    dump: &'a [u8],
+    pos: usize,
+    /// Sizes of `int`, `size_t`, instructions, integers and floats, from the header.
+    sizes: [usize; 5],
+}
+
+impl<'a> Reader<'a> {
+    /// Reads the header and the number of upvalues of the chunk.
+    fn open(dump: &'a [u8]) -> Option<Self> {
+        if dump.get(..5)? != b"\x1bLua\x53" {
+            return None;
+        }
+        let sizes = dump.get(12..17)?;
+        let mut reader = Self {
+            dump,
+            pos: 17,
+            sizes: [0, 1, 2, 3, 4].map(|i| sizes[i] as usize),
+        };
+        // an integer and a float checking their encoding
+        reader.take(reader.sizes[3] + reader.sizes[4])?;
+        reader.byte()?;
+        Some(reader)
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.dump.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    /// A little endian number of `size` bytes.
+    fn number(&mut self, size: usize) -> Option<usize> {
+        let bytes = self.take(size)?;
+        let n = bytes.iter().rev().fold(0u64, |n, &b| n << 8 | b as u64);
+        usize::try_from(n).ok()
+    }
+
+    fn int(&mut self) -> Option<usize> {
+        self.number(self.sizes[0])
+    }
+
+    /// `count` ints, each followed by `size` bytes.
+    fn array(&mut self, size: usize) -> Option<()> {
+        let count = self.int()?;
+        self.take(count.checked_mul(size)?)?;
+        Some(())
+    }
+
+    /// Skips a string: its length plus one in a byte, or in a `size_t` after 0xFF.
+    fn string(&mut self) -> Option<()> {
+        let size = match self.byte()? {
+            0xFF => self.number(self.sizes[1])?,
+            size => size as usize,
+        };
+        self.take(size.saturating_sub(1))?;
+        Some(())
+    }
+
+    /// Skips a function and the ones nested in it, adding them to `functions`.
+    fn function(&mut self, functions: &mut Vec<(Range<usize>, u8)>) -> Option<()> {
+        let start = self.pos;
+        let index = functions.len();
+        functions.push((start..start, 0));
+
+        // first and last line, parameters, vararg flag, stack size
+        self.take(self.sizes[0] * 2 + 3)?;
+        self.array(self.sizes[2])?;
+        for _ in 0..self.int()? {
+            match self.byte()? {
+                // nil, boolean, float, integer, short and long string
+                0 => {}
+                1 => self.take(1).map(drop)?,
+                3 => self.take(self.sizes[4]).map(drop)?,
+                19 => self.take(self.sizes[3]).map(drop)?,
+                4 | 20 => self.string()?,
+                _ => return None,
+            }
+        }
+        let upvalues = self.int()?;
+        self.take(upvalues.checked_mul(2)?)?;
+        for _ in 0..self.int()? {
+            self.string()?;
+            self.function(functions)?;
+        }
+
+        // debug info: line of each instruction, locals and upvalue names
+        self.array(self.sizes[0])?;
+        for _ in 0..self.int()? {
+            self.string()?;
+            self.take(self.sizes[0] * 2)?;
+        }
+        for _ in 0..self.int()? {
+            self.string()?;
+        }
+        functions[index] = (start..self.pos, u8::try_from(upvalues).ok()?);
+        Some(())
+    }
+}
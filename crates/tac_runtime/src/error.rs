@@ -0,0 +1,216 @@
+use mlua::prelude::*;
+use thiserror::Error;
+
+use crate::limits::OutOfTime;
+
+/// Name the cart's code is loaded under, which Lua puts in front of line numbers.
+pub(crate) const CHUNK_NAME: &str = "cart";
+
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    /// The cart's code didn't compile, or raised an error while running.
+    #[error("{message}")]
+    Lua {
+        message: String,
+        /// Line of the cart's code the error comes from, when Lua knows it.
+        line: Option<u32>,
+        /// The call stack, one frame per line, innermost first.
+        traceback: Option<String>,
+    },
+    /// A call into the cart ran past [`Limits::instructions`](crate::Limits), most
+    /// likely stuck in a loop.
+    #[error("{}ran for over {instructions} instructions without returning", at(.line))]
+    Timeout {
+        instructions: u64,
+        line: Option<u32>,
+        traceback: Option<String>,
+    },
+    /// The cart's Lua heap outgrew [`Limits::memory`](crate::Limits).
+    #[error("the cart ran out of memory")]
+    OutOfMemory,
+    #[error("the cart has no TIC function")]
+    MissingTic,
+    #[error("can't save state: {path} is {what}")]
+    Unsaveable { path: String, what: &'static str },
+    #[error("invalid save state: {0}")]
+    BadState(String),
+}
+
+impl RuntimeError {
+    /// The call stack the error was raised from, one frame per line, innermost first.
+    pub fn traceback(&self) -> Option<&str> {
+        match self {
+            RuntimeError::Lua { traceback, .. } | RuntimeError::Timeout { traceback, .. } => {
+                traceback.as_deref()
+            }
+            _ => None,
+        }
+    }
+
+    /// The error followed by its traceback, if it has one.
+    pub fn report(&self) -> String {
+        match self.traceback() {
+            Some(traceback) => format!(
+                "{}\nstack traceback:\n  {}",
+                self,
+                traceback.replace('\n', "\n  ")
+            ),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl From<LuaError> for RuntimeError {
+    fn from(e: LuaError) -> Self {
+        let (message, traceback) = match e {
+            LuaError::SyntaxError { message, .. } => (message, None),
+            LuaError::MemoryError(_) => return RuntimeError::OutOfMemory,
+            LuaError::CallbackError { traceback, cause } => {
+                // errors raised by the bindings don't say where they were called from
+                let mut cause = cause.as_ref();
+                while let LuaError::CallbackError { cause: inner, .. } = cause {
+                    cause = inner;
+                }
+                match cause {
+                    LuaError::ExternalError(e) => {
+                        if let Some(OutOfTime(instructions)) = e.downcast_ref() {
+                            let traceback = clean_traceback(&traceback);
+                            return RuntimeError::Timeout {
+                                instructions: *instructions,
+                                line: cart_line(&traceback),
+                                traceback: Some(traceback),
+                            };
+                        }
+                    }
+                    LuaError::MemoryError(_) => return RuntimeError::OutOfMemory,
+                    _ => {}
+                }
+                let message = match cart_line(&traceback) {
+                    Some(line) => format!("{}:{}: {}", CHUNK_NAME, line, cause_message(cause)),
+                    None => cause_message(cause),
+                };
+                (message, Some(traceback))
+            }
+            LuaError::RuntimeError(message) => match message.split_once("\nstack traceback:") {
+                Some((message, traceback)) => (
+                    message.to_string(),
+                    Some(format!("stack traceback:{}", traceback)),
+                ),
+                None => (message, None),
+            },
+            e => (e.to_string(), None),
+        };
+        let traceback = traceback.as_deref().map(clean_traceback);
+        let line = cart_line(&message).or_else(|| traceback.as_deref().and_then(cart_line));
+        RuntimeError::Lua {
+            message,
+            line,
+            traceback,
+        }
+    }
+}
+
+/// Strips the header and indentation Lua puts on a traceback.
+fn clean_traceback(traceback: &str) -> String {
+    traceback
+        .trim_start_matches("stack traceback:")
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && *l != "[C]: in ?")
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `cart:<line>: `, to put in front of a message.
+fn at(line: &Option<u32>) -> String {
+    line.map_or(String::new(), |line| format!("{}:{}: ", CHUNK_NAME, line))
+}
+
+fn cause_message(cause: &LuaError) -> String {
+    match cause {
+        LuaError::RuntimeError(message) => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+/// The first `cart:<line>:` in `text`.
+fn cart_line(text: &str) -> Option<u32> {
+    let prefix = format!("{}:", CHUNK_NAME);
+    text.match_indices(&prefix).find_map(|(i, _)| {
+        let rest = &text[i + prefix.len()..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        match rest[digits..].starts_with(':') {
+            true => rest[..digits].parse().ok(),
+            false => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tac_core::TAC70;
+
+    use super::*;
+    use crate::TAC70Runtime;
+
+    fn error(code: &str) -> RuntimeError {
+        let tac = TAC70::new(&[0u8; 0x18000], code.to_string());
+        match TAC70Runtime::with_seed(tac, 0) {
+            Ok(mut rt) => rt.step().unwrap_err(),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let cases = [
+            (
+                "function TIC()\n  error('boom')\nend",
+                "cart:2: boom",
+                Some(2),
+            ),
+            (
+                "function TIC()\n\n  pmem(999)\nend",
+                "cart:3: invalid pmem index 999",
+                Some(3),
+            ),
+            (
+                "x = 1\nx = = 1",
+                "cart:2: unexpected symbol near '='",
+                Some(2),
+            ),
+            // no message to take the line from, but the traceback has it
+            ("function TIC() error({}) end", "", Some(1)),
+        ];
+        for (code, message, line) in cases {
+            match error(code) {
+                RuntimeError::Lua {
+                    message: m,
+                    line: l,
+                    ..
+                } => {
+                    assert!(m.starts_with(message), "{:?} for {:?}", m, code);
+                    assert_eq!(l, line, "{:?}", code);
+                }
+                e => panic!("{:?}", e),
+            }
+        }
+        assert!(matches!(error("x = 1"), RuntimeError::MissingTic));
+    }
+
+    #[test]
+    fn report() {
+        let e = error("function f()\n  error('boom')\nend\nfunction TIC() f() end");
+        let RuntimeError::Lua { traceback, .. } = &e else {
+            panic!("{:?}", e);
+        };
+        let traceback = traceback.as_deref().unwrap();
+        assert!(traceback.contains("cart:2: in function"), "{}", traceback);
+        assert!(traceback.contains("cart:4: in function"), "{}", traceback);
+        assert!(e.report().starts_with("cart:2: boom\nstack traceback:\n  "));
+        assert_eq!(
+            RuntimeError::MissingTic.report(),
+            "the cart has no TIC function"
+        );
+    }
+}
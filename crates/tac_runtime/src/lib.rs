@@ -1,27 +1,91 @@
-use std::{error::Error, time::Instant};
+mod bytecode;
+mod error;
+mod limits;
+mod reload;
+mod rewind;
+mod rng;
+mod scheduler;
+mod state;
+
+use std::{cell::RefCell, rc::Rc};
 
 use mlua::prelude::*;
-use tac_core::{Colorized, PixBuf, Rotated, TAC70};
+use tac_core::{ColorKey, MapRegion, MapTile, PixBuf, TexVertex, Texture, TAC70};
+
+use rng::Rng;
+use scheduler::Clock;
+pub use error::RuntimeError;
+pub use limits::Limits;
+pub use reload::Preserve;
+pub use rewind::Rewind;
+pub use scheduler::FrameScheduler;
+
+/// Receives every message the cart passes to `trace`.
+type TraceSink = Box<dyn FnMut(&str)>;
 
 pub struct TAC70Runtime {
     pub lua_ctx: Lua,
+    budget: Rc<RefCell<limits::Budget>>,
+    rng: Rc<RefCell<Rng>>,
+    trace: Rc<RefCell<Option<TraceSink>>>,
+    clock: Rc<RefCell<Clock>>,
 }
 
 impl TAC70Runtime {
-    pub fn new(tac: TAC70) -> Result<Self, Box<dyn Error>> {
-        let lua = Lua::new_with(
-            LuaStdLib::NONE
-                | LuaStdLib::TABLE
-                | LuaStdLib::STRING
-                | LuaStdLib::MATH
-                | LuaStdLib::UTF8,
-            LuaOptions::new(),
-        )?;
+    /// Loads the cart's code with `math.random` seeded from the clock.
+    pub fn new(tac: TAC70) -> Result<Self, RuntimeError> {
+        Self::create(tac, Rng::from_time())
+    }
+
+    /// Loads the cart's code with `math.random` seeded with `seed`, to replay a run.
+    pub fn with_seed(tac: TAC70, seed: u64) -> Result<Self, RuntimeError> {
+        Self::create(tac, Rng::new(seed))
+    }
+
+    // Lua bindings take their arguments as one big tuple
+    #[allow(clippy::type_complexity)]
+    fn create(tac: TAC70, rng: Rng) -> Result<Self, RuntimeError> {
+        // SAFETY: `debug` is moved out of the globals before any cart code runs, only
+        // save states use it. The only bytecode ever loaded is the cart's own, compiled
+        // by this same Lua when a save state is restored, see `bytecode`
+        let lua = unsafe {
+            Lua::unsafe_new_with(
+                LuaStdLib::NONE
+                    | LuaStdLib::TABLE
+                    | LuaStdLib::STRING
+                    | LuaStdLib::MATH
+                    | LuaStdLib::UTF8
+                    | LuaStdLib::DEBUG,
+                LuaOptions::new(),
+            )
+        };
 
         let globals = lua.globals();
+        let debug: LuaTable = globals.get("debug")?;
+        lua.set_named_registry_value(state::DEBUG, debug)?;
+        globals.raw_set("debug", LuaNil)?;
+
+        // carts can't reach the host's files, nor load bytecode, which can crash the VM
+        globals.raw_set("dofile", LuaNil)?;
+        globals.raw_set("loadfile", LuaNil)?;
+        let load: LuaFunction = lua
+            .load(
+                "local load = ...
+                return function(chunk, name, _, ...) return load(chunk, name, 't', ...) end",
+            )
+            .set_name("=load")?
+            .call(globals.get::<_, LuaFunction>("load")?)?;
+        globals.raw_set("load", load)?;
 
-        let trace = lua.create_function(|_, msg: String| {
-            println!("TRACE: {}", msg);
+        // what the bindings change besides the console is kept out of the app data,
+        // which `map` holds on to while calling its remap function
+        let sink: Rc<RefCell<Option<TraceSink>>> = Rc::default();
+        let trace_sink = sink.clone();
+        let trace = lua.create_function(move |_, msg: String| {
+            match trace_sink.borrow_mut().as_mut() {
+                Some(sink) => sink(&msg),
+                None => println!("TRACE: {}", msg),
+            }
             Ok(())
         })?;
 
@@ -32,12 +96,14 @@ impl TAC70Runtime {
 
         let mset = lua.create_function(|ctx, (x, y, id): (i32, i32, u8)| {
             let tac = ctx.app_data_ref::<TAC70>().unwrap();
-            Ok(tac.map().set(x, y, id))
+            tac.map().set(x, y, id);
+            Ok(())
         })?;
 
         let cls = lua.create_function(|ctx, pix: Option<u8>| {
             let tac = ctx.app_data_ref::<TAC70>().unwrap();
-            Ok(tac.screen().clear(pix.unwrap_or(0)))
+            tac.screen().clear(pix.unwrap_or(0));
+            Ok(())
         })?;
 
         let rect = lua.create_function(|ctx, (x, y, w, h, pix): (i32, i32, u32, u32, u8)| {
@@ -75,6 +141,41 @@ impl TAC70Runtime {
             },
         )?;
 
+        // more arguments than a tuple can take
+        let ttri = lua.create_function(|ctx, args: LuaMultiValue| {
+            let tac = ctx.app_data_ref::<TAC70>().unwrap();
+            let mut args = args.into_iter();
+            let mut next = || args.next().unwrap_or(LuaNil);
+            let mut corners = [TexVertex::default(); 3];
+            for corner in &mut corners {
+                (corner.x, corner.y) = (ctx.unpack(next())?, ctx.unpack(next())?);
+            }
+            for corner in &mut corners {
+                (corner.u, corner.v) = (ctx.unpack(next())?, ctx.unpack(next())?);
+            }
+            let src = match next() {
+                // older carts pass whether to use the map
+                LuaNil | LuaValue::Boolean(false) => Texture::Tiles,
+                LuaValue::Boolean(true) => Texture::Map,
+                value => match ctx.unpack::<i64>(value)? {
+                    0 => Texture::Tiles,
+                    1 => Texture::Map,
+                    src => {
+                        return Err(LuaError::RuntimeError(format!(
+                            "unsupported texture source {}",
+                            src
+                        )))
+                    }
+                },
+            };
+            let alpha = colorkey(next())?;
+            for corner in &mut corners {
+                corner.z = ctx.unpack::<Option<f64>>(next())?.unwrap_or(0.0);
+            }
+            tac.ttri(corners, src, alpha);
+            Ok(())
+        })?;
+
         let print = lua.create_function(
             |ctx,
              (s, x, y, pix, fixed, scale, smallfont): (
@@ -94,23 +195,63 @@ impl TAC70Runtime {
                     smallfont.unwrap_or(false),
                 );
 
-                let mut cursor = 0;
-                let fixedw = if smallfont { 3 } else { 5 };
-                for c in s.chars() {
-                    let fchar = tac.char(c, smallfont).unwrap();
-                    let advance = if !fixed { fchar.width as i32 } else { fixedw } + 1;
-                    tac.screen().blit(
-                        x + (cursor - if !fixed { fchar.padx } else { 0 }) * scale as i32,
-                        y,
-                        &Colorized(pix, fchar),
-                        Some(0),
-                        false,
-                        false,
+                // returns the width
+                Ok(tac.print(&s, x, y, pix, fixed, scale, smallfont))
+            },
+        )?;
+
+        let font = lua.create_function(
+            |ctx,
+             (s, x, y, alpha, w, h, fixed, scale): (
+                String,
+                i32,
+                i32,
+                LuaValue,
+                Option<i32>,
+                Option<i32>,
+                Option<bool>,
+                Option<u32>,
+            )| {
+                let tac = ctx.app_data_ref::<TAC70>().unwrap();
+                let (alpha, w, h, fixed, scale) = (
+                    colorkey(alpha)?,
+                    w.unwrap_or(8),
+                    h.unwrap_or(8),
+                    fixed.unwrap_or(false),
+                    scale.unwrap_or(1),
+                );
+
+                // glyphs are the foreground sprites, indexed by character code
+                let (mut cursor, mut line) = (0, 0);
+                let mut width = 0;
+                for c in s.bytes() {
+                    if c == b'\n' {
+                        width = width.max(cursor);
+                        cursor = 0;
+                        line += h;
+                        continue;
+                    }
+                    let view = tac.sprite_view(256 + c as u16, 1, 1);
+                    tac.screen().draw_sprite(
+                        x + cursor * scale as i32,
+                        y + line * scale as i32,
+                        &view,
+                        alpha,
                         scale,
                     );
-                    cursor += advance;
+                    let advance = if fixed {
+                        w
+                    } else {
+                        // trim to the rightmost visible column
+                        (0..8)
+                            .rev()
+                            .find(|&i| (0..8).any(|j| !alpha.contains(view.get_pix(i, j))))
+                            .map_or(w, |i| i + 1)
+                            .min(w)
+                    };
+                    cursor += advance + 1;
                 }
-                Ok(cursor) // return width
+                Ok(width.max(cursor) * scale as i32) // return width
             },
         )?;
 
@@ -120,7 +261,7 @@ impl TAC70Runtime {
                 u16,
                 i32,
                 i32,
-                Option<u8>,
+                LuaValue,
                 Option<u32>,
                 LuaValue,
                 Option<u32>,
@@ -134,27 +275,10 @@ impl TAC70Runtime {
                     w.unwrap_or(1),
                     h.unwrap_or(1),
                 );
-                let flip = match flip {
-                    LuaValue::Boolean(b) if b => 1,
-                    LuaValue::Integer(n) => n,
-                    _ => 0,
-                };
-                let (hflip, vflip) = (flip & 0b1 != 0, flip & 0b10 != 0);
-                for i in 0..w {
-                    for j in 0..h {
-                        let px = if hflip { (w - i - 1) * 8 } else { i * 8 } * scale;
-                        let py = if vflip { (h - j - 1) * 8 } else { j * 8 } * scale;
-                        tac.screen().blit(
-                            x + px as i32,
-                            y + py as i32,
-                            &Rotated(rot as u32, tac.sprite(id + (i + j * 16) as u16).unwrap()),
-                            alpha,
-                            hflip,
-                            vflip,
-                            scale,
-                        );
-                    }
-                }
+                let (hflip, vflip) = flip_arg(flip);
+                let view = tac.sprite_view(id, w, h).flip(hflip, vflip).rotate(rot);
+                tac.screen()
+                    .draw_sprite(x, y, &view, colorkey(alpha)?, scale);
                 Ok(())
             },
         )?;
@@ -175,6 +299,48 @@ impl TAC70Runtime {
             Ok((mx, my, ml, mm, mr, scrollx, scrolly))
         })?;
 
+        let key = lua.create_function(|ctx, code: Option<u8>| {
+            let tac = ctx.app_data_ref::<TAC70>().unwrap();
+            let keyboard = tac.keyboard();
+            Ok(match code {
+                Some(code) => keyboard.held(code),
+                None => keyboard.any(),
+            })
+        })?;
+
+        let pmem = lua.create_function(|ctx, (index, value): (usize, Option<u32>)| {
+            let tac = ctx.app_data_ref::<TAC70>().unwrap();
+            let pmem = tac.pmem();
+            match value {
+                Some(value) => pmem.set(index, value),
+                None => pmem.get(index),
+            }
+            .ok_or_else(|| LuaError::RuntimeError(format!("invalid pmem index {}", index)))
+        })?;
+
+        let rng = Rc::new(RefCell::new(rng));
+        let random_rng = rng.clone();
+        let random = lua.create_function(move |_, (m, n): (Option<i64>, Option<i64>)| {
+            let mut rng = random_rng.borrow_mut();
+            let (lo, hi) = match (m, n) {
+                (None, _) => return Ok(LuaValue::Number(rng.next_f64())),
+                (Some(m), None) => (1, m),
+                (Some(m), Some(n)) => (m, n),
+            };
+            if lo > hi {
+                return Err(LuaError::RuntimeError(
+                    "bad argument to 'random' (interval is empty)".into(),
+                ));
+            }
+            Ok(LuaValue::Integer(rng.range(lo, hi)))
+        })?;
+
+        let seed_rng = rng.clone();
+        let randomseed = lua.create_function(move |_, seed: f64| {
+            seed_rng.borrow_mut().reseed(seed as i64 as u64);
+            Ok(())
+        })?;
+
         let pix = lua.create_function(
             |ctx, (x, y, pix): (i32, i32, Option<u8>)| -> LuaResult<Option<u8>> {
                 let tac = ctx.app_data_ref::<TAC70>().unwrap();
@@ -197,60 +363,41 @@ impl TAC70Runtime {
                 Option<i32>,
                 Option<i32>,
                 Option<i32>,
-                Option<u8>,
+                LuaValue,
                 Option<u32>,
                 Option<LuaFunction>,
             )| {
                 let tac = ctx.app_data_ref::<TAC70>().unwrap();
-                let (x, y, w, h, sx, sy) = (
-                    x.unwrap_or(0),
-                    y.unwrap_or(0),
-                    w.unwrap_or(30),
-                    h.unwrap_or(17),
-                    sx.unwrap_or(0),
-                    sy.unwrap_or(0),
-                );
-                let scale = scale.unwrap_or(1); // TODO: use scale
-                for i in 0..w {
-                    for j in 0..h {
-                        let (spr_id, flip, rot) = {
-                            match &remap {
-                                None => (
-                                    tac.map().get(x + i, y + j).unwrap() as u16,
-                                    LuaValue::Nil,
-                                    Option::<i32>::None,
-                                ),
-                                Some(f) => f.call::<_, _>((
-                                    tac.map().get(x + i, y + j).unwrap() as u16,
-                                    x + i,
-                                    y + j,
-                                ))?,
-                            }
-                        };
-
-                        let flip = match flip {
-                            LuaValue::Boolean(b) if b => 1,
-                            LuaValue::Integer(n) => n,
-                            _ => 0,
-                        };
-                        tac.screen().blit(
-                            sx + i * 8 * scale as i32,
-                            sy + j * 8 * scale as i32,
-                            &Rotated(rot.unwrap_or(0) as u32, tac.sprite(spr_id).unwrap()),
-                            alpha,
-                            flip & 0b1 != 0,
-                            flip & 0b10 != 0,
-                            scale,
-                        );
+                let region = MapRegion {
+                    x: x.unwrap_or(0),
+                    y: y.unwrap_or(0),
+                    w: w.unwrap_or(30),
+                    h: h.unwrap_or(17),
+                    sx: sx.unwrap_or(0),
+                    sy: sy.unwrap_or(0),
+                    alpha: colorkey(alpha)?,
+                    scale: scale.unwrap_or(1),
+                };
+                tac.draw_map(&region, |tile, x, y| match &remap {
+                    None => Ok(tile),
+                    Some(f) => {
+                        let (id, flip, rot) =
+                            f.call::<_, (u16, LuaValue, Option<u32>)>((tile.id, x, y))?;
+                        let (hflip, vflip) = flip_arg(flip);
+                        Ok(MapTile {
+                            id,
+                            hflip,
+                            vflip,
+                            rot: rot.unwrap_or(0),
+                        })
                     }
-                }
-                Ok(())
+                })
             },
         )?;
 
-        let start_time = Instant::now();
-        let time =
-            lua.create_function(move |_, _: ()| Ok(start_time.elapsed().as_secs_f64() * 1000.0))?;
+        let clock = Rc::new(RefCell::new(Clock::new()));
+        let time_clock = clock.clone();
+        let time = lua.create_function(move |_, ()| Ok(time_clock.borrow().millis()))?;
 
         globals.set("trace", trace)?;
         globals.set("mset", mset)?;
@@ -264,40 +411,238 @@ impl TAC70Runtime {
         globals.set("rect", rect)?;
         globals.set("rectb", rectb)?;
         globals.set("print", print)?;
+        globals.set("font", font)?;
         globals.set("mouse", mouse)?;
+        globals.set("key", key)?;
+        globals.set("pmem", pmem)?;
         globals.set("line", line)?;
         globals.set("tri", tri)?;
         globals.set("trib", trib)?;
+        globals.set("ttri", ttri)?;
 
+        let math: LuaTable = globals.get("math")?;
+        math.set("random", random)?;
+        math.set("randomseed", randomseed)?;
+
+        drop(math);
         drop(globals);
 
         let code = tac.code.clone();
         lua.set_app_data(tac);
-        lua.load(&code).exec()?;
+        state::snapshot_builtins(&lua)?;
+        let budget = limits::install(&lua, Limits::default())?;
+        limits::budgeted(&lua, &budget, || {
+            lua.load(&code)
+                .set_name(format!("={}", error::CHUNK_NAME))?
+                .exec()
+        })?;
 
-        Ok(Self { lua_ctx: lua })
+        Ok(Self {
+            lua_ctx: lua,
+            budget,
+            rng,
+            trace: sink,
+            clock,
+        })
     }
 
-    pub fn boot(&mut self) -> LuaResult<()> {
+    pub fn boot(&mut self) -> Result<(), RuntimeError> {
         if let Ok(f) = self.lua_ctx.globals().get::<_, LuaFunction>("BOOT") {
-            f.call::<_, _>(())?;
+            limits::budgeted(&self.lua_ctx, &self.budget, || f.call::<_, ()>(()))?;
         }
         Ok(())
     }
 
-    pub fn step(&mut self) -> LuaResult<()> {
-        self.lua_ctx
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        let tic = self
+            .lua_ctx
             .globals()
-            .get::<_, LuaFunction>("TIC")
-            .unwrap()
-            .call::<_, ()>(())?;
+            .get::<_, Option<LuaFunction>>("TIC")?
+            .ok_or(RuntimeError::MissingTic)?;
+        limits::budgeted(&self.lua_ctx, &self.budget, || tic.call::<_, ()>(()))?;
+        self.clock.borrow_mut().frames += 1;
+        Ok(())
+    }
+
+    /// Number of times `TIC` has run.
+    pub fn frame(&self) -> u64 {
+        self.clock.borrow().frames
+    }
+
+    /// In deterministic mode `time()` counts emulated frames (1000/60 ms each)
+    /// instead of wall time, so runs can be reproduced exactly.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.clock.borrow_mut().deterministic = deterministic;
+    }
+
+    /// The seed `math.random` started from, to record alongside a replay. Later calls
+    /// to `math.randomseed` don't change it.
+    pub fn seed(&self) -> u64 {
+        self.rng.borrow().seed
+    }
+
+    /// Bounds the instructions each call into the cart may run and the memory it may
+    /// use. Carts start with [`Limits::default`].
+    pub fn set_limits(&mut self, limits: Limits) -> Result<(), RuntimeError> {
+        self.lua_ctx.set_memory_limit(limits.memory.unwrap_or(0))?;
+        self.budget.borrow_mut().limits = limits;
         Ok(())
     }
 
-    pub fn state(&mut self) -> std::cell::RefMut<TAC70> {
+    pub fn limits(&self) -> Limits {
+        self.budget.borrow().limits
+    }
+
+    /// Sends `trace` output to `sink` instead of stdout.
+    pub fn set_trace(&mut self, sink: impl FnMut(&str) + 'static) {
+        *self.trace.borrow_mut() = Some(Box::new(sink));
+    }
+
+    pub fn state(&mut self) -> std::cell::RefMut<'_, TAC70> {
         self.lua_ctx.app_data_mut().unwrap()
     }
 }
 
+/// Reads a flip argument as (horizontal, vertical): bit 0 flips horizontally, bit 1
+/// vertically. `true` is accepted as a horizontal flip.
+fn flip_arg(value: LuaValue) -> (bool, bool) {
+    let flip = match value {
+        LuaValue::Boolean(b) if b => 1,
+        LuaValue::Integer(n) => n,
+        LuaValue::Number(n) => n as i64,
+        _ => 0,
+    };
+    (flip & 0b1 != 0, flip & 0b10 != 0)
+}
+
+/// Reads a TIC-80 colorkey argument: either a single color or a table of colors.
+/// Anything else, including the default `-1`, makes nothing transparent.
+fn colorkey(value: LuaValue) -> LuaResult<ColorKey> {
+    let color = |v: LuaValue| match v {
+        LuaValue::Integer(n) => u8::try_from(n).ok(),
+        LuaValue::Number(n) if n >= 0.0 => u8::try_from(n as i64).ok(),
+        _ => None,
+    };
+    Ok(match value {
+        LuaValue::Table(t) => {
+            let mut key = ColorKey::NONE;
+            for v in t.sequence_values::<LuaValue>() {
+                if let Some(c) = color(v?) {
+                    key = key.with(c);
+                }
+            }
+            key
+        }
+        v => color(v).map_or(ColorKey::NONE, ColorKey::single),
+    })
+}
+
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    fn runtime(code: &str) -> TAC70Runtime {
+        let mut mem = vec![0u8; 0x18000];
+        // sprite 1 has columns colored 0, 1, 2 ... 7
+        for row in 0..8 {
+            mem[0x4020 + row * 4..0x4020 + row * 4 + 4].copy_from_slice(&[0x10, 0x32, 0x54, 0x76]);
+        }
+        TAC70Runtime::new(TAC70::new(&mem, code.to_string())).unwrap()
+    }
+
+    #[test]
+    fn colorkey_table() {
+        let mut rt = runtime("function TIC() cls(15) spr(1, 0, 0, {1, 3, 5}) end");
+        rt.step().unwrap();
+        let tac = rt.state();
+        let row: Vec<u8> = (0..8).map(|x| tac.screen().get_pix(x, 0)).collect();
+        assert_eq!(row, [0, 15, 2, 15, 4, 15, 6, 7]);
+    }
+
+    #[test]
+    fn colorkey_number() {
+        let mut rt =
+            runtime("function TIC() cls(15) spr(1, 0, 0, 2) map(0, 0, 1, 1, 8, 0, -1) end");
+        rt.step().unwrap();
+        let tac = rt.state();
+        assert_eq!(tac.screen().get_pix(2, 0), 15);
+        assert_eq!(tac.screen().get_pix(3, 0), 3);
+        assert_eq!(tac.screen().get_pix(8, 0), 0); // map tile 0 with no colorkey
+    }
+
+    #[test]
+    fn colorkey_ttri() {
+        let mut rt = runtime(
+            "function TIC()
+                cls(15)
+                ttri(0, 0, 8, 0, 0, 8, 8, 0, 16, 0, 8, 8, 0, {1, 3, 5})
+                ttri(8, 0, 8, 8, 0, 8, 16, 0, 16, 8, 8, 8, false, 2)
+            end",
+        );
+        rt.step().unwrap();
+        let tac = rt.state();
+        let row: Vec<u8> = (0..8).map(|x| tac.screen().get_pix(x, 0)).collect();
+        assert_eq!(row, [0, 15, 2, 15, 4, 15, 6, 7]);
+        let row: Vec<u8> = (0..8).map(|x| tac.screen().get_pix(x, 7)).collect();
+        assert_eq!(row, [0, 1, 15, 3, 4, 5, 6, 7]);
+        drop(tac);
+        let screen_texture = "ttri(0, 0, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 2)";
+        assert!(rt.lua_ctx.load(screen_texture).exec().is_err());
+    }
+
+    #[test]
+    fn deterministic_time() {
+        let mut rt = runtime("function TIC() t = time() end");
+        rt.set_deterministic(true);
+        for _ in 0..3 {
+            rt.step().unwrap();
+        }
+        assert_eq!(rt.frame(), 3);
+        let t: f64 = rt.lua_ctx.globals().get("t").unwrap();
+        assert_eq!(t, 2000.0 / 60.0);
+    }
+
+    #[test]
+    fn seeded_random() {
+        let code = "r = {math.random(), math.random(6), math.random(-2, 2)}";
+        let rolls = |rt: TAC70Runtime| {
+            let r: Vec<f64> = rt.lua_ctx.globals().get("r").unwrap();
+            r
+        };
+        let mem = vec![0u8; 0x18000];
+        let tac = || TAC70::new(&mem, code.to_string());
+        let a = TAC70Runtime::with_seed(tac(), 1234).unwrap();
+        assert_eq!(a.seed(), 1234);
+        let a = rolls(a);
+        assert_eq!(a, rolls(TAC70Runtime::with_seed(tac(), 1234).unwrap()));
+        assert_ne!(a, rolls(TAC70Runtime::with_seed(tac(), 1235).unwrap()));
+        assert!((1.0..=6.0).contains(&a[1]) && (-2.0..=2.0).contains(&a[2]));
+
+        let rt = runtime("math.randomseed(5) a = math.random(100) math.randomseed(5)");
+        let again: i64 = rt.lua_ctx.load("return math.random(100)").eval().unwrap();
+        assert_eq!(rt.lua_ctx.globals().get::<_, i64>("a").unwrap(), again);
+        assert!(rt.lua_ctx.load("math.random(3, 1)").exec().is_err());
+    }
+
+    #[test]
+    fn keyboard() {
+        let mut rt = runtime("function TIC() any, space, a = key(), key(48), key(1) end");
+        rt.state().keyboard().set(&[48]);
+        rt.step().unwrap();
+        let held: (bool, bool, bool) = rt.lua_ctx.load("return any, space, a").eval().unwrap();
+        assert_eq!(held, (true, true, false));
+    }
+
+    #[test]
+    fn map_remap() {
+        let mut rt = runtime(
+            "function TIC()
+                map(-1, 0, 2, 1, 0, 0, -1, 1, function(t, x, y) return x == 239 and 1 or t, 1 end)
+            end",
+        );
+        rt.step().unwrap();
+        let tac = rt.state();
+        let row: Vec<u8> = (0..16).map(|x| tac.screen().get_pix(x, 0)).collect();
+        assert_eq!(row, [7, 6, 5, 4, 3, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::{cell::RefCell, rc::Rc};
+
+use mlua::prelude::*;
+use mlua::HookTriggers;
+use thiserror::Error;
+
+/// How much of the host a cart may take before it's stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Lua instructions a single call into the cart may run: loading its code, `BOOT`
+    /// or one `TIC`. Counted in steps of [`Limits::GRANULARITY`]. `None` lets it loop
+    /// forever.
+    pub instructions: Option<u64>,
+    /// Bytes the Lua heap may grow to. `None` for no limit.
+    pub memory: Option<usize>,
+}
+
+impl Limits {
+    /// How often the instruction count is checked.
+    pub const GRANULARITY: u32 = 1000;
+
+    pub const UNLIMITED: Self = Self {
+        instructions: None,
+        memory: None,
+    };
+}
+
+impl Default for Limits {
+    /// Far more than any cart needs to draw a frame, but stops one stuck in a loop
+    /// within a fraction of a second.
+    fn default() -> Self {
+        Self {
+            instructions: Some(50_000_000),
+            memory: Some(256 << 20),
+        }
+    }
+}
+
+/// What the instruction hook raises once a call runs out of budget.
+#[derive(Debug, Error)]
+#[error("out of instructions")]
+pub(crate) struct OutOfTime(pub(crate) u64);
+
+/// Shared between the runtime and the instruction hook, which counts it down. Not in
+/// the Lua app data: that is a single `RefCell`, which the bindings hold a borrow of
+/// while they call back into the cart, as `map` does for its remap function.
+pub(crate) struct Budget {
+    pub(crate) limits: Limits,
+    /// Instructions left for the running call, `None` outside of one.
+    left: Option<u64>,
+}
+
+/// Installs the instruction hook and memory limit on a fresh Lua state.
+pub(crate) fn install(lua: &Lua, limits: Limits) -> LuaResult<Rc<RefCell<Budget>>> {
+    let budget = Rc::new(RefCell::new(Budget { limits, left: None }));
+    lua.set_memory_limit(limits.memory.unwrap_or(0))?;
+    set_hook(lua, &budget, Limits::GRANULARITY)?;
+    Ok(budget)
+}
+
+/// Runs the hook every `instructions` instructions.
+fn set_hook(lua: &Lua, budget: &Rc<RefCell<Budget>>, instructions: u32) -> LuaResult<()> {
+    let budget = budget.clone();
+    let triggers = HookTriggers {
+        every_nth_instruction: Some(instructions),
+        ..Default::default()
+    };
+    lua.set_hook(triggers, move |lua, _| hook(lua, &budget))
+}
+
+fn hook(lua: &Lua, budget: &Rc<RefCell<Budget>>) -> LuaResult<()> {
+    let mut state = budget.borrow_mut();
+    let limit = state.limits.instructions.unwrap_or(u64::MAX);
+    match &mut state.left {
+        Some(0) => Err(LuaError::external(OutOfTime(limit))),
+        Some(left) if *left <= Limits::GRANULARITY as u64 => {
+            *left = 0;
+            drop(state);
+            // a `pcall` catches the error, but then the code around it fails on its
+            // first instruction, and so on up to the cart's entry point
+            set_hook(lua, budget, 1)?;
+            Err(LuaError::external(OutOfTime(limit)))
+        }
+        Some(left) => {
+            *left -= Limits::GRANULARITY as u64;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Runs `f` as a call into the cart, within the instruction budget.
+pub(crate) fn budgeted<R>(
+    lua: &Lua,
+    budget: &Rc<RefCell<Budget>>,
+    f: impl FnOnce() -> LuaResult<R>,
+) -> LuaResult<R> {
+    {
+        let mut budget = budget.borrow_mut();
+        budget.left = budget.limits.instructions;
+    }
+    let result = f();
+    let tripped = budget.borrow_mut().left.take() == Some(0);
+    if tripped {
+        set_hook(lua, budget, Limits::GRANULARITY)?;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use tac_core::TAC70;
+
+    use super::*;
+    use crate::{RuntimeError, TAC70Runtime};
+
+    fn runtime(code: &str) -> Result<TAC70Runtime, RuntimeError> {
+        TAC70Runtime::with_seed(TAC70::new(&[0u8; 0x18000], code.to_string()), 0)
+    }
+
+    #[test]
+    fn timeout() {
+        let mut rt = runtime(
+            "n = 0
+            function TIC()
+              n = n + 1
+              if n == 2 then
+                while true do pcall(function() while true do end end) end
+              end
+            end",
+        )
+        .unwrap();
+        rt.set_limits(Limits {
+            instructions: Some(100_000),
+            memory: None,
+        })
+        .unwrap();
+        rt.step().unwrap();
+        match rt.step() {
+            Err(RuntimeError::Timeout {
+                instructions: 100_000,
+                line: Some(5),
+                ..
+            }) => {}
+            e => panic!("{:?}", e),
+        }
+        // nothing else is counted against it
+        rt.save_state().unwrap();
+        rt.lua_ctx.globals().set("n", 5).unwrap();
+        rt.step().unwrap();
+
+        let e = runtime("while true do end").err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "cart:1: ran for over 50000000 instructions without returning"
+        );
+    }
+
+    #[test]
+    fn memory() {
+        let mut rt = runtime("function TIC() t = {} for i = 1, 1e7 do t[i] = i end end").unwrap();
+        rt.set_limits(Limits {
+            instructions: None,
+            memory: Some(1 << 20),
+        })
+        .unwrap();
+        assert!(matches!(rt.step(), Err(RuntimeError::OutOfMemory)));
+
+        let mut rt = runtime("function TIC() s = string.rep('x', 1 << 24) end").unwrap();
+        rt.set_limits(Limits {
+            memory: Some(1 << 20),
+            ..Limits::UNLIMITED
+        })
+        .unwrap();
+        // the string library raises its own error rather than a memory error
+        let e = rt.step().unwrap_err();
+        assert!(e.to_string().contains("not enough memory"), "{}", e);
+        rt.set_limits(Limits::UNLIMITED).unwrap();
+        rt.step().unwrap();
+    }
+
+    #[test]
+    fn remap() {
+        // the hook runs within `map`'s remap function, while `map` holds on to the
+        // console
+        let mut rt = runtime(
+            "function TIC()
+              map(0, 0, 30, 17, 0, 0, -1, 1, function(t)
+                for i = 1, 100 do t = t + math.random(2) end
+                trace(t)
+                if stuck then while true do end end
+                return t % 256
+              end)
+            end",
+        )
+        .unwrap();
+        let traced = Rc::new(RefCell::new(0));
+        let count = traced.clone();
+        rt.set_trace(move |_| *count.borrow_mut() += 1);
+        rt.step().unwrap();
+        assert_eq!(*traced.borrow(), 30 * 17);
+        rt.lua_ctx.globals().set("stuck", true).unwrap();
+        rt.set_limits(Limits {
+            instructions: Some(100_000),
+            memory: None,
+        })
+        .unwrap();
+        assert!(matches!(rt.step(), Err(RuntimeError::Timeout { .. })));
+    }
+
+    #[test]
+    fn sandbox() {
+        let rt = runtime("").unwrap();
+        let mut libs: Vec<String> = rt
+            .lua_ctx
+            .globals()
+            .pairs::<String, LuaValue>()
+            .filter_map(|pair| match pair.unwrap() {
+                (name, LuaValue::Table(_)) => Some(name),
+                _ => None,
+            })
+            .collect();
+        libs.sort();
+        assert_eq!(libs, ["_G", "math", "string", "table", "utf8"]);
+        for name in [
+            "dofile", "loadfile", "require", "io", "os", "debug", "package",
+        ] {
+            let value: LuaValue = rt.lua_ctx.globals().get(name).unwrap();
+            assert_eq!(value, LuaNil, "{}", name);
+        }
+
+        let eval = |code: &str| rt.lua_ctx.load(code).eval::<LuaValue>();
+        assert_eq!(
+            eval("return load(string.dump(function() end))").unwrap(),
+            LuaNil
+        );
+        assert_eq!(
+            eval("return load('return 1')()").unwrap(),
+            LuaValue::Integer(1)
+        );
+        assert_eq!(
+            eval("return load('return x', 'x', 'b', {x = 2})()").unwrap(),
+            LuaValue::Integer(2)
+        );
+    }
+}
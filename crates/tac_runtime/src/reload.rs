@@ -0,0 +1,156 @@
+use mlua::prelude::*;
+use tac_core::TAC70;
+
+use crate::{error::CHUNK_NAME, limits, state::BUILTINS, RuntimeError, TAC70Runtime};
+
+/// What a cart keeps when [`TAC70Runtime::reload`] swaps its code and assets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Preserve {
+    /// RAM outside of [`TAC70::ASSETS`]: the screen, pmem, sound registers...
+    pub ram: bool,
+    /// Global variables, other than functions, which come from the new code. Functions
+    /// stored in tables keep their old code, which save states can't name.
+    pub globals: bool,
+}
+
+impl Preserve {
+    pub const NOTHING: Self = Self {
+        ram: false,
+        globals: false,
+    };
+
+    pub const ALL: Self = Self {
+        ram: true,
+        globals: true,
+    };
+}
+
+impl TAC70Runtime {
+    /// Runs the code of `tac` in place of the cart's and takes its RAM, for when the
+    /// cart changed on disk. `BOOT` runs again unless the globals are kept. The frame
+    /// count and `math.random` go on where they were.
+    ///
+    /// Nothing changes if the new code doesn't compile. If it fails while running,
+    /// the cart is left half reloaded, to be reloaded again once fixed.
+    pub fn reload(&mut self, tac: TAC70, preserve: Preserve) -> Result<(), RuntimeError> {
+        self.swap_code(tac, preserve)?;
+        match preserve.globals {
+            true => Ok(()),
+            false => self.boot(),
+        }
+    }
+
+    fn swap_code(&self, tac: TAC70, preserve: Preserve) -> Result<(), RuntimeError> {
+        let lua = &self.lua_ctx;
+        let chunk = lua
+            .load(&tac.code)
+            .set_name(format!("={}", CHUNK_NAME))?
+            .into_function()?;
+
+        let globals = lua.globals();
+        let old = globals
+            .clone()
+            .pairs::<LuaValue, LuaValue>()
+            .collect::<LuaResult<Vec<_>>>()?;
+        for (key, _) in &old {
+            globals.raw_set(key.clone(), LuaNil)?;
+        }
+        let builtins: LuaTable = lua.named_registry_value(BUILTINS)?;
+        for pair in builtins.pairs::<String, LuaValue>() {
+            let (name, value) = pair?;
+            if !name.contains('.') {
+                globals.raw_set(name, value)?;
+            }
+        }
+
+        {
+            let mut current = lua.app_data_mut::<TAC70>().unwrap();
+            if preserve.ram {
+                for (addr, cell) in tac.mem.iter().enumerate() {
+                    if !TAC70::ASSETS.iter().any(|assets| assets.contains(&addr)) {
+                        cell.set(current.mem[addr].get());
+                    }
+                }
+            }
+            *current = tac;
+            current.update_font_data();
+        }
+
+        limits::budgeted(lua, &self.budget, || chunk.call::<_, ()>(()))?;
+        if preserve.globals {
+            for (key, value) in old {
+                if !matches!(value, LuaValue::Function(_)) {
+                    globals.raw_set(key, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tac_core::PixBuf;
+
+    use super::*;
+
+    const OLD: &str = "
+        function BOOT() booted = (booted or 0) + 1 end
+        n, t = 0, {}
+        function TIC() n = n + 1 t[n] = n pix(0, 0, 1) end";
+    const NEW: &str = "
+        function BOOT() booted = (booted or 0) + 10 end
+        n, t = 100, {}
+        function TIC() n = n + 2 pix(1, 0, 2) end";
+
+    fn cart(code: &str, tile: u8) -> TAC70 {
+        let mut mem = vec![0u8; 0x18000];
+        mem[0x4000] = tile;
+        TAC70::new(&mem, code.to_string())
+    }
+
+    fn run(preserve: Preserve) -> TAC70Runtime {
+        let mut rt = TAC70Runtime::with_seed(cart(OLD, 1), 0).unwrap();
+        rt.boot().unwrap();
+        for _ in 0..3 {
+            rt.step().unwrap();
+        }
+        rt.reload(cart(NEW, 2), preserve).unwrap();
+        rt.step().unwrap();
+        rt
+    }
+
+    #[test]
+    fn reload() {
+        let global =
+            |rt: &TAC70Runtime, name: &str| -> i64 { rt.lua_ctx.globals().get(name).unwrap() };
+
+        let mut rt = run(Preserve::ALL);
+        assert_eq!((global(&rt, "n"), global(&rt, "booted")), (5, 1));
+        let t: Vec<i64> = rt.lua_ctx.globals().get("t").unwrap();
+        assert_eq!(t, [1, 2, 3]);
+        let tac = rt.state();
+        assert_eq!(tac.screen().get_pix(0, 0), 1);
+        assert_eq!(tac.screen().get_pix(1, 0), 2);
+        assert_eq!(tac.mem[0x4000].get(), 2);
+        drop(tac);
+        assert_eq!(rt.frame(), 4);
+        // save states go with the new code
+        let state = rt.save_state().unwrap();
+        rt.load_state(&state).unwrap();
+
+        let mut rt = run(Preserve::NOTHING);
+        assert_eq!((global(&rt, "n"), global(&rt, "booted")), (102, 10));
+        assert_eq!(rt.state().screen().get_pix(0, 0), 0);
+    }
+
+    #[test]
+    fn broken_code() {
+        let mut rt = TAC70Runtime::with_seed(cart(OLD, 1), 0).unwrap();
+        rt.step().unwrap();
+        assert!(rt.reload(cart("function TIC(", 2), Preserve::ALL).is_err());
+        rt.step().unwrap();
+        assert_eq!(rt.lua_ctx.globals().get::<_, i64>("n").unwrap(), 2);
+        assert_eq!(rt.state().mem[0x4000].get(), 1);
+    }
+}
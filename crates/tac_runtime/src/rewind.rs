@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+
+use crate::{RuntimeError, TAC70Runtime};
+
+/// The last few seconds of a running cart, to step back through frame by frame.
+///
+/// Every frame keeps the console's RAM, frame count and random state, but only the
+/// newest whole. Older ones are kept as the bytes that differ from the frame after
+/// them, XORed and run-length encoded; RAM barely changes from one frame to the
+/// next, so most of the buffer is runs of zeroes.
+///
+/// The Lua globals are only saved every [`Rewind::KEYFRAME`] frames, at keyframes.
+/// Stepping back to a frame puts back the globals of the last keyframe at or before
+/// it, so the cart's variables may be a few frames older than its RAM, until
+/// [`Rewind::settle`] goes on back to that keyframe. Carts whose globals can't be
+/// saved, see [`TAC70Runtime::save_state`], only rewind their RAM.
+pub struct Rewind {
+    capacity: usize,
+    /// Frames from one keyframe to the next.
+    keyframe: usize,
+    /// Oldest first. Applying the delta of the one before the last to the last
+    /// gives that frame, and so on.
+    frames: VecDeque<Frame>,
+    /// Frames recorded since the last keyframe.
+    since_keyframe: usize,
+    /// Whether the runtime's globals are those of the last keyframe, after stepping
+    /// back.
+    restored: bool,
+    /// Whether the globals couldn't be saved at the last keyframe.
+    failing: bool,
+}
+
+struct Frame {
+    /// The console's part of a save state, or its delta to the next frame.
+    machine: Vec<u8>,
+    /// The Lua part of a save state, for keyframes.
+    globals: Option<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Frames from one keyframe to the next, half a second.
+    pub const KEYFRAME: usize = 30;
+
+    /// Keeps up to `capacity` frames to go back to.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            keyframe: Self::KEYFRAME,
+            frames: VecDeque::new(),
+            since_keyframe: 0,
+            restored: false,
+            failing: false,
+        }
+    }
+
+    /// Records the state `runtime` is in. Call once every `TIC`.
+    ///
+    /// Fails when the globals can't be saved at a keyframe after they could be, or at
+    /// the first one. The frame is recorded all the same, without its globals.
+    pub fn record(&mut self, runtime: &TAC70Runtime) -> Result<(), RuntimeError> {
+        let machine = runtime.save_machine();
+        let mut result = Ok(());
+        let globals = match self.since_keyframe {
+            0 => match runtime.save_globals() {
+                Ok(globals) => {
+                    self.failing = false;
+                    Some(globals)
+                }
+                Err(e) => {
+                    if !self.failing {
+                        result = Err(e);
+                    }
+                    self.failing = true;
+                    None
+                }
+            },
+            _ => None,
+        };
+        self.since_keyframe = (self.since_keyframe + 1) % self.keyframe;
+
+        if let Some(newest) = self.frames.back_mut() {
+            newest.machine = delta(&machine, &newest.machine);
+        }
+        self.frames.push_back(Frame { machine, globals });
+        if self.frames.len() > self.capacity + 1 {
+            self.frames.pop_front();
+        }
+        self.restored = false;
+        result
+    }
+
+    /// Puts `runtime` back in the frame before the last recorded one. Returns false,
+    /// leaving it alone, once there is nothing older left.
+    pub fn step_back(&mut self, runtime: &mut TAC70Runtime) -> Result<bool, RuntimeError> {
+        let [.., previous, newest] = self.frames.make_contiguous() else {
+            return Ok(false);
+        };
+        let machine = apply(&newest.machine, &previous.machine)?;
+        if !self.restored || newest.globals.is_some() {
+            let keyframe = self
+                .frames
+                .iter()
+                .rev()
+                .skip(1)
+                .find_map(|f| f.globals.as_ref());
+            if let Some(globals) = keyframe {
+                runtime.load_globals(globals)?;
+            }
+        }
+        runtime.load_machine(&machine)?;
+
+        self.frames.pop_back();
+        self.frames.back_mut().unwrap().machine = machine;
+        self.restored = true;
+        self.since_keyframe = match self.frames.iter().rev().position(|f| f.globals.is_some()) {
+            Some(since) => (since + 1) % self.keyframe,
+            None => 0,
+        };
+        Ok(true)
+    }
+
+    /// Steps `runtime` back to the last keyframe, where its RAM and globals are of the
+    /// same frame, for the cart to play on from a state it really was in: a recording
+    /// of its input only plays back the same from there. Call once done stepping
+    /// back. Returns false, leaving it alone, if no frame left has its globals.
+    pub fn settle(&mut self, runtime: &mut TAC70Runtime) -> Result<bool, RuntimeError> {
+        if self.frames.iter().all(|f| f.globals.is_none()) {
+            return Ok(false);
+        }
+        let mut stepped = false;
+        while self.frames.back().is_some_and(|f| f.globals.is_none()) {
+            stepped |= self.step_back(runtime)?;
+        }
+        Ok(stepped)
+    }
+
+    /// How many frames back it can go.
+    pub fn len(&self) -> usize {
+        self.frames.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory used by the recorded frames.
+    pub fn bytes(&self) -> usize {
+        self.frames
+            .iter()
+            .map(|f| f.machine.len() + f.globals.as_ref().map_or(0, Vec::len))
+            .sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.since_keyframe = 0;
+        self.restored = false;
+        self.failing = false;
+    }
+}
+
+/// Encodes `old` against `new`: its length, then runs of unchanged bytes and literal
+/// XORed bytes, `zeroes:varint literal_len:varint bytes...` until `old` is covered.
+fn delta(new: &[u8], old: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    write_varint(&mut out, old.len());
+    let xor = |i: usize| old[i] ^ new.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < old.len() {
+        let start = i;
+        while i < old.len() && xor(i) == 0 {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+
+        // a lone unchanged byte costs less as a literal than as a new run
+        let start = i;
+        while i < old.len() && (xor(i) != 0 || (i + 1 < old.len() && xor(i + 1) != 0)) {
+            i += 1;
+        }
+        write_varint(&mut out, i - start);
+        out.extend((start..i).map(xor));
+    }
+    out
+}
+
+/// Recovers `old` from `new` and `delta(new, old)`.
+fn apply(new: &[u8], delta: &[u8]) -> Result<Vec<u8>, RuntimeError> {
+    let mut delta = delta.iter().copied();
+    let len = read_varint(&mut delta)?;
+    let mut old: Vec<u8> = (0..len).map(|i| new.get(i).copied().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while i < len {
+        i += read_varint(&mut delta)?;
+        let literal = read_varint(&mut delta)?;
+        if i + literal > len {
+            return Err(corrupt());
+        }
+        for byte in &mut old[i..i + literal] {
+            *byte ^= delta.next().ok_or_else(corrupt)?;
+        }
+        i += literal;
+    }
+    Ok(old)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<usize, RuntimeError> {
+    let mut n = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = bytes.next().ok_or_else(corrupt)?;
+        n |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(corrupt())
+}
+
+fn corrupt() -> RuntimeError {
+    RuntimeError::BadState("corrupt rewind delta".into())
+}
+
+#[cfg(test)]
+mod test {
+    use tac_core::{InputState, PixBuf, Replay, TAC70};
+
+    use super::*;
+
+    #[test]
+    fn deltas() {
+        let new = vec![0u8; 1000];
+        let mut old = new.clone();
+        old[3] = 1;
+        old[4] = 2;
+        old[6] = 3;
+        old[999] = 4;
+        let d = delta(&new, &old);
+        assert!(d.len() < 16, "{}", d.len());
+        assert_eq!(apply(&new, &d).unwrap(), old);
+
+        for (new, old) in [(&old[..10], &new[..]), (&new[..], &old[..10]), (&[], &[])] {
+            assert_eq!(apply(new, &delta(new, old)).unwrap(), old);
+        }
+        assert!(apply(&new, &d[..d.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn step_back() {
+        let code = "x = 0 function TIC() x = x + 1 pix(x, 0, 5) end";
+        let tac = TAC70::new(&[0u8; 0x18000], code.to_string());
+        let mut rt = TAC70Runtime::with_seed(tac, 0).unwrap();
+        let mut rewind = Rewind::new(3);
+        rewind.keyframe = 2;
+        rewind.record(&rt).unwrap();
+        for _ in 0..5 {
+            rt.step().unwrap();
+            rewind.record(&rt).unwrap();
+        }
+        assert_eq!(rewind.len(), 3);
+
+        // the globals go back a keyframe at a time, frames 4 and 2
+        let x = |rt: &TAC70Runtime| rt.lua_ctx.globals().get::<_, i64>("x").unwrap();
+        for (frame, expected) in [(4, 4), (3, 2), (2, 2)] {
+            assert!(rewind.step_back(&mut rt).unwrap());
+            assert_eq!(x(&rt), expected);
+            assert_eq!(rt.frame(), frame);
+        }
+        assert_eq!(rt.state().screen().get_buf(1), 0x05); // pixel 2 stays, 3 is gone
+        assert!(!rewind.step_back(&mut rt).unwrap());
+        assert_eq!(x(&rt), 2);
+
+        // and play on from there
+        rt.step().unwrap();
+        rewind.record(&rt).unwrap();
+        assert!(rewind.step_back(&mut rt).unwrap());
+        assert_eq!(x(&rt), 2);
+    }
+
+    /// Plays `frames` frames the way the front does, recording their input and the
+    /// map cell each leaves at 0, 0.
+    fn play(
+        rt: &mut TAC70Runtime,
+        rewind: &mut Rewind,
+        replay: &mut Replay,
+        seen: &mut Vec<u8>,
+        frames: usize,
+    ) {
+        for _ in 0..frames {
+            // left held every third frame
+            let mut input = InputState::default();
+            input[0] = seen.len().is_multiple_of(3) as u8;
+            rt.state().set_input_state(&input);
+            replay.push(input);
+            rt.step().unwrap();
+            rewind.record(rt).unwrap();
+            seen.push(rt.state().mem[0x8000].get());
+        }
+    }
+
+    #[test]
+    fn settle() {
+        // input and random numbers both feed x, so a replay only plays back the same
+        // if the cart went on from a state it really was in
+        let code = "x = 0 function TIC() \
+            if btn(0) then x = x * 3 end x = x + math.random(9) mset(0, 0, x % 256) end";
+        let start = || {
+            let tac = TAC70::new(&[0u8; 0x18000], code.to_string());
+            TAC70Runtime::with_seed(tac, 7).unwrap()
+        };
+        let mut rt = start();
+        let mut rewind = Rewind::new(20);
+        rewind.keyframe = 4;
+        let mut replay = Replay::new(0, 7);
+        let mut seen = vec![];
+        rewind.record(&rt).unwrap();
+        play(&mut rt, &mut rewind, &mut replay, &mut seen, 10);
+
+        // back to frame 5, then on to the keyframe at 4
+        for _ in 0..5 {
+            assert!(rewind.step_back(&mut rt).unwrap());
+        }
+        assert_eq!(rt.frame(), 5);
+        assert!(rewind.settle(&mut rt).unwrap());
+        assert_eq!(rt.frame(), 4);
+        assert!(!rewind.settle(&mut rt).unwrap());
+        replay.truncate(4);
+        seen.truncate(4);
+        play(&mut rt, &mut rewind, &mut replay, &mut seen, 8);
+
+        let mut file = vec![];
+        replay.write(&mut file).unwrap();
+        let replay = Replay::read(file.as_slice()).unwrap();
+        let mut replayed = start();
+        assert_eq!(replay.len(), seen.len());
+        for (frame, &expected) in seen.iter().enumerate() {
+            replayed
+                .state()
+                .set_input_state(replay.frame(frame).unwrap());
+            replayed.step().unwrap();
+            assert_eq!(
+                replayed.state().mem[0x8000].get(),
+                expected,
+                "frame {}",
+                frame
+            );
+        }
+        assert_eq!(replayed.save_machine(), rt.save_machine());
+    }
+
+    #[test]
+    fn ram_only() {
+        let code = "f = load('') x = 0 function TIC() x = x + 1 pix(x, 0, 5) end";
+        let tac = TAC70::new(&[0u8; 0x18000], code.to_string());
+        let mut rt = TAC70Runtime::with_seed(tac, 0).unwrap();
+        let mut rewind = Rewind::new(10);
+        rewind.keyframe = 2;
+        assert!(rewind.record(&rt).is_err());
+        for _ in 0..3 {
+            rt.step().unwrap();
+            // said once
+            rewind.record(&rt).unwrap();
+        }
+        // a frame of this cart is a handful of pixels and the frame count
+        assert!(rewind.bytes() < 0x18000 + 256, "{}", rewind.bytes());
+
+        assert!(rewind.step_back(&mut rt).unwrap());
+        assert_eq!(rt.frame(), 2);
+        assert_eq!(rt.state().screen().get_pix(3, 0), 0);
+        assert_eq!(rt.lua_ctx.globals().get::<_, i64>("x").unwrap(), 3);
+    }
+}
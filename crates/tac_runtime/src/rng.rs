@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Backs `math.random`. Lua's own uses the C library's `rand`, which differs between
+/// platforms and is shared by the whole process, so it can't be replayed.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    /// What the generator was created with.
+    pub(crate) seed: u64,
+    pub(crate) state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// Restarts the sequence, for `math.randomseed`. `seed` keeps the initial one.
+    pub(crate) fn reseed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub(crate) fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    /// SplitMix64.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [lo, hi], which must not be empty.
+    pub(crate) fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = hi.wrapping_sub(lo) as u64;
+        let n = match span.checked_add(1) {
+            Some(n) => self.next_u64() % n,
+            None => self.next_u64(),
+        };
+        lo.wrapping_add(n as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        for _ in 0..1000 {
+            let n = a.range(-3, 3);
+            assert!((-3..=3).contains(&n));
+            let f = a.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(a.range(5, 5), 5);
+        a.range(i64::MIN, i64::MAX);
+    }
+}
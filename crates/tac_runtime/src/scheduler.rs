@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+/// Decides how many times `TIC` should run to keep up with the wall clock at
+/// exactly 60 Hz, independently of how often the frontend renders.
+#[derive(Debug, Clone)]
+pub struct FrameScheduler {
+    /// Wall time owed to the console, in 1/60 ns so frames divide it exactly.
+    owed: u128,
+    max_steps: u32,
+}
+
+impl FrameScheduler {
+    pub const FPS: u32 = 60;
+    const FRAME: u128 = 1_000_000_000;
+
+    /// `max_steps` bounds how many frames a single `advance` may ask for. Past that,
+    /// the backlog is dropped so a long stall slows the game down instead of making
+    /// it jump ahead.
+    pub fn new(max_steps: u32) -> Self {
+        Self {
+            owed: 0,
+            max_steps: max_steps.max(1),
+        }
+    }
+
+    /// Adds `elapsed` wall time and returns how many frames are now due.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.owed += elapsed.as_nanos() * Self::FPS as u128;
+        let due = self.owed / Self::FRAME;
+        if due > self.max_steps as u128 {
+            self.owed = 0;
+            return self.max_steps;
+        }
+        self.owed -= due * Self::FRAME;
+        due as u32
+    }
+}
+
+impl Default for FrameScheduler {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+/// Source of `time()`.
+pub(crate) struct Clock {
+    pub start: Instant,
+    pub frames: u64,
+    pub deterministic: bool,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+            deterministic: false,
+        }
+    }
+
+    /// Jumps to `frames`, as if the cart had been running for that long.
+    pub fn restore(&mut self, frames: u64) {
+        let elapsed = Duration::from_secs(frames) / FrameScheduler::FPS;
+        self.start = Instant::now().checked_sub(elapsed).unwrap_or_else(Instant::now);
+        self.frames = frames;
+    }
+
+    /// Milliseconds since the cart started: wall time, or emulated frames when
+    /// deterministic.
+    pub fn millis(&self) -> f64 {
+        if self.deterministic {
+            self.frames as f64 * 1000.0 / FrameScheduler::FPS as f64
+        } else {
+            self.start.elapsed().as_secs_f64() * 1000.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sixty_per_second() {
+        let mut sched = FrameScheduler::new(10);
+        // 144 Hz display: a second's worth of refreshes still makes 60 frames
+        let total: u32 = (0..144)
+            .map(|_| sched.advance(Duration::from_secs(1) / 144))
+            .sum();
+        assert!((59..=60).contains(&total), "{}", total);
+        let total: u32 = (0..1440)
+            .map(|_| sched.advance(Duration::from_secs(1) / 144))
+            .sum();
+        assert!((599..=601).contains(&total), "{}", total);
+    }
+
+    #[test]
+    fn catch_up_is_bounded() {
+        let mut sched = FrameScheduler::new(4);
+        assert_eq!(sched.advance(Duration::from_millis(50)), 3);
+        assert_eq!(sched.advance(Duration::from_secs(2)), 4);
+        // the rest of the stall is forgotten
+        assert_eq!(sched.advance(Duration::from_millis(1)), 0);
+    }
+}
@@ -0,0 +1,739 @@
+//! Save states: a snapshot of everything a running cart can observe, to restore
+//! into the same cart later.
+//!
+//! That is all of `TAC70::mem`, which holds pmem and the sound registers too (there
+//! is no audio engine with state of its own yet), the frame counter, the state of
+//! `math.random`, and every value reachable from the Lua globals: tables with their
+//! metatables, shared references and cycles, strings, numbers, and Lua functions
+//! with their upvalues. Functions are saved as which function of the cart's code
+//! they are, never as bytecode, see [`crate::bytecode`], so functions made by `load`
+//! can't be saved. Built-in functions are saved by name, and the libraries by name
+//! with their contents, which the cart may have added to. Coroutines and userdata
+//! can't be saved either; trying to fails naming the path to the offending value.
+//!
+//! ```text
+//! "TACS" version:u8 code_hash:u64 frames:u64 rng_seed:u64 rng_state:u64  (little endian)
+//! mem (0x18000 bytes)
+//! the globals: key value key value ... nil, then their metatable
+//! ```
+
+use std::{cell::Cell, collections::HashMap, ffi::c_void};
+
+use mlua::{prelude::*, ChunkMode};
+use tac_core::{Replay, TAC70};
+
+use crate::{bytecode::Prototypes, error::CHUNK_NAME, rng::Rng, RuntimeError, TAC70Runtime};
+
+const MAGIC: &[u8; 4] = b"TACS";
+const VERSION: u8 = 2;
+/// Bytes before RAM in the console's part: the frame count and the two RNG numbers.
+const MACHINE: usize = 24;
+
+/// Registry key of the `debug` library, which carts can't reach.
+pub(crate) const DEBUG: &str = "tac70.debug";
+/// Registry key of the globals that existed before the cart's code ran, and of the
+/// contents of the libraries among them, by name.
+pub(crate) const BUILTINS: &str = "tac70.builtins";
+
+// value tags
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INTEGER: u8 = 3;
+const NUMBER: u8 = 4;
+const STRING: u8 = 5;
+/// A table or function written earlier, by the order it was written in.
+const REF: u8 = 6;
+const TABLE: u8 = 7;
+const FUNCTION: u8 = 8;
+const BUILTIN: u8 = 9;
+const GLOBALS: u8 = 10;
+/// A built-in library, by name, and its contents.
+const LIBRARY: u8 = 11;
+
+// upvalue kinds
+const OWN: u8 = 0;
+/// The same variable as an upvalue of a function written earlier.
+const SHARED: u8 = 1;
+
+/// Records the built-ins. Must run before the cart's code.
+pub(crate) fn snapshot_builtins(lua: &Lua) -> LuaResult<()> {
+    let builtins = lua.create_table()?;
+    for pair in lua.globals().pairs::<String, LuaValue>() {
+        let (name, value) = pair?;
+        match &value {
+            LuaValue::Table(lib) if name != "_G" => {
+                for pair in lib.clone().pairs::<String, LuaValue>() {
+                    let (key, value) = pair?;
+                    builtins.set(format!("{}.{}", name, key), value)?;
+                }
+            }
+            _ => {}
+        }
+        builtins.set(name, value)?;
+    }
+    lua.set_named_registry_value(BUILTINS, builtins)
+}
+
+impl TAC70Runtime {
+    /// Snapshots the console and the cart's Lua state, see [`crate::state`].
+    pub fn save_state(&self) -> Result<Vec<u8>, RuntimeError> {
+        let mut state = MAGIC.to_vec();
+        state.push(VERSION);
+        state.extend(self.code_hash().to_le_bytes());
+        state.extend(self.save_machine());
+        state.extend(self.save_globals()?);
+        Ok(state)
+    }
+
+    /// Restores a snapshot taken by [`TAC70Runtime::save_state`] on the same cart.
+    /// Nothing changes if it fails.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), RuntimeError> {
+        let lua = &self.lua_ctx;
+        let mut dec = Decoder::new(lua, state, self.prototypes()?)?;
+
+        if dec.take(4)? != MAGIC {
+            return Err(bad_state("not a save state"));
+        }
+        let version = dec.byte()?;
+        if version != VERSION {
+            return Err(bad_state(format!("unsupported version {}", version)));
+        }
+        if dec.u64()? != self.code_hash() {
+            return Err(bad_state("it was saved from a different cart"));
+        }
+        let machine = dec.take(MACHINE + lua.app_data_ref::<TAC70>().unwrap().mem.len())?;
+        let globals = dec.globals()?;
+
+        self.restore_globals(globals)?;
+        self.load_machine(machine)
+    }
+
+    /// The console's part of a save state: the frame count, the state of
+    /// `math.random` and RAM.
+    pub(crate) fn save_machine(&self) -> Vec<u8> {
+        let tac = self.lua_ctx.app_data_ref::<TAC70>().unwrap();
+        let rng = self.rng.borrow();
+        let mut machine = vec![];
+        machine.extend(self.clock.borrow().frames.to_le_bytes());
+        machine.extend(rng.seed.to_le_bytes());
+        machine.extend(rng.state.to_le_bytes());
+        machine.extend(tac.mem.iter().map(Cell::get));
+        machine
+    }
+
+    /// Restores what [`TAC70Runtime::save_machine`] saved.
+    pub(crate) fn load_machine(&self, machine: &[u8]) -> Result<(), RuntimeError> {
+        let mut tac = self.lua_ctx.app_data_mut::<TAC70>().unwrap();
+        if machine.len() != MACHINE + tac.mem.len() {
+            return Err(bad_state("truncated"));
+        }
+        let (numbers, mem) = machine.split_at(MACHINE);
+        let number = |i: usize| u64::from_le_bytes(numbers[i * 8..i * 8 + 8].try_into().unwrap());
+
+        for (cell, &byte) in tac.mem.iter().zip(mem) {
+            cell.set(byte);
+        }
+        tac.update_font_data();
+        self.clock.borrow_mut().restore(number(0));
+        *self.rng.borrow_mut() = Rng {
+            seed: number(1),
+            state: number(2),
+        };
+        Ok(())
+    }
+
+    /// The Lua part of a save state: everything reachable from the globals.
+    pub(crate) fn save_globals(&self) -> Result<Vec<u8>, RuntimeError> {
+        let lua = &self.lua_ctx;
+        let mut enc = Encoder::new(lua, self.prototypes()?)?;
+        enc.entries(&lua.globals(), "")?;
+        Ok(enc.out)
+    }
+
+    /// Restores what [`TAC70Runtime::save_globals`] saved. Nothing changes if it fails.
+    pub(crate) fn load_globals(&self, globals: &[u8]) -> Result<(), RuntimeError> {
+        let lua = &self.lua_ctx;
+        let globals = Decoder::new(lua, globals, self.prototypes()?)?.globals()?;
+        self.restore_globals(globals)
+    }
+
+    fn restore_globals(&self, globals: Globals) -> Result<(), RuntimeError> {
+        let table = self.lua_ctx.globals();
+        clear(&table)?;
+        for (key, value) in globals.entries {
+            table.raw_set(key, value)?;
+        }
+        table.set_metatable(globals.metatable);
+        for (library, entries, metatable) in globals.libraries {
+            clear(&library)?;
+            for (key, value) in entries {
+                library.raw_set(key, value)?;
+            }
+            library.set_metatable(metatable);
+        }
+        Ok(())
+    }
+
+    fn code_hash(&self) -> u64 {
+        Replay::hash(
+            self.lua_ctx
+                .app_data_ref::<TAC70>()
+                .unwrap()
+                .code
+                .as_bytes(),
+        )
+    }
+
+    /// The functions of the cart's code.
+    fn prototypes(&self) -> Result<Prototypes, RuntimeError> {
+        let lua = &self.lua_ctx;
+        let code = lua.app_data_ref::<TAC70>().unwrap().code.clone();
+        let chunk = lua
+            .load(&code)
+            .set_name(format!("={}", CHUNK_NAME))?
+            .into_function()?;
+        Prototypes::new(chunk.dump(false)).ok_or_else(|| bad_state("unexpected bytecode"))
+    }
+}
+
+fn clear(table: &LuaTable) -> LuaResult<()> {
+    let keys = table
+        .clone()
+        .pairs::<LuaValue, LuaValue>()
+        .map(|pair| pair.map(|(k, _)| k))
+        .collect::<LuaResult<Vec<_>>>()?;
+    for key in keys {
+        table.raw_set(key, LuaNil)?;
+    }
+    Ok(())
+}
+
+/// The parts of the `debug` library needed to get at upvalues.
+struct Debug<'lua> {
+    getupvalue: LuaFunction<'lua>,
+    setupvalue: LuaFunction<'lua>,
+    upvalueid: LuaFunction<'lua>,
+    upvaluejoin: LuaFunction<'lua>,
+}
+
+impl<'lua> Debug<'lua> {
+    fn new(lua: &'lua Lua) -> LuaResult<Self> {
+        let debug: LuaTable = lua.named_registry_value(DEBUG)?;
+        Ok(Self {
+            getupvalue: debug.get("getupvalue")?,
+            setupvalue: debug.get("setupvalue")?,
+            upvalueid: debug.get("upvalueid")?,
+            upvaluejoin: debug.get("upvaluejoin")?,
+        })
+    }
+}
+
+struct Encoder<'lua> {
+    out: Vec<u8>,
+    debug: Debug<'lua>,
+    prototypes: Prototypes,
+    globals: *const c_void,
+    builtins: HashMap<*const c_void, String>,
+    /// Tables and functions written so far, to their ids.
+    ids: HashMap<*const c_void, u32>,
+    /// Upvalues written so far, to the function id and index they were written with.
+    upvalues: HashMap<*const c_void, (u32, u8)>,
+}
+
+impl<'lua> Encoder<'lua> {
+    fn new(lua: &'lua Lua, prototypes: Prototypes) -> LuaResult<Self> {
+        let mut builtins = HashMap::new();
+        let table: LuaTable = lua.named_registry_value(BUILTINS)?;
+        for pair in table.pairs::<String, LuaValue>() {
+            let (name, value) = pair?;
+            if matches!(value, LuaValue::Table(_) | LuaValue::Function(_)) {
+                builtins.insert(value.to_pointer(), name);
+            }
+        }
+        Ok(Self {
+            out: vec![],
+            debug: Debug::new(lua)?,
+            prototypes,
+            globals: lua.globals().to_pointer(),
+            builtins,
+            ids: HashMap::new(),
+            upvalues: HashMap::new(),
+        })
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend((bytes.len() as u32).to_le_bytes());
+        self.out.extend(bytes);
+    }
+
+    /// Writes `value`, found at `path`, which names it in errors.
+    fn value(&mut self, value: LuaValue<'lua>, path: &str) -> Result<(), RuntimeError> {
+        let ptr = value.to_pointer();
+        match value {
+            LuaValue::Nil => self.out.push(NIL),
+            LuaValue::Boolean(b) => self.out.push(if b { TRUE } else { FALSE }),
+            LuaValue::Integer(n) => {
+                self.out.push(INTEGER);
+                self.out.extend(n.to_le_bytes());
+            }
+            LuaValue::Number(n) => {
+                self.out.push(NUMBER);
+                self.out.extend(n.to_le_bytes());
+            }
+            LuaValue::String(s) => {
+                self.out.push(STRING);
+                self.bytes(s.as_bytes());
+            }
+            LuaValue::Table(_) if ptr == self.globals => self.out.push(GLOBALS),
+            LuaValue::Table(_) | LuaValue::Function(_) if self.ids.contains_key(&ptr) => {
+                self.out.push(REF);
+                self.out.extend(self.ids[&ptr].to_le_bytes());
+            }
+            LuaValue::Table(t) if self.builtins.contains_key(&ptr) => {
+                self.ids.insert(ptr, self.ids.len() as u32);
+                self.out.push(LIBRARY);
+                let name = self.builtins[&ptr].clone();
+                self.bytes(name.as_bytes());
+                self.entries(&t, path)?;
+            }
+            LuaValue::Function(_) if self.builtins.contains_key(&ptr) => {
+                self.out.push(BUILTIN);
+                let name = self.builtins[&ptr].clone();
+                self.bytes(name.as_bytes());
+            }
+            LuaValue::Table(t) => {
+                self.ids.insert(ptr, self.ids.len() as u32);
+                self.out.push(TABLE);
+                self.entries(&t, path)?;
+            }
+            LuaValue::Function(f) => self.function(f, ptr, path)?,
+            LuaValue::Thread(_) => return Err(unsaveable(path, "a coroutine")),
+            LuaValue::UserData(_) | LuaValue::LightUserData(_) => {
+                return Err(unsaveable(path, "userdata"))
+            }
+            LuaValue::Error(_) => return Err(unsaveable(path, "an error object")),
+        }
+        Ok(())
+    }
+
+    /// Writes the contents of `table` then its metatable.
+    fn entries(&mut self, table: &LuaTable<'lua>, path: &str) -> Result<(), RuntimeError> {
+        for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+            let (key, value) = pair?;
+            let child = child_path(path, &key);
+            self.value(key, &format!("{} (key)", child))?;
+            self.value(value, &child)?;
+        }
+        self.out.push(NIL);
+        let metatable = table.get_metatable().map_or(LuaNil, LuaValue::Table);
+        self.value(
+            metatable,
+            &format!("getmetatable({})", path_or_globals(path)),
+        )
+    }
+
+    fn function(
+        &mut self,
+        f: LuaFunction<'lua>,
+        ptr: *const c_void,
+        path: &str,
+    ) -> Result<(), RuntimeError> {
+        if f.info().what.as_deref() == Some(b"C") {
+            return Err(unsaveable(path, "a native function"));
+        }
+        let index = self
+            .prototypes
+            .find(&f.dump(false))
+            .ok_or_else(|| unsaveable(path, "a function the cart's code doesn't define"))?;
+        let id = self.ids.len() as u32;
+        self.ids.insert(ptr, id);
+        self.out.push(FUNCTION);
+        self.out.extend(index.to_le_bytes());
+
+        let mut upvalues = vec![];
+        for i in 1.. {
+            let (name, value): (Option<String>, LuaValue) =
+                self.debug.getupvalue.call((f.clone(), i))?;
+            match name {
+                Some(name) => upvalues.push((name, value)),
+                None => break,
+            }
+        }
+        self.out.push(upvalues.len() as u8);
+        for (i, (name, value)) in upvalues.into_iter().enumerate() {
+            let index = i as u8 + 1;
+            let upvalue: LuaLightUserData = self.debug.upvalueid.call((f.clone(), index))?;
+            match self.upvalues.get(&(upvalue.0 as *const c_void)) {
+                Some(&(other, other_index)) => {
+                    self.out.push(SHARED);
+                    self.out.extend(other.to_le_bytes());
+                    self.out.push(other_index);
+                }
+                None => {
+                    self.upvalues.insert(upvalue.0, (id, index));
+                    self.out.push(OWN);
+                    self.value(value, &format!("{} (upvalue {})", path, name))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Decoder<'a, 'lua> {
+    lua: &'lua Lua,
+    input: &'a [u8],
+    debug: Debug<'lua>,
+    prototypes: Prototypes,
+    builtins: LuaTable<'lua>,
+    /// Tables and functions read so far, by id.
+    objects: Vec<LuaValue<'lua>>,
+    /// The libraries read so far with their contents, to fill them with once the
+    /// whole state has been read.
+    libraries: Vec<Library<'lua>>,
+}
+
+/// The globals of a save state, read but not restored yet.
+struct Globals<'lua> {
+    entries: Vec<(LuaValue<'lua>, LuaValue<'lua>)>,
+    metatable: Option<LuaTable<'lua>>,
+    libraries: Vec<Library<'lua>>,
+}
+
+type Library<'lua> = (
+    LuaTable<'lua>,
+    Vec<(LuaValue<'lua>, LuaValue<'lua>)>,
+    Option<LuaTable<'lua>>,
+);
+
+impl<'a, 'lua> Decoder<'a, 'lua> {
+    fn new(lua: &'lua Lua, input: &'a [u8], prototypes: Prototypes) -> LuaResult<Self> {
+        Ok(Self {
+            lua,
+            input,
+            debug: Debug::new(lua)?,
+            prototypes,
+            builtins: lua.named_registry_value(BUILTINS)?,
+            objects: vec![],
+            libraries: vec![],
+        })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], RuntimeError> {
+        if self.input.len() < n {
+            return Err(bad_state("truncated"));
+        }
+        let (head, tail) = self.input.split_at(n);
+        self.input = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, RuntimeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, RuntimeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, RuntimeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], RuntimeError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn value(&mut self) -> Result<LuaValue<'lua>, RuntimeError> {
+        Ok(match self.byte()? {
+            NIL => LuaNil,
+            FALSE => LuaValue::Boolean(false),
+            TRUE => LuaValue::Boolean(true),
+            INTEGER => LuaValue::Integer(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            NUMBER => LuaValue::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            STRING => LuaValue::String(self.lua.create_string(self.bytes()?)?),
+            REF => {
+                let id = self.u32()? as usize;
+                self.objects
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| bad_state("bad reference"))?
+            }
+            GLOBALS => LuaValue::Table(self.lua.globals()),
+            BUILTIN => {
+                let name = String::from_utf8_lossy(self.bytes()?).into_owned();
+                match self.builtins.get::<_, LuaValue>(name.as_str())? {
+                    LuaValue::Function(f) => LuaValue::Function(f),
+                    _ => return Err(bad_state(format!("unknown built-in {}", name))),
+                }
+            }
+            LIBRARY => {
+                let name = String::from_utf8_lossy(self.bytes()?).into_owned();
+                let library = match self.builtins.get::<_, LuaValue>(name.as_str())? {
+                    LuaValue::Table(t) if t != self.lua.globals() => t,
+                    _ => return Err(bad_state(format!("unknown library {}", name))),
+                };
+                self.objects.push(LuaValue::Table(library.clone()));
+                let (entries, metatable) = self.entries()?;
+                self.libraries.push((library.clone(), entries, metatable));
+                LuaValue::Table(library)
+            }
+            TABLE => {
+                let table = self.lua.create_table()?;
+                self.objects.push(LuaValue::Table(table.clone()));
+                let (entries, metatable) = self.entries()?;
+                for (key, value) in entries {
+                    table.raw_set(key, value)?;
+                }
+                table.set_metatable(metatable);
+                LuaValue::Table(table)
+            }
+            FUNCTION => {
+                let index = self.u32()?;
+                let chunk = self
+                    .prototypes
+                    .chunk(index)
+                    .ok_or_else(|| bad_state("unknown function"))?;
+                // compiled from the cart's code just now, not read from the state
+                let f = self
+                    .lua
+                    .load(&chunk)
+                    .set_mode(ChunkMode::Binary)
+                    .into_function()?;
+                self.objects.push(LuaValue::Function(f.clone()));
+                for index in 1..=self.byte()? {
+                    match self.byte()? {
+                        OWN => {
+                            let value = self.value()?;
+                            self.debug
+                                .setupvalue
+                                .call::<_, ()>((f.clone(), index, value))?;
+                        }
+                        SHARED => {
+                            let other = self.u32()? as usize;
+                            let other_index = self.byte()?;
+                            match self.objects.get(other) {
+                                Some(LuaValue::Function(other)) => {
+                                    self.debug.upvaluejoin.call::<_, ()>((
+                                        f.clone(),
+                                        index,
+                                        other.clone(),
+                                        other_index,
+                                    ))?
+                                }
+                                _ => return Err(bad_state("bad upvalue")),
+                            }
+                        }
+                        _ => return Err(bad_state("bad upvalue")),
+                    }
+                }
+                LuaValue::Function(f)
+            }
+            tag => return Err(bad_state(format!("unknown tag {}", tag))),
+        })
+    }
+
+    /// Reads the globals, which must be all that is left.
+    fn globals(mut self) -> Result<Globals<'lua>, RuntimeError> {
+        let (entries, metatable) = self.entries()?;
+        if !self.input.is_empty() {
+            return Err(bad_state("trailing data"));
+        }
+        Ok(Globals {
+            entries,
+            metatable,
+            libraries: self.libraries,
+        })
+    }
+
+    /// Reads the contents of a table then its metatable.
+    #[allow(clippy::type_complexity)]
+    fn entries(
+        &mut self,
+    ) -> Result<
+        (
+            Vec<(LuaValue<'lua>, LuaValue<'lua>)>,
+            Option<LuaTable<'lua>>,
+        ),
+        RuntimeError,
+    > {
+        let mut entries = vec![];
+        loop {
+            match self.value()? {
+                LuaNil => break,
+                key => entries.push((key, self.value()?)),
+            }
+        }
+        let metatable = match self.value()? {
+            LuaNil => None,
+            LuaValue::Table(t) => Some(t),
+            _ => return Err(bad_state("bad metatable")),
+        };
+        Ok((entries, metatable))
+    }
+}
+
+fn unsaveable(path: &str, what: &'static str) -> RuntimeError {
+    RuntimeError::Unsaveable {
+        path: path_or_globals(path).to_string(),
+        what,
+    }
+}
+
+fn bad_state(why: impl Into<String>) -> RuntimeError {
+    RuntimeError::BadState(why.into())
+}
+
+fn path_or_globals(path: &str) -> &str {
+    if path.is_empty() {
+        "_G"
+    } else {
+        path
+    }
+}
+
+/// How Lua code would get at `key` in the table at `path`.
+fn child_path(path: &str, key: &LuaValue) -> String {
+    match key {
+        LuaValue::String(s) => match s.to_str() {
+            Ok(s) if path.is_empty() => s.to_string(),
+            Ok(s) if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                format!("{}.{}", path, s)
+            }
+            _ => format!("{}[{:?}]", path_or_globals(path), s.to_string_lossy()),
+        },
+        LuaValue::Integer(n) => format!("{}[{}]", path_or_globals(path), n),
+        _ => format!("{}[?]", path_or_globals(path)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tac_core::PixBuf;
+
+    use super::*;
+
+    fn runtime(code: &str) -> TAC70Runtime {
+        let tac = TAC70::new(&[0u8; 0x18000], code.to_string());
+        TAC70Runtime::with_seed(tac, 1).unwrap()
+    }
+
+    fn eval<'lua, T: FromLuaMulti<'lua>>(rt: &'lua TAC70Runtime, expr: &str) -> T {
+        rt.lua_ctx.load(expr).eval().unwrap()
+    }
+
+    const GAME: &str = r#"
+        local score = 0
+        local function bump(n) score = score + n return score end
+        player = {x = 3, name = "p1", hits = {1, 2, 3}}
+        player.self = player
+        player.bump = bump
+        enemies = setmetatable({}, {__index = function(_, k) return k * 2 end})
+        function TIC()
+            bump(1)
+            player.x = player.x + 1
+            pix(0, 0, score % 16)
+            roll = math.random(1000)
+        end
+    "#;
+
+    #[test]
+    fn round_trip() {
+        let mut rt = runtime(GAME);
+        rt.step().unwrap();
+        let state = rt.save_state().unwrap();
+        let rolls: Vec<i64> = (0..3)
+            .map(|_| {
+                rt.step().unwrap();
+                eval(&rt, "return roll")
+            })
+            .collect();
+        assert_eq!(eval::<i64>(&rt, "return player.bump(0)"), 4);
+
+        rt.load_state(&state).unwrap();
+        assert_eq!(rt.frame(), 1);
+        assert_eq!(rt.state().screen().get_pix(0, 0), 1);
+        assert_eq!(eval::<i64>(&rt, "return player.x"), 4);
+        assert!(eval::<bool>(&rt, "return player.self == player"));
+        assert_eq!(eval::<i64>(&rt, "return #player.hits + enemies[21]"), 45);
+        assert!(eval::<bool>(
+            &rt,
+            "return math.floor(1.5) == 1 and spr ~= nil"
+        ));
+
+        // the upvalue `score` is shared between TIC and bump again
+        rt.step().unwrap();
+        assert_eq!(eval::<i64>(&rt, "return player.bump(0)"), 2);
+        assert_eq!(eval::<i64>(&rt, "return roll"), rolls[0]);
+    }
+
+    #[test]
+    fn load_into_fresh_runtime() {
+        let mut a = runtime(GAME);
+        for _ in 0..5 {
+            a.step().unwrap();
+        }
+        let state = a.save_state().unwrap();
+
+        let mut b = runtime(GAME);
+        b.lua_ctx.load("extra = 1").exec().unwrap();
+        b.load_state(&state).unwrap();
+        assert_eq!(eval::<LuaValue>(&b, "return extra"), LuaNil);
+        a.step().unwrap();
+        b.step().unwrap();
+        assert_eq!(
+            eval::<(i64, i64)>(&a, "return player.x, roll"),
+            eval::<(i64, i64)>(&b, "return player.x, roll")
+        );
+
+        let mut other = runtime("x = 1");
+        assert!(other.load_state(&state).is_err());
+        assert!(b.load_state(&state[..state.len() - 1]).is_err());
+        assert!(b.load_state(b"TACS").is_err());
+    }
+
+    #[test]
+    fn unsaveable_values() {
+        let rt = runtime("t = {inner = {}}");
+        let co = rt
+            .lua_ctx
+            .create_thread(rt.lua_ctx.load("return 1").into_function().unwrap())
+            .unwrap();
+        let inner: LuaTable = eval(&rt, "return t.inner");
+        inner.set("co", co).unwrap();
+        assert_eq!(
+            rt.save_state().unwrap_err().to_string(),
+            "can't save state: t.inner.co is a coroutine"
+        );
+
+        let rt = runtime("f = load('return 1')");
+        assert_eq!(
+            rt.save_state().unwrap_err().to_string(),
+            "can't save state: f is a function the cart's code doesn't define"
+        );
+    }
+
+    #[test]
+    fn libraries() {
+        let code = "function TIC() function math.twice(n) return n * 2 end string.rep = nil end";
+        let mut a = runtime(code);
+        a.step().unwrap();
+        let state = a.save_state().unwrap();
+        // functions are saved as where they are in the code, not as bytecode
+        assert!(!state.windows(4).any(|w| w == b"\x1bLua"));
+
+        let mut b = runtime(code);
+        b.load_state(&state).unwrap();
+        assert_eq!(eval::<i64>(&b, "return math.twice(4)"), 8);
+        assert_eq!(eval::<LuaValue>(&b, "return string.rep"), LuaNil);
+        assert_eq!(eval::<String>(&b, "return ('x'):upper()"), "X");
+    }
+
+    #[test]
+    fn debug_is_hidden() {
+        let rt = runtime("");
+        assert_eq!(eval::<LuaValue>(&rt, "return debug"), LuaNil);
+    }
+}